@@ -0,0 +1,99 @@
+use crate::client::IcHttpRequestWithCycles;
+use crate::convert::ConvertError;
+use crate::ConvertServiceBuilder;
+use futures_util::future;
+use ic_cdk::management_canister::{
+    HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse,
+};
+use std::task::{Context, Poll};
+use thiserror::Error;
+use tower::{BoxError, Service, ServiceBuilder};
+
+/// Drop-in replacement for [`Client`] that never issues the HTTPs outcall but instead always fails
+/// with a [`CyclesQuote`] containing the request and the number of cycles that would have been attached to it.
+///
+/// This allows running a request through the whole middleware stack to get the exact cost of
+/// an HTTPs outcall. Pair it with [`ChargeMyself`] so that nobody is charged for the quotation:
+/// the canister pays for its own HTTPs outcalls, which are never issued.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{
+///     cycles::{ChargeMyself, CyclesAccountingServiceBuilder, CyclesCostEstimator},
+///     http::HttpConversionLayer,
+///     CyclesQuote, DryRunClient, MaxResponseBytesRequestExtension,
+/// };
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+///     .layer(HttpConversionLayer)
+///     .cycles_accounting(34, ChargeMyself::default())
+///     .service(DryRunClient::new_with_box_error());
+///
+/// let request = http::Request::post("https://internetcomputer.org/")
+///     .max_response_bytes(1_000)
+///     .body(vec![])
+///     .unwrap();
+///
+/// let error = service.ready().await.unwrap().call(request).await.unwrap_err();
+/// let quote = error.downcast_ref::<CyclesQuote>().unwrap();
+///
+/// assert_eq!(
+///     quote.cycles,
+///     CyclesCostEstimator::new(34).cost_of_http_request(&quote.request)
+/// );
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Client`]: crate::Client
+/// [`ChargeMyself`]: crate::cycles::ChargeMyself
+#[derive(Clone, Debug)]
+pub struct DryRunClient;
+
+impl DryRunClient {
+    /// Create a new dry-run client returning custom errors.
+    pub fn new_with_error<CustomError: From<CyclesQuote>>(
+    ) -> ConvertError<DryRunClient, CustomError> {
+        ServiceBuilder::new()
+            .convert_error::<CustomError>()
+            .service(DryRunClient)
+    }
+
+    /// Creates a new dry-run client where error type is erased.
+    pub fn new_with_box_error() -> ConvertError<DryRunClient, BoxError> {
+        Self::new_with_error::<BoxError>()
+    }
+}
+
+/// Error returned by [`DryRunClient`] instead of issuing an HTTPs outcall.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+#[error("HTTPs outcall not issued (dry run): {cycles} cycles would have been attached")]
+pub struct CyclesQuote {
+    /// Request that would have been made.
+    pub request: IcHttpRequest,
+    /// Number of cycles that would have been attached to the request.
+    pub cycles: u128,
+}
+
+impl Service<IcHttpRequestWithCycles> for DryRunClient {
+    type Response = IcHttpResponse;
+    type Error = CyclesQuote;
+    type Future = future::Ready<Result<Self::Response, Self::Error>>;
+
+    fn poll_ready(&mut self, _cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        Poll::Ready(Ok(()))
+    }
+
+    fn call(
+        &mut self,
+        IcHttpRequestWithCycles {
+            request, cycles, ..
+        }: IcHttpRequestWithCycles,
+    ) -> Self::Future {
+        future::ready(Err(CyclesQuote { request, cycles }))
+    }
+}
@@ -1,28 +1,43 @@
 #[cfg(test)]
 mod tests;
 
+pub use dry_run::{CyclesQuote, DryRunClient};
+pub use usage::{ReportCyclesUsage, ReportCyclesUsageLayer};
+
+mod dry_run;
+mod usage;
+
 use crate::convert::ConvertError;
+use crate::transform::{ContextCodec, ContextCodecError};
 use crate::ConvertServiceBuilder;
-use ic_cdk::api::call::RejectionCode;
-use ic_cdk::api::management_canister::http_request::{
-    CanisterHttpRequestArgument as IcHttpRequest, HttpResponse as IcHttpResponse, TransformContext,
+use candid::Principal;
+use ic_cdk::call::{Call, CallFailed};
+use ic_cdk::management_canister::{
+    HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse, TransformContext,
+    TransformFunc,
 };
 use ic_error_types::RejectCode;
 use std::future::Future;
 use std::pin::Pin;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
 use thiserror::Error;
 use tower::{BoxError, Service, ServiceBuilder};
 
-/// Thin wrapper around [`ic_cdk::api::management_canister::http_request::http_request`]
-/// that implements the [`tower::Service`] trait. Its functionality can be extended by composing so-called
+/// Thin wrapper around the [`http_request`](https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-http_request)
+/// method of the management canister that implements the [`tower::Service`] trait. Its functionality can be extended by composing so-called
 /// [tower middlewares](https://docs.rs/tower/latest/tower/#usage).
 ///
+/// The number of cycles that were attached to the request and refunded by the management canister
+/// is recorded in [`IcHttpRequestWithCycles::usage`], see [`ReportCyclesUsageLayer`] to return it with the response.
+///
 /// Middlewares from this crate:
 /// * [`crate::cycles::CyclesAccounting`]: handles cycles accounting.
 /// * [`crate::observability`]: add logging or metrics.
 /// * [`crate::http`]: use types from the [http](https://crates.io/crates/http) crate for requests and responses.
 /// * [`crate::retry::DoubleMaxResponseBytes`]: automatically retry failed requests due to the response being too big.
+///
+/// See [`DryRunClient`] to quote the cycles cost of an HTTPs outcall without issuing it.
 #[derive(Clone, Debug)]
 pub struct Client;
 
@@ -61,47 +76,217 @@ impl Service<IcHttpRequestWithCycles> for Client {
 
     fn call(
         &mut self,
-        IcHttpRequestWithCycles { request, cycles }: IcHttpRequestWithCycles,
+        IcHttpRequestWithCycles {
+            request,
+            cycles,
+            usage,
+            ..
+        }: IcHttpRequestWithCycles,
     ) -> Self::Future {
-        use ic_cdk::api::call::RejectionCode as IcCdkRejectionCode;
-        fn convert_reject_code(code: IcCdkRejectionCode) -> RejectCode {
-            match code {
-                IcCdkRejectionCode::SysFatal => RejectCode::SysFatal,
-                IcCdkRejectionCode::SysTransient => RejectCode::SysTransient,
-                IcCdkRejectionCode::DestinationInvalid => RejectCode::DestinationInvalid,
-                IcCdkRejectionCode::CanisterReject => RejectCode::CanisterReject,
-                IcCdkRejectionCode::CanisterError => RejectCode::CanisterError,
-                IcCdkRejectionCode::Unknown => {
-                    // This can only happen if there is a new error code on ICP that the CDK is not aware of.
-                    // We map it to SysFatal since none of the other error codes apply.
-                    // In particular, note that RejectCode::SysUnknown is only applicable to inter-canister calls that used ic0.call_with_best_effort_response.
-                    RejectCode::SysFatal
+        #[cfg(feature = "tracing")]
+        let span = tracing::debug_span!(
+            "http_outcall",
+            url = %crate::redact::RedactionPolicy::default().redact_url(&request.url),
+            method = ?request.method,
+            cycles,
+        );
+        let future = async move {
+            // The attached cycles are recorded before the call is made, so that they count as spent
+            // if the future is dropped while the HTTPs outcall is in flight.
+            usage.record(CyclesUsage {
+                attached: cycles,
+                refunded: 0,
+            });
+            // Cycles are attached explicitly (instead of using `ic_cdk::management_canister::http_request`)
+            // so that the amount is the one computed by the cycles accounting middleware.
+            let (result, refunded) =
+                match Call::unbounded_wait(Principal::management_canister(), "http_request")
+                    .with_arg(&request)
+                    .with_cycles(cycles)
+                    .await
+                {
+                    // The refund must be read right after the call returns to refer to that call.
+                    Ok(response) => (
+                        response.candid::<IcHttpResponse>().map_err(|e| IcError {
+                            code: RejectCode::CanisterError,
+                            message: e.to_string(),
+                        }),
+                        ic_cdk::api::msg_cycles_refunded(),
+                    ),
+                    Err(CallFailed::CallRejected(rejected)) => (
+                        Err(IcError {
+                            // An unrecognized reject code can only happen if there is a new error code on ICP that the CDK is not aware of.
+                            // We map it to SysFatal since none of the other error codes apply.
+                            // In particular, note that RejectCode::SysUnknown is only applicable to inter-canister calls that used ic0.call_with_best_effort_response.
+                            code: rejected.reject_code().unwrap_or(RejectCode::SysFatal),
+                            message: rejected.reject_message().to_string(),
+                        }),
+                        ic_cdk::api::msg_cycles_refunded(),
+                    ),
+                    // The call could not be made, so that the attached cycles were not spent.
+                    // Retrying does not help as long as the canister does not receive more cycles.
+                    Err(CallFailed::InsufficientLiquidCycleBalance(e)) => (
+                        Err(IcError {
+                            code: RejectCode::SysFatal,
+                            message: e.to_string(),
+                        }),
+                        cycles,
+                    ),
+                    Err(CallFailed::CallPerformFailed(e)) => (
+                        Err(IcError {
+                            code: RejectCode::SysTransient,
+                            message: e.to_string(),
+                        }),
+                        cycles,
+                    ),
+                };
+            usage.record(CyclesUsage {
+                attached: 0,
+                refunded,
+            });
+            match result {
+                Ok(response) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        status = u16::try_from(&response.status.0).unwrap_or(u16::MAX),
+                        cycles_refunded = refunded,
+                        "HTTPs outcall succeeded"
+                    );
+                    Ok(response)
+                }
+                Err(error) => {
+                    #[cfg(feature = "tracing")]
+                    tracing::warn!(
+                        reject_code = ?error.code,
+                        message = error.message,
+                        cycles_refunded = refunded,
+                        "HTTPs outcall failed"
+                    );
+                    Err(error)
                 }
-                RejectionCode::NoError => unreachable!("ic_cdk::api::management_canister::http_request::http_request should never produce a RejectionCode::NoError error")
-            }
-        }
-
-        Box::pin(async move {
-            match ic_cdk::api::management_canister::http_request::http_request(request, cycles)
-                .await
-            {
-                Ok((response,)) => Ok(response),
-                Err((code, message)) => Err(IcError {
-                    code: convert_reject_code(code),
-                    message,
-                }),
             }
-        })
+        };
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, span);
+        Box::pin(future)
     }
 }
 
 /// [`IcHttpRequest`] specifying how many cycles should be attached for the HTTPs outcall.
+///
+/// Use [`IcHttpRequestWithCycles::new`] to create a request, since more fields may be added.
 #[derive(Clone, Debug, Default, PartialEq, Eq)]
+#[non_exhaustive]
 pub struct IcHttpRequestWithCycles {
     /// Request to be made.
     pub request: IcHttpRequest,
     /// Number of cycles to attach.
     pub cycles: u128,
+    /// Where [`Client`] reports the number of cycles attached when the HTTPs outcall is made
+    /// and refunded once it completed, whether it succeeded or not.
+    pub usage: CyclesUsageRecorder,
+    /// Retry attempt of the request, where 0 denotes the first attempt
+    /// (see [`RetryAttemptRequestExtension`]).
+    pub retry_attempt: u32,
+}
+
+impl IcHttpRequestWithCycles {
+    /// Create a new request to which the given number of cycles will be attached.
+    pub fn new(request: IcHttpRequest, cycles: u128) -> Self {
+        Self {
+            request,
+            cycles,
+            usage: CyclesUsageRecorder::default(),
+            retry_attempt: 0,
+        }
+    }
+}
+
+impl From<IcHttpRequest> for IcHttpRequestWithCycles {
+    /// Request to which no cycles are attached yet, see [`CyclesAccounting`](crate::cycles::CyclesAccounting).
+    fn from(request: IcHttpRequest) -> Self {
+        Self::new(request, 0)
+    }
+}
+
+/// [`IcHttpResponse`] together with the number of cycles that were spent for the HTTPs outcall.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct IcHttpResponseWithCycles {
+    /// Response received.
+    pub response: IcHttpResponse,
+    /// Cycles attached to the request and refunded with the response.
+    pub cycles: CyclesUsage,
+}
+
+/// Number of cycles attached to an HTTPs outcall and refunded by the management canister.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CyclesUsage {
+    /// Number of cycles attached to the request.
+    pub attached: u128,
+    /// Number of cycles refunded with the response.
+    pub refunded: u128,
+}
+
+impl CyclesUsage {
+    /// Number of cycles actually consumed by the HTTPs outcall.
+    pub fn consumed(&self) -> u128 {
+        self.attached.saturating_sub(self.refunded)
+    }
+}
+
+/// Shared record of the number of cycles attached to HTTPs outcalls and refunded by the management canister.
+///
+/// All clones share the same record, so that a middleware can keep a clone of the recorder of a request
+/// (see [`IcHttpRequestWithCycles::usage`]) and read the cycles usage once the request was processed,
+/// including when it failed.
+/// Usages recorded several times, e.g. for requests retried below that middleware, are added up.
+#[derive(Clone, Debug, Default)]
+pub struct CyclesUsageRecorder(Arc<Mutex<Option<CyclesUsage>>>);
+
+impl CyclesUsageRecorder {
+    /// Record the cycles usage of an HTTPs outcall.
+    pub fn record(&self, usage: CyclesUsage) {
+        let mut recorded = self.0.lock().unwrap();
+        *recorded = Some(match *recorded {
+            Some(previous) => CyclesUsage {
+                attached: previous.attached.saturating_add(usage.attached),
+                refunded: previous.refunded.saturating_add(usage.refunded),
+            },
+            None => usage,
+        });
+    }
+
+    /// Cycles usage recorded so far, if any.
+    pub fn get(&self) -> Option<CyclesUsage> {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl PartialEq for CyclesUsageRecorder {
+    fn eq(&self, other: &Self) -> bool {
+        self.get() == other.get()
+    }
+}
+
+impl Eq for CyclesUsageRecorder {}
+
+/// Add support for retrieving the cycles spent for an HTTPs outcall.
+pub trait CyclesUsageResponseExtension {
+    /// Set the cycles usage.
+    fn set_cycles_usage(&mut self, value: CyclesUsage);
+
+    /// Retrieves the cycles usage, if any.
+    fn get_cycles_usage(&self) -> Option<CyclesUsage>;
+}
+
+impl CyclesUsageResponseExtension for IcHttpResponseWithCycles {
+    fn set_cycles_usage(&mut self, value: CyclesUsage) {
+        self.cycles = value;
+    }
+
+    fn get_cycles_usage(&self) -> Option<CyclesUsage> {
+        Some(self.cycles)
+    }
 }
 
 /// Add support for max response bytes.
@@ -160,6 +345,46 @@ pub trait TransformContextRequestExtension: Sized {
         self.set_transform_context(value);
         self
     }
+
+    /// Convenience method to use the builder pattern, setting a transform context calling the given
+    /// transform function with the given payload encoded with the given codec.
+    ///
+    /// The transform function can decode the payload from [`TransformArgs::context`] with the same codec.
+    ///
+    /// Returns an error if the payload cannot be encoded.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use canhttp::{
+    ///     transform::{CandidCodec, ContextCodec},
+    ///     TransformContextRequestExtension,
+    /// };
+    /// use candid::Principal;
+    /// use ic_cdk::management_canister::TransformFunc;
+    ///
+    /// let transform = TransformFunc::new(Principal::anonymous(), "transform".to_string());
+    /// let request = http::Request::post("https://internetcomputer.org/")
+    ///     .transform_payload(transform, CandidCodec, &(1_u64, "max_items".to_string()))
+    ///     .unwrap()
+    ///     .body(Vec::<u8>::new())
+    ///     .unwrap();
+    ///
+    /// let context = &request.get_transform_context().unwrap().context;
+    /// let payload: (u64, String) = CandidCodec.decode(context).unwrap();
+    /// assert_eq!(payload, (1, "max_items".to_string()));
+    /// ```
+    ///
+    /// [`TransformArgs::context`]: ic_cdk::management_canister::TransformArgs::context
+    fn transform_payload<C: ContextCodec<T>, T>(
+        self,
+        function: TransformFunc,
+        codec: C,
+        payload: &T,
+    ) -> Result<Self, ContextCodecError> {
+        let context = codec.encode(payload)?;
+        Ok(self.transform_context(TransformContext { function, context }))
+    }
 }
 
 impl TransformContextRequestExtension for IcHttpRequest {
@@ -182,6 +407,81 @@ impl TransformContextRequestExtension for IcHttpRequestWithCycles {
     }
 }
 
+/// Add support for non-replicated HTTPs outcalls.
+///
+/// By default, an HTTPs outcall is made by all nodes of the subnet and the responses go through consensus.
+/// A non-replicated outcall is made by a single node, which is cheaper but means that the response
+/// is trusted as is.
+pub trait IsReplicatedRequestExtension: Sized {
+    /// Set whether the request should be replicated.
+    fn set_is_replicated(&mut self, value: bool);
+
+    /// Retrieves whether the request should be replicated, if specified.
+    ///
+    /// If not specified, the request is replicated.
+    fn get_is_replicated(&self) -> Option<bool>;
+
+    /// Convenience method to use the builder pattern.
+    fn replicated(mut self, value: bool) -> Self {
+        self.set_is_replicated(value);
+        self
+    }
+}
+
+impl IsReplicatedRequestExtension for IcHttpRequest {
+    fn set_is_replicated(&mut self, value: bool) {
+        self.is_replicated = Some(value);
+    }
+
+    fn get_is_replicated(&self) -> Option<bool> {
+        self.is_replicated
+    }
+}
+
+impl IsReplicatedRequestExtension for IcHttpRequestWithCycles {
+    fn set_is_replicated(&mut self, value: bool) {
+        self.request.set_is_replicated(value);
+    }
+
+    fn get_is_replicated(&self) -> Option<bool> {
+        self.request.get_is_replicated()
+    }
+}
+
+/// Add support for tracking which attempt of a retried request is being made.
+///
+/// Retry policies wrapped in [`TrackRetryAttempt`](crate::retry::TrackRetryAttempt) set the retry attempt
+/// of the requests they retry, so that middlewares below them, such as [`crate::observability`],
+/// can tell attempts apart.
+///
+/// [`IcHttpRequest`] has no room for such metadata, contrary to [`IcHttpRequestWithCycles`]
+/// which keeps the retry attempt below [`CyclesAccounting`](crate::cycles::CyclesAccounting).
+pub trait RetryAttemptRequestExtension: Sized {
+    /// Set the retry attempt, where 0 denotes the first attempt.
+    fn set_retry_attempt(&mut self, value: u32);
+
+    /// Retrieves the retry attempt, if any.
+    ///
+    /// If not specified, the request is the first attempt.
+    fn get_retry_attempt(&self) -> Option<u32>;
+
+    /// Convenience method to use the builder pattern.
+    fn retry_attempt(mut self, value: u32) -> Self {
+        self.set_retry_attempt(value);
+        self
+    }
+}
+
+impl RetryAttemptRequestExtension for IcHttpRequestWithCycles {
+    fn set_retry_attempt(&mut self, value: u32) {
+        self.retry_attempt = value;
+    }
+
+    fn get_retry_attempt(&self) -> Option<u32> {
+        Some(self.retry_attempt)
+    }
+}
+
 /// Characterize errors that are specific to HTTPs outcalls.
 pub trait HttpsOutcallError {
     /// Determines whether the error indicates that the response was larger than the specified
@@ -189,6 +489,20 @@ pub trait HttpsOutcallError {
     ///
     /// If true, retrying with a larger value for `max_response_bytes` may help.
     fn is_response_too_large(&self) -> bool;
+
+    /// Determines whether the error is transient, in which case retrying the same request may help.
+    ///
+    /// The default implementation returns `false`.
+    fn is_transient(&self) -> bool {
+        false
+    }
+
+    /// Message of the reject returned by the Internet Computer, if the error is such a reject.
+    ///
+    /// The default implementation returns `None`.
+    fn reject_message(&self) -> Option<&str> {
+        None
+    }
 }
 
 impl HttpsOutcallError for IcError {
@@ -196,6 +510,14 @@ impl HttpsOutcallError for IcError {
         self.code == RejectCode::SysFatal
             && (self.message.contains("size limit") || self.message.contains("length limit"))
     }
+
+    fn is_transient(&self) -> bool {
+        self.code == RejectCode::SysTransient
+    }
+
+    fn reject_message(&self) -> Option<&str> {
+        Some(&self.message)
+    }
 }
 
 impl HttpsOutcallError for BoxError {
@@ -205,4 +527,14 @@ impl HttpsOutcallError for BoxError {
         }
         false
     }
+
+    fn is_transient(&self) -> bool {
+        self.downcast_ref::<IcError>()
+            .is_some_and(|ic_error| ic_error.is_transient())
+    }
+
+    fn reject_message(&self) -> Option<&str> {
+        self.downcast_ref::<IcError>()
+            .and_then(|ic_error| ic_error.reject_message())
+    }
 }
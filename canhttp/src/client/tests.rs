@@ -1,6 +1,16 @@
+use crate::cycles::{ChargeMyself, CyclesAccountingServiceBuilder, FixedCyclesCost};
 use crate::retry::DoubleMaxResponseBytes;
-use crate::{Client, HttpsOutcallError, IcError};
-use tower::{ServiceBuilder, ServiceExt};
+use crate::transform::{CandidCodec, ContextCodec, ContextCodecError};
+use crate::{
+    Client, CyclesQuote, CyclesUsage, DryRunClient, HttpsOutcallError, IcError,
+    IcHttpRequestWithCycles, IcHttpResponseWithCycles, ReportCyclesUsageLayer,
+    TransformContextRequestExtension,
+};
+use candid::Principal;
+use ic_cdk::management_canister::{
+    HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse, TransformFunc,
+};
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
 
 // Some middlewares like tower::retry need the underlying service to be cloneable.
 #[test]
@@ -27,6 +37,127 @@ async fn should_be_able_to_use_retry_layer() {
     let _ = service.ready().await.unwrap();
 }
 
+#[tokio::test]
+async fn should_quote_cycles_without_issuing_request() {
+    let mut service = ServiceBuilder::new()
+        .cycles_accounting_with_estimator(FixedCyclesCost(42), ChargeMyself::default())
+        .service(DryRunClient::new_with_box_error());
+    let request = IcHttpRequest {
+        url: "https://internetcomputer.org/".to_string(),
+        ..Default::default()
+    };
+
+    let error = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request.clone())
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        error.downcast_ref::<CyclesQuote>(),
+        Some(&CyclesQuote {
+            request,
+            cycles: 42
+        })
+    );
+}
+
+#[tokio::test]
+async fn should_report_recorded_cycles_usage() {
+    let mut service = ServiceBuilder::new()
+        .layer(ReportCyclesUsageLayer)
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 400,
+            });
+            Ok::<_, BoxError>(IcHttpResponse::default())
+        });
+
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequestWithCycles::new(
+            IcHttpRequest::default(),
+            1_000,
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(
+        response,
+        IcHttpResponseWithCycles {
+            response: IcHttpResponse::default(),
+            cycles: CyclesUsage {
+                attached: 1_000,
+                refunded: 400
+            }
+        }
+    );
+}
+
+#[tokio::test]
+async fn should_report_attached_cycles_as_consumed_when_no_usage_recorded() {
+    let mut service = ServiceBuilder::new()
+        .layer(ReportCyclesUsageLayer)
+        .service_fn(|_request: IcHttpRequestWithCycles| async move {
+            Ok::<_, BoxError>(IcHttpResponse::default())
+        });
+
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequestWithCycles::new(
+            IcHttpRequest::default(),
+            1_000,
+        ))
+        .await
+        .unwrap();
+
+    assert_eq!(response.cycles.consumed(), 1_000);
+}
+
+#[test]
+fn should_return_error_when_transform_payload_cannot_be_encoded() {
+    struct FailingCodec;
+
+    impl ContextCodec<u64> for FailingCodec {
+        fn encode(&self, _payload: &u64) -> Result<Vec<u8>, ContextCodecError> {
+            Err(ContextCodecError::EncodingError {
+                codec: "Failing",
+                message: "cannot encode".to_string(),
+            })
+        }
+
+        fn decode(&self, _context: &[u8]) -> Result<u64, ContextCodecError> {
+            unimplemented!()
+        }
+    }
+
+    let function = TransformFunc::new(Principal::anonymous(), "transform".to_string());
+
+    let result = IcHttpRequest::default().transform_payload(function.clone(), FailingCodec, &1);
+    assert_eq!(
+        result.map(|_request| ()),
+        Err(ContextCodecError::EncodingError {
+            codec: "Failing",
+            message: "cannot encode".to_string(),
+        })
+    );
+
+    let request = IcHttpRequest::default()
+        .transform_payload(function, CandidCodec, &1_u64)
+        .unwrap();
+    assert_eq!(
+        CandidCodec.decode(&request.transform.unwrap().context),
+        Ok(1_u64)
+    );
+}
+
 #[derive(Debug)]
 struct CustomError(IcError);
 
@@ -0,0 +1,110 @@
+use crate::client::{CyclesUsage, IcHttpRequestWithCycles, IcHttpResponseWithCycles};
+use crate::CyclesUsageRecorder;
+use ic_cdk::management_canister::HttpRequestResult as IcHttpResponse;
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Middleware that returns responses together with the number of cycles that were attached to the request
+/// and refunded by the management canister, as recorded by [`Client`] (see [`IcHttpRequestWithCycles::usage`]).
+///
+/// The responses are of type [`IcHttpResponseWithCycles`] and the cycles usage is kept as an extension
+/// when they are converted into [`http::Response`] (see [`CyclesUsageResponseExtension`]),
+/// so that it can be observed for example with the [`ObservabilityLayer`].
+///
+/// If the inner service did not record any cycles usage, all attached cycles are reported as consumed.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{
+///     cycles::{ChargeMyself, CyclesAccountingServiceBuilder},
+///     http::HttpConversionLayer,
+///     observability::ObservabilityLayer,
+///     Client, CyclesUsageResponseExtension, ReportCyclesUsageLayer,
+/// };
+/// use tower::ServiceBuilder;
+///
+/// let service = ServiceBuilder::new()
+///     .layer(
+///         ObservabilityLayer::new().on_response(|_req_data: (), response: &http::Response<Vec<u8>>| {
+///             if let Some(cycles) = response.get_cycles_usage() {
+///                 ic_cdk::println!("Consumed {} cycles", cycles.consumed());
+///             }
+///         }),
+///     )
+///     .layer(HttpConversionLayer)
+///     .cycles_accounting(34, ChargeMyself::default())
+///     .layer(ReportCyclesUsageLayer)
+///     .service(Client::new_with_box_error());
+/// ```
+///
+/// [`Client`]: crate::Client
+/// [`CyclesUsageResponseExtension`]: crate::CyclesUsageResponseExtension
+/// [`ObservabilityLayer`]: crate::observability::ObservabilityLayer
+#[derive(Clone, Debug)]
+pub struct ReportCyclesUsageLayer;
+
+impl<S> Layer<S> for ReportCyclesUsageLayer {
+    type Service = ReportCyclesUsage<S>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        ReportCyclesUsage { inner }
+    }
+}
+
+/// Service produced by the [`ReportCyclesUsageLayer`] middleware.
+#[derive(Clone, Debug)]
+pub struct ReportCyclesUsage<S> {
+    inner: S,
+}
+
+impl<S> Service<IcHttpRequestWithCycles> for ReportCyclesUsage<S>
+where
+    S: Service<IcHttpRequestWithCycles, Response = IcHttpResponse>,
+{
+    type Response = IcHttpResponseWithCycles;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: IcHttpRequestWithCycles) -> Self::Future {
+        ResponseFuture {
+            cycles_attached: request.cycles,
+            usage: request.usage.clone(),
+            response_future: self.inner.call(request),
+        }
+    }
+}
+
+/// Response future for [`ReportCyclesUsage`].
+#[pin_project]
+pub struct ResponseFuture<F> {
+    #[pin]
+    response_future: F,
+    cycles_attached: u128,
+    usage: CyclesUsageRecorder,
+}
+
+impl<F, Error> Future for ResponseFuture<F>
+where
+    F: Future<Output = Result<IcHttpResponse, Error>>,
+{
+    type Output = Result<IcHttpResponseWithCycles, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        this.response_future.poll(cx).map_ok(|response| {
+            let cycles = this.usage.get().unwrap_or(CyclesUsage {
+                attached: *this.cycles_attached,
+                refunded: 0,
+            });
+            IcHttpResponseWithCycles { response, cycles }
+        })
+    }
+}
@@ -57,7 +57,14 @@ where
     fn call(&mut self, new_req: NewRequest) -> Self::Future {
         match self.converter.try_convert(new_req) {
             Ok(request) => future::Either::Left(self.inner.call(request)),
-            Err(err) => future::Either::Right(future::ready(Err(err.into()))),
+            Err(err) => {
+                #[cfg(feature = "tracing")]
+                tracing::debug!(
+                    converter = std::any::type_name::<Converter>(),
+                    "Failed to convert request"
+                );
+                future::Either::Right(future::ready(Err(err.into())))
+            }
         }
     }
 }
@@ -84,9 +84,14 @@ where
         let result_fut = this.response_future.poll(cx);
         match result_fut {
             Poll::Ready(result) => match result {
-                Ok(response) => {
-                    Poll::Ready(this.converter.try_convert(response).map_err(Into::into))
-                }
+                Ok(response) => Poll::Ready(this.converter.try_convert(response).map_err(|err| {
+                    #[cfg(feature = "tracing")]
+                    tracing::debug!(
+                        converter = std::any::type_name::<Filter>(),
+                        "Failed to convert response"
+                    );
+                    err.into()
+                })),
                 Err(err) => Poll::Ready(Err(err)),
             },
             Poll::Pending => Poll::Pending,
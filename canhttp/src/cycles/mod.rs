@@ -40,41 +40,155 @@
 //! # Ok(())
 //! # }
 //! ```
+//!
+//! To charge the caller only for the cycles that were actually spent by the HTTPs outcall,
+//! i.e. leaving the cycles refunded by the management canister with the caller:
+//! ```rust
+//! use canhttp::{cycles::{ChargeCaller, CyclesAccountingServiceBuilder}, Client};
+//! use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut service = ServiceBuilder::new()
+//!   .cycles_accounting(34, ChargeCaller::new(|_request, cost| cost).with_refund())
+//!   .service(Client::new_with_box_error());
+//!
+//! let _ = service.ready().await.unwrap();
+//!
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To retry requests whose response was too large with a larger `max_response_bytes`
+//! (see [`DoubleMaxResponseBytes`](crate::retry::DoubleMaxResponseBytes))
+//! while charging the caller only once for all attempts and refunding the cycles of the attempts that were not made:
+//! ```rust
+//! use canhttp::{cycles::{ChargeCaller, CyclesAccountingServiceBuilder}, Client};
+//! use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut service = ServiceBuilder::new()
+//!   .cycles_accounting_with_retries(34, ChargeCaller::new(|_request, cost| cost).with_refund())
+//!   .service(Client::new_with_box_error());
+//!
+//! let _ = service.ready().await.unwrap();
+//!
+//! # Ok(())
+//! # }
+//! ```
+//!
+//! To quote the cycles cost of an HTTPs outcall without issuing it, see [`DryRunClient`](crate::DryRunClient).
+//!
+//! To over-attach cycles on purpose, for example 20% more than the minimum required:
+//! ```rust
+//! use canhttp::{cycles::{ChargeMyself, CyclesAccountingServiceBuilder, CyclesCostEstimator, WithSafetyMargin}, Client};
+//! use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut service = ServiceBuilder::new()
+//!   .cycles_accounting_with_estimator(
+//!       WithSafetyMargin::new(CyclesCostEstimator::new(34), 20),
+//!       ChargeMyself::default()
+//!   )
+//!   .service(Client::new_with_box_error());
+//!
+//! let _ = service.ready().await.unwrap();
+//!
+//! # Ok(())
+//! # }
+//! ```
 #[cfg(test)]
 mod tests;
 
+#[cfg(feature = "stable")]
+pub use prepaid::StablePrepaidBalances;
+pub use prepaid::{
+    ChargePrepaidBalance, InMemoryPrepaidBalances, PrepaidBalanceError, PrepaidBalances,
+    PrepaidCharge,
+};
+pub use reserve::{ChargeMyselfError, ChargeMyselfWithReserve};
+pub use retry::{
+    CyclesAccountingWithRetries, CyclesAccountingWithRetriesService,
+    IcHttpRequestWithCyclesReserve, PayFromCyclesReserve,
+};
+
+mod prepaid;
+mod reserve;
+mod retry;
+
 use crate::client::IcHttpRequestWithCycles;
-use crate::convert::{Convert, ConvertRequestLayer};
-use crate::ConvertServiceBuilder;
-use ic_cdk::api::management_canister::http_request::CanisterHttpRequestArgument;
+use crate::{CyclesUsage, CyclesUsageRecorder};
+use futures_util::future;
+use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+use pin_project::pin_project;
 use std::convert::Infallible;
+use std::future::Future;
+use std::marker::PhantomData;
+use std::pin::Pin;
+use std::task::{Context, Poll};
 use thiserror::Error;
-use tower::ServiceBuilder;
-use tower_layer::Stack;
+use tower::{Service, ServiceBuilder};
+use tower_layer::{Layer, Stack};
 
 /// Charge cycles to pay for a single HTTPs outcall.
 pub trait CyclesChargingPolicy {
     /// Type returned in case of a charging error.
     type Error;
 
-    /// Charge cycles and return the charged amount.
+    /// Cycles charged for a single request, kept until they are settled.
+    type Charge: ChargedCycles;
+
+    /// Charge cycles and return the charge.
     fn charge_cycles(
         &self,
-        request: &CanisterHttpRequestArgument,
+        request: &IcHttpRequest,
         request_cycles_cost: u128,
-    ) -> Result<u128, Self::Error>;
+    ) -> Result<Self::Charge, Self::Error>;
+
+    /// Settle the charged cycles once the HTTPs outcall completed.
+    ///
+    /// This is called exactly once per charged request, including when the response future
+    /// is dropped before completing, e.g. because the call was canceled.
+    /// The `charge` is the one returned by [`Self::charge_cycles`] for that request,
+    /// while `usage` contains the number of cycles actually attached to the request and refunded
+    /// by the management canister, as recorded by [`Client`], whether the HTTPs outcall succeeded or not.
+    /// An HTTPs outcall dropped while in flight is recorded without any refund.
+    /// The usage is unknown (`None`) when the request never reached a [`Client`],
+    /// e.g. because it was rejected by another middleware.
+    ///
+    /// [`Client`]: crate::Client
+    ///
+    /// The default implementation does nothing.
+    fn settle_cycles(&self, _charge: Self::Charge, _usage: Option<CyclesUsage>) {}
+}
+
+/// Cycles charged for a single request by a [`CyclesChargingPolicy`].
+pub trait ChargedCycles {
+    /// Number of charged cycles.
+    fn charged_cycles(&self) -> u128;
+}
+
+impl ChargedCycles for u128 {
+    fn charged_cycles(&self) -> u128 {
+        *self
+    }
 }
 
 /// The canister using that policy will pay for HTTPs outcalls with its own cycles.
+///
+/// The canister cycles balance is not checked, see [`ChargeMyselfWithReserve`] to keep a minimum balance.
 #[derive(Default, Clone)]
 pub struct ChargeMyself {}
 
 impl CyclesChargingPolicy for ChargeMyself {
     type Error = Infallible;
+    type Charge = u128;
 
     fn charge_cycles(
         &self,
-        _request: &CanisterHttpRequestArgument,
+        _request: &IcHttpRequest,
         _request_cycles_cost: u128,
     ) -> Result<u128, Self::Error> {
         // no-op,
@@ -83,56 +197,203 @@ impl CyclesChargingPolicy for ChargeMyself {
 }
 
 /// Cycles will be transferred from the caller of the canister using that library to pay for HTTPs outcalls.
+///
+/// By default, the cycles are accepted from the caller before the HTTPs outcall is made
+/// and are kept regardless of the outcome of the HTTPs outcall.
+/// See [`ChargeCaller::with_refund`] to only keep the cycles that were actually spent.
 #[derive(Clone)]
 pub struct ChargeCaller<F> {
     cycles_to_charge: F,
+    refund_unspent_cycles: bool,
 }
 
 impl<F> ChargeCaller<F>
 where
-    F: Fn(&CanisterHttpRequestArgument, u128) -> u128,
+    F: Fn(&IcHttpRequest, u128) -> u128,
 {
     /// Create a new instance of [`ChargeCaller`].
     pub fn new(cycles_to_charge: F) -> Self {
-        ChargeCaller { cycles_to_charge }
+        ChargeCaller {
+            cycles_to_charge,
+            refund_unspent_cycles: false,
+        }
+    }
+
+    /// Return the cycles that were not spent by the HTTPs outcall to the caller.
+    ///
+    /// The cycles attached by the caller are only checked before the HTTPs outcall is made,
+    /// which is paid for by the canister in the meantime. Once the HTTPs outcall completed,
+    /// or its response future was dropped, the charged amount minus the cycles refunded by the management canister
+    /// are accepted from the caller, and the remaining cycles are returned to the caller when the call finishes.
+    /// When the HTTPs outcall was not made, no cycles are accepted.
+    ///
+    /// Since the cycles are only accepted once the HTTPs outcall completed, each of several requests made
+    /// concurrently within the same call is checked against all the cycles attached to that call,
+    /// and the canister pays for whatever the caller did not attach.
+    /// Use [`ChargeCaller::new`], which accepts the cycles before making the HTTPs outcall,
+    /// to make concurrent requests within a single call.
+    pub fn with_refund(self) -> Self {
+        Self {
+            refund_unspent_cycles: true,
+            ..self
+        }
+    }
+}
+
+impl<F> ChargeCaller<F> {
+    /// Check that the cycles still available in the call cover the cycles to charge.
+    fn check_cycles_available(
+        cycles_to_charge: u128,
+        cycles_available: u128,
+    ) -> Result<(), ChargeCallerError> {
+        if cycles_available < cycles_to_charge {
+            return Err(ChargeCallerError::InsufficientCyclesError {
+                expected: cycles_to_charge,
+                received: cycles_available,
+            });
+        }
+        Ok(())
+    }
+
+    /// Cycles to accept from the caller once the HTTPs outcall completed.
+    fn cycles_to_accept(&self, charged_cycles: u128, usage: Option<CyclesUsage>) -> u128 {
+        if !self.refund_unspent_cycles {
+            return 0;
+        }
+        usage.map_or(0, |usage| charged_cycles.saturating_sub(usage.refunded))
     }
 }
 
 impl<F> CyclesChargingPolicy for ChargeCaller<F>
 where
-    F: Fn(&CanisterHttpRequestArgument, u128) -> u128,
+    F: Fn(&IcHttpRequest, u128) -> u128,
 {
     type Error = ChargeCallerError;
+    type Charge = u128;
 
     fn charge_cycles(
         &self,
-        request: &CanisterHttpRequestArgument,
+        request: &IcHttpRequest,
         request_cycles_cost: u128,
     ) -> Result<u128, Self::Error> {
         let cycles_to_charge = (self.cycles_to_charge)(request, request_cycles_cost);
         if cycles_to_charge > 0 {
-            let cycles_available = ic_cdk::api::call::msg_cycles_available128();
-            if cycles_available < cycles_to_charge {
-                return Err(ChargeCallerError::InsufficientCyclesError {
-                    expected: cycles_to_charge,
-                    received: cycles_available,
-                });
+            Self::check_cycles_available(cycles_to_charge, ic_cdk::api::msg_cycles_available())?;
+            if !self.refund_unspent_cycles {
+                let cycles_received = ic_cdk::api::msg_cycles_accept(cycles_to_charge);
+                assert_eq!(
+                    cycles_received, cycles_to_charge,
+                    "Expected to receive {cycles_to_charge}, but got {cycles_received}"
+                );
             }
-            let cycles_received = ic_cdk::api::call::msg_cycles_accept128(cycles_to_charge);
-            assert_eq!(
-                cycles_received, cycles_to_charge,
-                "Expected to receive {cycles_to_charge}, but got {cycles_received}"
-            );
         }
         Ok(cycles_to_charge)
     }
+
+    fn settle_cycles(&self, charged_cycles: u128, usage: Option<CyclesUsage>) {
+        let cycles_to_accept = self.cycles_to_accept(charged_cycles, usage);
+        if cycles_to_accept > 0 {
+            // Accepts at most the cycles still available, which may have been accepted meanwhile
+            // by another request made within the same call.
+            ic_cdk::api::msg_cycles_accept(cycles_to_accept);
+        }
+    }
+}
+
+/// Fees charged for HTTPs outcalls, as specified
+/// [here](https://internetcomputer.org/docs/current/developer-docs/gas-cost#https-outcalls).
+///
+/// All fees are expressed in cycles and are multiplied by the number of nodes making the outcall.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct CyclesPricing {
+    base_fee: u128,
+    base_fee_per_node: u128,
+    request_fee_per_byte: u128,
+    response_fee_per_byte: u128,
+}
+
+impl CyclesPricing {
+    /// Pricing schedule in effect since HTTPs outcalls were introduced.
+    pub const V1: CyclesPricing = CyclesPricing::new(3_000_000, 60_000, 400, 800);
+
+    /// Create a new pricing schedule.
+    ///
+    /// For a request made by `n` nodes, the base fee is `(base_fee + base_fee_per_node * n) * n`,
+    /// while each byte of the request (resp. response) costs `request_fee_per_byte * n`
+    /// (resp. `response_fee_per_byte * n`).
+    pub const fn new(
+        base_fee: u128,
+        base_fee_per_node: u128,
+        request_fee_per_byte: u128,
+        response_fee_per_byte: u128,
+    ) -> Self {
+        Self {
+            base_fee,
+            base_fee_per_node,
+            request_fee_per_byte,
+            response_fee_per_byte,
+        }
+    }
+
+    /// Fixed fee paid for each HTTPs outcall made by the given number of nodes.
+    pub fn base_fee(&self, num_nodes: u32) -> u128 {
+        let num_nodes = num_nodes as u128;
+        self.base_fee
+            .saturating_add(self.base_fee_per_node.saturating_mul(num_nodes))
+            .saturating_mul(num_nodes)
+    }
+
+    /// Fee paid for sending the given number of request bytes with the given number of nodes.
+    pub fn request_fee(&self, num_nodes: u32, bytes: u128) -> u128 {
+        self.request_fee_per_byte
+            .saturating_mul(num_nodes as u128)
+            .saturating_mul(bytes)
+    }
+
+    /// Fee paid for receiving the given number of response bytes with the given number of nodes.
+    pub fn response_fee(&self, num_nodes: u32, bytes: u128) -> u128 {
+        self.response_fee_per_byte
+            .saturating_mul(num_nodes as u128)
+            .saturating_mul(bytes)
+    }
+}
+
+impl Default for CyclesPricing {
+    fn default() -> Self {
+        Self::V1
+    }
+}
+
+/// Cost of an HTTPs outcall broken down by component.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct CyclesCostBreakdown {
+    /// Fixed fee paid for each HTTPs outcall.
+    pub base_fee: u128,
+    /// Fee paid for the request bytes (URL, headers, body and transform context).
+    pub request_fee: u128,
+    /// Fee paid for the response bytes, as given by `max_response_bytes`.
+    pub response_fee: u128,
+}
+
+impl CyclesCostBreakdown {
+    /// Total cost of the HTTPs outcall.
+    pub fn total(&self) -> u128 {
+        self.base_fee
+            .saturating_add(self.request_fee)
+            .saturating_add(self.response_fee)
+    }
 }
 
 /// Estimate the exact minimum cycles amount required to send an HTTPs outcall as specified
 /// [here](https://internetcomputer.org/docs/current/developer-docs/gas-cost#https-outcalls).
+///
+/// Replicated outcalls are made by all nodes of the subnet, while non-replicated outcalls
+/// (see [`IsReplicatedRequestExtension`](crate::IsReplicatedRequestExtension)) are made
+/// by a single node and priced accordingly.
 #[derive(Debug, Clone, Eq, PartialEq)]
 pub struct CyclesCostEstimator {
     num_nodes_in_subnet: u32,
+    pricing: CyclesPricing,
 }
 
 impl CyclesCostEstimator {
@@ -140,10 +401,18 @@ impl CyclesCostEstimator {
     /// see the [IC specification](https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-http_request).
     pub const DEFAULT_MAX_RESPONSE_BYTES: u64 = 2_000_000;
 
-    /// Create a new estimator for a subnet having the given number of nodes.
+    /// Create a new estimator for a subnet having the given number of nodes
+    /// using the default pricing schedule.
     pub const fn new(num_nodes_in_subnet: u32) -> Self {
+        Self::new_with_pricing(num_nodes_in_subnet, CyclesPricing::V1)
+    }
+
+    /// Create a new estimator for a subnet having the given number of nodes
+    /// using the given pricing schedule.
+    pub const fn new_with_pricing(num_nodes_in_subnet: u32, pricing: CyclesPricing) -> Self {
         CyclesCostEstimator {
             num_nodes_in_subnet,
+            pricing,
         }
     }
 
@@ -154,7 +423,12 @@ impl CyclesCostEstimator {
     /// ([IC specification](https://internetcomputer.org/docs/current/references/ic-interface-spec#ic-http_request)).
     /// The required amount of cycles to attach is specified
     /// [here](https://internetcomputer.org/docs/current/developer-docs/gas-cost#https-outcalls).
-    pub fn cost_of_http_request(&self, request: &CanisterHttpRequestArgument) -> u128 {
+    pub fn cost_of_http_request(&self, request: &IcHttpRequest) -> u128 {
+        self.cost_breakdown_of_http_request(request).total()
+    }
+
+    /// Similar to [`Self::cost_of_http_request`] but returns the cost of each component.
+    pub fn cost_breakdown_of_http_request(&self, request: &IcHttpRequest) -> CyclesCostBreakdown {
         let payload_body_bytes = request
             .body
             .as_ref()
@@ -173,31 +447,87 @@ impl CyclesCostEstimator {
             .max_response_bytes
             .unwrap_or(Self::DEFAULT_MAX_RESPONSE_BYTES);
         let request_bytes = (payload_body_bytes + extra_payload_bytes) as u128;
-        self.base_fee()
-            + self.request_fee(request_bytes)
-            + self.response_fee(max_response_bytes as u128)
+        let num_nodes = self.num_nodes(request);
+        CyclesCostBreakdown {
+            base_fee: self.pricing.base_fee(num_nodes),
+            request_fee: self.pricing.request_fee(num_nodes, request_bytes),
+            response_fee: self
+                .pricing
+                .response_fee(num_nodes, max_response_bytes as u128),
+        }
     }
 
-    fn base_fee(&self) -> u128 {
-        3_000_000_u128
-            .saturating_add(60_000_u128.saturating_mul(self.num_nodes_as_u128()))
-            .saturating_mul(self.num_nodes_as_u128())
+    fn num_nodes(&self, request: &IcHttpRequest) -> u32 {
+        match request.is_replicated {
+            Some(false) => 1,
+            Some(true) | None => self.num_nodes_in_subnet,
+        }
     }
+}
 
-    fn request_fee(&self, bytes: u128) -> u128 {
-        400_u128
-            .saturating_mul(self.num_nodes_as_u128())
-            .saturating_mul(bytes)
+/// Estimate the number of cycles to attach to an HTTPs outcall.
+///
+/// Implementations from this crate:
+/// * [`CyclesCostEstimator`]: exact minimum amount required by the Internet Computer.
+/// * [`FixedCyclesCost`]: the same amount for every request.
+/// * [`WithSafetyMargin`]: increase the estimate of another estimator by a percentage.
+pub trait CyclesEstimator {
+    /// Estimate the number of cycles to attach to send the given request.
+    fn cost_of_http_request(&self, request: &IcHttpRequest) -> u128;
+}
+
+impl CyclesEstimator for CyclesCostEstimator {
+    fn cost_of_http_request(&self, request: &IcHttpRequest) -> u128 {
+        CyclesCostEstimator::cost_of_http_request(self, request)
     }
+}
 
-    fn response_fee(&self, bytes: u128) -> u128 {
-        800_u128
-            .saturating_mul(self.num_nodes_as_u128())
-            .saturating_mul(bytes)
+/// Attach the same number of cycles to every HTTPs outcall, regardless of the request.
+///
+/// Cycles not used by an HTTPs outcall are refunded, so that this estimator can be used
+/// to attach an upper bound on the cost of the requests.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub struct FixedCyclesCost(pub u128);
+
+impl CyclesEstimator for FixedCyclesCost {
+    fn cost_of_http_request(&self, _request: &IcHttpRequest) -> u128 {
+        self.0
+    }
+}
+
+/// Increase the estimate of the inner estimator by the given percentage.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::cycles::{CyclesEstimator, FixedCyclesCost, WithSafetyMargin};
+/// use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+///
+/// let estimator = WithSafetyMargin::new(FixedCyclesCost(1_000_000), 10);
+///
+/// assert_eq!(estimator.cost_of_http_request(&IcHttpRequest::default()), 1_100_000);
+/// ```
+#[derive(Debug, Clone, Eq, PartialEq)]
+pub struct WithSafetyMargin<E> {
+    estimator: E,
+    margin_percent: u32,
+}
+
+impl<E> WithSafetyMargin<E> {
+    /// Create a new estimator adding `margin_percent` percent to the estimate of the given estimator.
+    pub fn new(estimator: E, margin_percent: u32) -> Self {
+        Self {
+            estimator,
+            margin_percent,
+        }
     }
+}
 
-    fn num_nodes_as_u128(&self) -> u128 {
-        self.num_nodes_in_subnet as u128
+impl<E: CyclesEstimator> CyclesEstimator for WithSafetyMargin<E> {
+    fn cost_of_http_request(&self, request: &IcHttpRequest) -> u128 {
+        let cost = self.estimator.cost_of_http_request(request);
+        let margin = cost.saturating_mul(self.margin_percent as u128) / 100;
+        cost.saturating_add(margin)
     }
 }
 
@@ -215,41 +545,234 @@ pub enum ChargeCallerError {
 }
 
 /// A middleware to handle cycles accounting, i.e. verify if sufficiently many cycles are available in a request.
-/// How cycles are estimated is given by `CyclesEstimator`
+/// How cycles are estimated is given by [`CyclesEstimator`] and how cycles are charged is given by [`CyclesChargingPolicy`].
+///
+/// This [`Layer`] produces instances of the [`CyclesAccountingService`] service,
+/// which handles requests of type [`IcHttpRequest`] unless configured otherwise
+/// with [`CyclesAccounting::with_requests_with_cycles`].
 #[derive(Clone, Debug)]
-pub struct CyclesAccounting<ChargingPolicy> {
-    cycles_cost_estimator: CyclesCostEstimator,
+pub struct CyclesAccounting<
+    ChargingPolicy,
+    Estimator = CyclesCostEstimator,
+    Request = IcHttpRequest,
+> {
+    cycles_cost_estimator: Estimator,
     charging_policy: ChargingPolicy,
+    _marker: PhantomData<fn(Request)>,
 }
 
 impl<ChargingPolicy> CyclesAccounting<ChargingPolicy> {
-    /// Create a new middleware given the cycles estimator.
+    /// Create a new middleware estimating cycles with [`CyclesCostEstimator`]
+    /// for a subnet having the given number of nodes.
     pub fn new(num_nodes_in_subnet: u32, charging_policy: ChargingPolicy) -> Self {
+        Self::new_with_estimator(
+            CyclesCostEstimator::new(num_nodes_in_subnet),
+            charging_policy,
+        )
+    }
+}
+
+impl<ChargingPolicy, Estimator> CyclesAccounting<ChargingPolicy, Estimator> {
+    /// Create a new middleware given the cycles estimator.
+    pub fn new_with_estimator(estimator: Estimator, charging_policy: ChargingPolicy) -> Self {
         Self {
-            cycles_cost_estimator: CyclesCostEstimator::new(num_nodes_in_subnet),
+            cycles_cost_estimator: estimator,
             charging_policy,
+            _marker: PhantomData,
+        }
+    }
+
+    /// Handle requests of type [`IcHttpRequestWithCycles`] instead of [`IcHttpRequest`],
+    /// so that the metadata of a request, such as its retry attempt, is forwarded to the inner service,
+    /// see [`HttpRequestWithCyclesConverter`](crate::http::HttpRequestWithCyclesConverter).
+    pub fn with_requests_with_cycles(
+        self,
+    ) -> CyclesAccounting<ChargingPolicy, Estimator, IcHttpRequestWithCycles> {
+        CyclesAccounting {
+            cycles_cost_estimator: self.cycles_cost_estimator,
+            charging_policy: self.charging_policy,
+            _marker: PhantomData,
         }
     }
 }
 
-impl<ChargingPolicy> Convert<CanisterHttpRequestArgument> for CyclesAccounting<ChargingPolicy>
+impl<ChargingPolicy, Estimator, Request> CyclesAccounting<ChargingPolicy, Estimator, Request>
 where
     ChargingPolicy: CyclesChargingPolicy,
+    Estimator: CyclesEstimator,
+{
+    /// Estimate the cycles to attach to the request and charge them.
+    ///
+    /// Return the request with the cycles to attach together with the charge.
+    fn charge(
+        &self,
+        request: impl Into<IcHttpRequestWithCycles>,
+    ) -> Result<(IcHttpRequestWithCycles, ChargingPolicy::Charge), ChargingPolicy::Error> {
+        let mut request = request.into();
+        let cycles_to_attach = self
+            .cycles_cost_estimator
+            .cost_of_http_request(&request.request);
+        let charge = self
+            .charging_policy
+            .charge_cycles(&request.request, cycles_to_attach)
+            .inspect_err(|_| {
+                #[cfg(feature = "tracing")]
+                tracing::warn!(
+                    cycles_attached = cycles_to_attach,
+                    "Failed to charge cycles for HTTPs outcall"
+                );
+            })?;
+        #[cfg(feature = "tracing")]
+        tracing::debug!(
+            cycles_attached = cycles_to_attach,
+            cycles_charged = charge.charged_cycles(),
+            "Charged cycles for HTTPs outcall"
+        );
+        request.cycles = cycles_to_attach;
+        Ok((request, charge))
+    }
+}
+
+impl<S, ChargingPolicy, Estimator, Request> Layer<S>
+    for CyclesAccounting<ChargingPolicy, Estimator, Request>
+where
+    ChargingPolicy: Clone,
+    Estimator: Clone,
+    Request: Clone,
+{
+    type Service = CyclesAccountingService<S, ChargingPolicy, Estimator, Request>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CyclesAccountingService {
+            inner,
+            accounting: self.clone(),
+        }
+    }
+}
+
+/// Service produced by the [`CyclesAccounting`] middleware.
+///
+/// Charge cycles before forwarding the request to the inner service and settle the charged cycles
+/// once the inner service produced a result, or when the response future is dropped before that
+/// (see [`CyclesChargingPolicy::settle_cycles`]).
+///
+/// Requests are of type [`IcHttpRequest`] by default, or of type [`IcHttpRequestWithCycles`]
+/// to carry metadata such as the retry attempt down to the inner service
+/// (see [`CyclesAccounting::with_requests_with_cycles`]).
+#[derive(Clone, Debug)]
+pub struct CyclesAccountingService<S, ChargingPolicy, Estimator, Request = IcHttpRequest> {
+    inner: S,
+    accounting: CyclesAccounting<ChargingPolicy, Estimator, Request>,
+}
+
+impl<S, Request, ChargingPolicy, Estimator> Service<Request>
+    for CyclesAccountingService<S, ChargingPolicy, Estimator, Request>
+where
+    Request: Into<IcHttpRequestWithCycles>,
+    S: Service<IcHttpRequestWithCycles>,
+    ChargingPolicy: CyclesChargingPolicy + Clone,
+    ChargingPolicy::Error: Into<S::Error>,
+    Estimator: CyclesEstimator,
 {
-    type Output = IcHttpRequestWithCycles;
-    type Error = ChargingPolicy::Error;
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = future::Either<
+        ResponseFuture<S::Future, ChargingPolicy>,
+        future::Ready<Result<S::Response, S::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
 
-    fn try_convert(
-        &mut self,
-        request: CanisterHttpRequestArgument,
-    ) -> Result<Self::Output, Self::Error> {
-        let cycles_to_attach = self.cycles_cost_estimator.cost_of_http_request(&request);
-        self.charging_policy
-            .charge_cycles(&request, cycles_to_attach)?;
-        Ok(IcHttpRequestWithCycles {
-            request,
-            cycles: cycles_to_attach,
-        })
+    fn call(&mut self, request: Request) -> Self::Future {
+        match self.accounting.charge(request) {
+            Ok((request, charge)) => {
+                let settlement = Settlement::new(
+                    self.accounting.charging_policy.clone(),
+                    charge,
+                    request.usage.clone(),
+                );
+                future::Either::Left(ResponseFuture {
+                    response_future: self.inner.call(request),
+                    settlement,
+                })
+            }
+            Err(err) => future::Either::Right(future::ready(Err(err.into()))),
+        }
+    }
+}
+
+/// Response future for [`CyclesAccountingService`].
+#[pin_project]
+pub struct ResponseFuture<F, ChargingPolicy: CyclesChargingPolicy> {
+    // Declared before the settlement so that, when dropped, the inner future is dropped
+    // before the charged cycles are settled.
+    #[pin]
+    response_future: F,
+    settlement: Settlement<ChargingPolicy, CyclesUsageRecorder>,
+}
+
+impl<F, ChargingPolicy, Response, Error> Future for ResponseFuture<F, ChargingPolicy>
+where
+    F: Future<Output = Result<Response, Error>>,
+    ChargingPolicy: CyclesChargingPolicy,
+{
+    type Output = Result<Response, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result_fut = this.response_future.poll(cx);
+        if result_fut.is_ready() {
+            this.settlement.settle();
+        }
+        result_fut
+    }
+}
+
+/// Cycles usage with which charged cycles are settled.
+trait SettlementUsage {
+    fn settlement_usage(&self) -> Option<CyclesUsage>;
+}
+
+impl SettlementUsage for CyclesUsageRecorder {
+    fn settlement_usage(&self) -> Option<CyclesUsage> {
+        self.get()
+    }
+}
+
+/// Cycles charged for a single request, settled exactly once:
+/// when the response is ready or, if the response future is dropped before that, on drop.
+struct Settlement<ChargingPolicy: CyclesChargingPolicy, Usage: SettlementUsage> {
+    charging_policy: ChargingPolicy,
+    charge: Option<ChargingPolicy::Charge>,
+    usage: Usage,
+}
+
+impl<ChargingPolicy: CyclesChargingPolicy, Usage: SettlementUsage>
+    Settlement<ChargingPolicy, Usage>
+{
+    fn new(charging_policy: ChargingPolicy, charge: ChargingPolicy::Charge, usage: Usage) -> Self {
+        Self {
+            charging_policy,
+            charge: Some(charge),
+            usage,
+        }
+    }
+
+    fn settle(&mut self) {
+        if let Some(charge) = self.charge.take() {
+            self.charging_policy
+                .settle_cycles(charge, self.usage.settlement_usage());
+        }
+    }
+}
+
+impl<ChargingPolicy: CyclesChargingPolicy, Usage: SettlementUsage> Drop
+    for Settlement<ChargingPolicy, Usage>
+{
+    fn drop(&mut self) {
+        self.settle();
     }
 }
 
@@ -263,7 +786,26 @@ pub trait CyclesAccountingServiceBuilder<L> {
         self,
         num_nodes_in_subnet: u32,
         charging: C,
-    ) -> ServiceBuilder<Stack<ConvertRequestLayer<CyclesAccounting<C>>, L>>;
+    ) -> ServiceBuilder<Stack<CyclesAccounting<C>, L>>;
+
+    /// Add cycles accounting where cycles are estimated by the given [`CyclesEstimator`].
+    ///
+    /// See the [module docs](crate::cycles) for examples.
+    fn cycles_accounting_with_estimator<C, E>(
+        self,
+        estimator: E,
+        charging: C,
+    ) -> ServiceBuilder<Stack<CyclesAccounting<C, E>, L>>;
+
+    /// Add cycles accounting for requests that are retried with a larger `max_response_bytes`
+    /// when the response was too large, charging once for all attempts.
+    ///
+    /// See [`CyclesAccountingWithRetries`] and the [module docs](crate::cycles) for examples.
+    fn cycles_accounting_with_retries<C>(
+        self,
+        num_nodes_in_subnet: u32,
+        charging: C,
+    ) -> ServiceBuilder<Stack<CyclesAccountingWithRetries<C>, L>>;
 }
 
 impl<L> CyclesAccountingServiceBuilder<L> for ServiceBuilder<L> {
@@ -271,7 +813,26 @@ impl<L> CyclesAccountingServiceBuilder<L> for ServiceBuilder<L> {
         self,
         num_nodes_in_subnet: u32,
         charging: C,
-    ) -> ServiceBuilder<Stack<ConvertRequestLayer<CyclesAccounting<C>>, L>> {
-        self.convert_request(CyclesAccounting::new(num_nodes_in_subnet, charging))
+    ) -> ServiceBuilder<Stack<CyclesAccounting<C>, L>> {
+        self.layer(CyclesAccounting::new(num_nodes_in_subnet, charging))
+    }
+
+    fn cycles_accounting_with_estimator<C, E>(
+        self,
+        estimator: E,
+        charging: C,
+    ) -> ServiceBuilder<Stack<CyclesAccounting<C, E>, L>> {
+        self.layer(CyclesAccounting::new_with_estimator(estimator, charging))
+    }
+
+    fn cycles_accounting_with_retries<C>(
+        self,
+        num_nodes_in_subnet: u32,
+        charging: C,
+    ) -> ServiceBuilder<Stack<CyclesAccountingWithRetries<C>, L>> {
+        self.layer(CyclesAccountingWithRetries::new(
+            num_nodes_in_subnet,
+            charging,
+        ))
     }
 }
@@ -0,0 +1,311 @@
+use crate::cycles::{ChargedCycles, CyclesChargingPolicy};
+use crate::CyclesUsage;
+use candid::Principal;
+use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+#[cfg(feature = "stable")]
+use ic_stable_structures::{Memory, StableBTreeMap};
+#[cfg(feature = "stable")]
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+#[cfg(feature = "stable")]
+use std::rc::Rc;
+use std::sync::{Arc, Mutex};
+use thiserror::Error;
+
+/// Storage backend for the prepaid cycles balances used by [`ChargePrepaidBalance`].
+///
+/// Use `StablePrepaidBalances` (requires the `stable` feature) for balances to survive upgrades
+/// without further ado, or use [`InMemoryPrepaidBalances`] and persist its content in the
+/// `pre_upgrade` and `post_upgrade` hooks.
+pub trait PrepaidBalances {
+    /// Return the prepaid cycles balance of the given principal.
+    ///
+    /// Principals without any balance have a balance of 0.
+    fn balance(&self, principal: &Principal) -> u128;
+
+    /// Set the prepaid cycles balance of the given principal.
+    fn set_balance(&self, principal: Principal, balance: u128);
+}
+
+/// Prepaid cycles balances stored on the heap.
+///
+/// All clones share the same balances.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::cycles::{InMemoryPrepaidBalances, PrepaidBalances};
+/// use candid::Principal;
+/// use std::collections::BTreeMap;
+///
+/// let balances = InMemoryPrepaidBalances::default();
+/// balances.set_balance(Principal::anonymous(), 1_000);
+///
+/// // In `pre_upgrade`: save the balances to stable memory.
+/// let snapshot: BTreeMap<Principal, u128> = balances.snapshot();
+///
+/// // In `post_upgrade`: restore the balances from stable memory.
+/// let balances = InMemoryPrepaidBalances::from(snapshot);
+/// assert_eq!(balances.balance(&Principal::anonymous()), 1_000);
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct InMemoryPrepaidBalances(Arc<Mutex<BTreeMap<Principal, u128>>>);
+
+impl InMemoryPrepaidBalances {
+    /// Return a copy of all non-zero balances.
+    pub fn snapshot(&self) -> BTreeMap<Principal, u128> {
+        self.0.lock().unwrap().clone()
+    }
+}
+
+impl From<BTreeMap<Principal, u128>> for InMemoryPrepaidBalances {
+    fn from(balances: BTreeMap<Principal, u128>) -> Self {
+        Self(Arc::new(Mutex::new(balances)))
+    }
+}
+
+impl PrepaidBalances for InMemoryPrepaidBalances {
+    fn balance(&self, principal: &Principal) -> u128 {
+        self.0
+            .lock()
+            .unwrap()
+            .get(principal)
+            .copied()
+            .unwrap_or_default()
+    }
+
+    fn set_balance(&self, principal: Principal, balance: u128) {
+        let mut balances = self.0.lock().unwrap();
+        if balance == 0 {
+            balances.remove(&principal);
+        } else {
+            balances.insert(principal, balance);
+        }
+    }
+}
+
+/// Prepaid cycles balances stored in stable memory, so that they survive canister upgrades.
+///
+/// All clones share the same balances.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::cycles::{PrepaidBalances, StablePrepaidBalances};
+/// use candid::Principal;
+/// use ic_stable_structures::VectorMemory;
+///
+/// // Inside a canister, use a virtual memory from `ic_stable_structures::memory_manager` instead.
+/// let memory = VectorMemory::default();
+/// let balances = StablePrepaidBalances::init(memory.clone());
+/// balances.set_balance(Principal::anonymous(), 1_000);
+///
+/// // After an upgrade: the balances are read from the same memory.
+/// let balances = StablePrepaidBalances::init(memory);
+/// assert_eq!(balances.balance(&Principal::anonymous()), 1_000);
+/// ```
+#[cfg(feature = "stable")]
+pub struct StablePrepaidBalances<M: Memory>(Rc<RefCell<StableBTreeMap<Principal, u128, M>>>);
+
+#[cfg(feature = "stable")]
+impl<M: Memory> StablePrepaidBalances<M> {
+    /// Initialize the balances in the given memory, or load them if the memory already contains balances.
+    pub fn init(memory: M) -> Self {
+        Self(Rc::new(RefCell::new(StableBTreeMap::init(memory))))
+    }
+}
+
+#[cfg(feature = "stable")]
+impl<M: Memory> Clone for StablePrepaidBalances<M> {
+    fn clone(&self) -> Self {
+        Self(Rc::clone(&self.0))
+    }
+}
+
+#[cfg(feature = "stable")]
+impl<M: Memory> PrepaidBalances for StablePrepaidBalances<M> {
+    fn balance(&self, principal: &Principal) -> u128 {
+        self.0.borrow().get(principal).unwrap_or_default()
+    }
+
+    fn set_balance(&self, principal: Principal, balance: u128) {
+        let mut balances = self.0.borrow_mut();
+        if balance == 0 {
+            balances.remove(&principal);
+        } else {
+            balances.insert(principal, balance);
+        }
+    }
+}
+
+/// Cycles will be deducted from the prepaid balance of the caller of the canister using that library
+/// to pay for HTTPs outcalls.
+///
+/// Contrary to [`ChargeCaller`], the calls issuing HTTPs outcalls do not need to carry any cycles:
+/// cycles are deposited beforehand with [`ChargePrepaidBalance::deposit`],
+/// typically from an endpoint accepting the cycles attached to the call.
+///
+/// Once the HTTPs outcall completed, or its response future was dropped, the cycles refunded
+/// by the management canister are deposited back to the balance of the caller, up to the charged amount.
+/// When the HTTPs outcall was not made, the whole charged amount is deposited back.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{cycles::{ChargePrepaidBalance, CyclesAccountingServiceBuilder, InMemoryPrepaidBalances}, Client};
+/// use candid::Principal;
+/// use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let policy = ChargePrepaidBalance::new(
+///     InMemoryPrepaidBalances::default(),
+///     |_request, cost| cost + 1_000_000,
+/// );
+///
+/// let user = Principal::anonymous();
+/// policy.deposit(user, 1_000_000_000);
+/// assert_eq!(policy.balance(&user), 1_000_000_000);
+///
+/// let mut service = ServiceBuilder::new()
+///   .cycles_accounting(34, policy)
+///   .service(Client::new_with_box_error());
+///
+/// let _ = service.ready().await.unwrap();
+///
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`ChargeCaller`]: crate::cycles::ChargeCaller
+#[derive(Clone, Debug)]
+pub struct ChargePrepaidBalance<Balances, F> {
+    balances: Balances,
+    cycles_to_charge: F,
+    caller: fn() -> Principal,
+}
+
+impl<Balances, F> ChargePrepaidBalance<Balances, F>
+where
+    Balances: PrepaidBalances,
+    F: Fn(&IcHttpRequest, u128) -> u128,
+{
+    /// Create a new instance of [`ChargePrepaidBalance`].
+    pub fn new(balances: Balances, cycles_to_charge: F) -> Self {
+        Self {
+            balances,
+            cycles_to_charge,
+            caller: ic_cdk::api::msg_caller,
+        }
+    }
+
+    /// Charge the principal returned by the given function instead of the caller.
+    #[cfg(test)]
+    pub(super) fn with_caller(self, caller: fn() -> Principal) -> Self {
+        Self { caller, ..self }
+    }
+
+    /// Return the prepaid cycles balance of the given principal.
+    pub fn balance(&self, principal: &Principal) -> u128 {
+        self.balances.balance(principal)
+    }
+
+    /// Add cycles to the prepaid balance of the given principal and return the new balance.
+    pub fn deposit(&self, principal: Principal, cycles: u128) -> u128 {
+        let new_balance = self.balances.balance(&principal).saturating_add(cycles);
+        self.balances.set_balance(principal, new_balance);
+        new_balance
+    }
+
+    /// Remove cycles from the prepaid balance of the given principal and return the new balance.
+    ///
+    /// Note that the withdrawn cycles are only removed from the balance,
+    /// sending them back to the principal is the responsibility of the canister.
+    pub fn withdraw(
+        &self,
+        principal: Principal,
+        cycles: u128,
+    ) -> Result<u128, PrepaidBalanceError> {
+        let balance = self.balances.balance(&principal);
+        if balance < cycles {
+            return Err(PrepaidBalanceError::InsufficientBalance {
+                expected: cycles,
+                balance,
+            });
+        }
+        let new_balance = balance - cycles;
+        self.balances.set_balance(principal, new_balance);
+        Ok(new_balance)
+    }
+
+    /// Deposit back the cycles charged for an HTTPs outcall that were not spent.
+    pub(super) fn refund(&self, charge: PrepaidCharge, usage: Option<CyclesUsage>) {
+        let cycles_to_refund =
+            usage.map_or(charge.cycles, |usage| usage.refunded.min(charge.cycles));
+        if cycles_to_refund > 0 {
+            self.deposit(charge.principal, cycles_to_refund);
+        }
+    }
+}
+
+impl<Balances, F> CyclesChargingPolicy for ChargePrepaidBalance<Balances, F>
+where
+    Balances: PrepaidBalances,
+    F: Fn(&IcHttpRequest, u128) -> u128,
+{
+    type Error = PrepaidBalanceError;
+    type Charge = PrepaidCharge;
+
+    fn charge_cycles(
+        &self,
+        request: &IcHttpRequest,
+        request_cycles_cost: u128,
+    ) -> Result<PrepaidCharge, Self::Error> {
+        let charge = PrepaidCharge {
+            principal: (self.caller)(),
+            cycles: (self.cycles_to_charge)(request, request_cycles_cost),
+        };
+        if charge.cycles > 0 {
+            self.withdraw(charge.principal, charge.cycles)?;
+        }
+        Ok(charge)
+    }
+
+    fn settle_cycles(&self, charge: PrepaidCharge, usage: Option<CyclesUsage>) {
+        if charge.cycles > 0 {
+            self.refund(charge, usage);
+        }
+    }
+}
+
+/// Cycles deducted from the prepaid balance of a principal to pay for a single request,
+/// see [`ChargePrepaidBalance`].
+///
+/// The principal is kept with the charge, so that unspent cycles are deposited back to its balance
+/// even when the response future is dropped outside of the call that made the request.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PrepaidCharge {
+    /// Principal whose prepaid balance was charged.
+    pub principal: Principal,
+    /// Number of charged cycles.
+    pub cycles: u128,
+}
+
+impl ChargedCycles for PrepaidCharge {
+    fn charged_cycles(&self) -> u128 {
+        self.cycles
+    }
+}
+
+/// Error returned by the [`ChargePrepaidBalance`] cycles charging policy.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum PrepaidBalanceError {
+    /// Error returned when the prepaid balance does not contain sufficiently many cycles.
+    #[error("insufficient prepaid balance (expected {expected:?}, balance {balance:?})")]
+    InsufficientBalance {
+        /// Expected amount of cycles.
+        expected: u128,
+        /// Prepaid balance.
+        balance: u128,
+    },
+}
@@ -0,0 +1,166 @@
+use crate::cycles::CyclesChargingPolicy;
+use crate::time::{Clock, IcClock, Timestamp};
+use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+use std::collections::VecDeque;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+use thiserror::Error;
+
+/// The canister using that policy will pay for HTTPs outcalls with its own cycles,
+/// while making sure that its cycles balance never falls below a given reserve.
+///
+/// Optionally, the total amount of cycles spent on HTTPs outcalls within a rolling time window
+/// can be capped with [`ChargeMyselfWithReserve::with_spending_limit`].
+/// The time window is measured with [`IcClock`] by default, which can be changed with
+/// [`ChargeMyselfWithReserve::with_clock`].
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{cycles::{ChargeMyselfWithReserve, CyclesAccountingServiceBuilder}, Client};
+/// use std::time::Duration;
+/// use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+///   .cycles_accounting(
+///       34,
+///       ChargeMyselfWithReserve::new(1_000_000_000_000)
+///           .with_spending_limit(100_000_000_000, Duration::from_secs(3_600))
+///   )
+///   .service(Client::new_with_box_error());
+///
+/// let _ = service.ready().await.unwrap();
+///
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ChargeMyselfWithReserve<C = IcClock> {
+    reserve: u128,
+    spending_limit: Option<SpendingLimit>,
+    clock: C,
+}
+
+#[derive(Clone, Debug)]
+struct SpendingLimit {
+    max_cycles: u128,
+    window: Duration,
+    // Cycles spent by HTTPs outcalls, ordered by their time.
+    // Shared by all clones of the policy.
+    spent: Arc<Mutex<VecDeque<(Timestamp, u128)>>>,
+}
+
+impl ChargeMyselfWithReserve {
+    /// Create a new instance of [`ChargeMyselfWithReserve`] refusing HTTPs outcalls that would bring
+    /// the canister cycles balance below `reserve`.
+    ///
+    /// The reserve should be at least the freezing threshold of the canister.
+    pub fn new(reserve: u128) -> Self {
+        Self {
+            reserve,
+            spending_limit: None,
+            clock: IcClock,
+        }
+    }
+}
+
+impl<C: Clock> ChargeMyselfWithReserve<C> {
+    /// Additionally refuse HTTPs outcalls once the canister spent `max_cycles` on HTTPs outcalls
+    /// within the last `window`.
+    ///
+    /// The cycles spent by an HTTPs outcall are the cycles attached to it,
+    /// ignoring the cycles that may be refunded by the management canister.
+    pub fn with_spending_limit(self, max_cycles: u128, window: Duration) -> Self {
+        Self {
+            spending_limit: Some(SpendingLimit {
+                max_cycles,
+                window,
+                spent: Arc::new(Mutex::new(VecDeque::new())),
+            }),
+            ..self
+        }
+    }
+
+    /// Use the given clock to measure the time window of the spending limit.
+    pub fn with_clock<D: Clock>(self, clock: D) -> ChargeMyselfWithReserve<D> {
+        ChargeMyselfWithReserve {
+            reserve: self.reserve,
+            spending_limit: self.spending_limit,
+            clock,
+        }
+    }
+
+    pub(super) fn try_spend(&self, balance: u128, cycles: u128) -> Result<(), ChargeMyselfError> {
+        if balance.saturating_sub(cycles) < self.reserve {
+            return Err(ChargeMyselfError::ReserveExceeded {
+                balance,
+                cost: cycles,
+                reserve: self.reserve,
+            });
+        }
+        if let Some(limit) = &self.spending_limit {
+            let now = self.clock.now();
+            let mut spent = limit.spent.lock().unwrap();
+            while spent
+                .front()
+                .is_some_and(|(time, _)| now.saturating_duration_since(*time) >= limit.window)
+            {
+                spent.pop_front();
+            }
+            let spent_in_window = spent
+                .iter()
+                .fold(0_u128, |total, (_, cycles)| total.saturating_add(*cycles));
+            if spent_in_window.saturating_add(cycles) > limit.max_cycles {
+                return Err(ChargeMyselfError::SpendingLimitExceeded {
+                    spent: spent_in_window,
+                    cost: cycles,
+                    limit: limit.max_cycles,
+                });
+            }
+            spent.push_back((now, cycles));
+        }
+        Ok(())
+    }
+}
+
+impl<C: Clock> CyclesChargingPolicy for ChargeMyselfWithReserve<C> {
+    type Error = ChargeMyselfError;
+    type Charge = u128;
+
+    fn charge_cycles(
+        &self,
+        _request: &IcHttpRequest,
+        request_cycles_cost: u128,
+    ) -> Result<u128, Self::Error> {
+        self.try_spend(ic_cdk::api::canister_cycle_balance(), request_cycles_cost)?;
+        // The caller is not charged.
+        Ok(0)
+    }
+}
+
+/// Error returned by the [`ChargeMyselfWithReserve`] cycles charging policy.
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum ChargeMyselfError {
+    /// Error returned when paying for the HTTPs outcall would bring the canister cycles balance below the reserve.
+    #[error("cycles reserve exceeded (balance {balance:?}, cost {cost:?}, reserve {reserve:?})")]
+    ReserveExceeded {
+        /// Cycles balance of the canister.
+        balance: u128,
+        /// Cycles cost of the HTTPs outcall.
+        cost: u128,
+        /// Minimum cycles balance the canister must keep.
+        reserve: u128,
+    },
+    /// Error returned when paying for the HTTPs outcall would exceed the spending limit of the current time window.
+    #[error("cycles spending limit exceeded (spent {spent:?}, cost {cost:?}, limit {limit:?})")]
+    SpendingLimitExceeded {
+        /// Cycles already spent within the current time window.
+        spent: u128,
+        /// Cycles cost of the HTTPs outcall.
+        cost: u128,
+        /// Maximum amount of cycles that can be spent within a time window.
+        limit: u128,
+    },
+}
@@ -0,0 +1,391 @@
+use crate::client::IcHttpRequestWithCycles;
+use crate::cycles::{
+    CyclesChargingPolicy, CyclesCostEstimator, CyclesEstimator, Settlement, SettlementUsage,
+};
+use crate::retry::{DoubleMaxResponseBytes, MaxResponseBytesRetrySchedule};
+use crate::{
+    CyclesUsage, CyclesUsageRecorder, MaxResponseBytesRequestExtension,
+    RetryAttemptRequestExtension,
+};
+use futures_util::future;
+use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+use pin_project::pin_project;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::retry::Retry;
+use tower::Service;
+use tower_layer::Layer;
+
+/// A middleware combining cycles accounting with retrying requests whose response was too large
+/// (by default with [`DoubleMaxResponseBytes`], see [`CyclesAccountingWithRetries::with_retry_policy`]).
+///
+/// Stacking [`DoubleMaxResponseBytes`] on top of [`CyclesAccounting`] charges cycles anew for each attempt,
+/// which fails as soon as the cycles attached by the caller do not cover the next attempt.
+/// Instead, this middleware:
+/// 1. Charges once for the worst case, i.e. the sum of the cycles cost of every attempt
+///    that the retry policy could make (see [`MaxResponseBytesRetrySchedule`]).
+/// 2. Pays for each attempt from that reserve.
+///    The cycles refunded by the management canister for an attempt, as recorded by [`Client`]
+///    (see [`IcHttpRequestWithCycles::usage`]), go back to the reserve, whether the attempt succeeded or not.
+/// 3. Settles the charged cycles once the last attempt completed, or when the response future is dropped
+///    before that (see [`CyclesChargingPolicy::settle_cycles`]),
+///    where all cycles from the reserve that were not spent are reported as refunded.
+///
+/// To only charge the caller for the attempts that were actually made,
+/// use this middleware together with [`ChargeCaller::with_refund`].
+///
+/// This [`Layer`] produces instances of the [`CyclesAccountingWithRetriesService`] service.
+///
+/// [`CyclesAccounting`]: crate::cycles::CyclesAccounting
+/// [`ChargeCaller::with_refund`]: crate::cycles::ChargeCaller::with_refund
+/// [`Client`]: crate::Client
+#[derive(Clone, Debug)]
+pub struct CyclesAccountingWithRetries<
+    ChargingPolicy,
+    Estimator = CyclesCostEstimator,
+    RetryPolicy = DoubleMaxResponseBytes,
+> {
+    cycles_cost_estimator: Estimator,
+    charging_policy: ChargingPolicy,
+    retry_policy: RetryPolicy,
+}
+
+impl<ChargingPolicy> CyclesAccountingWithRetries<ChargingPolicy> {
+    /// Create a new middleware given the number of nodes in the subnet and the charging policy.
+    pub fn new(num_nodes_in_subnet: u32, charging_policy: ChargingPolicy) -> Self {
+        Self::new_with_estimator(
+            CyclesCostEstimator::new(num_nodes_in_subnet),
+            charging_policy,
+        )
+    }
+}
+
+impl<ChargingPolicy, Estimator> CyclesAccountingWithRetries<ChargingPolicy, Estimator> {
+    /// Create a new middleware given the cycles estimator and the charging policy.
+    pub fn new_with_estimator(estimator: Estimator, charging_policy: ChargingPolicy) -> Self {
+        Self {
+            cycles_cost_estimator: estimator,
+            charging_policy,
+            retry_policy: DoubleMaxResponseBytes,
+        }
+    }
+}
+
+impl<ChargingPolicy, Estimator, RetryPolicy>
+    CyclesAccountingWithRetries<ChargingPolicy, Estimator, RetryPolicy>
+{
+    /// Retry requests whose response was too large with the given policy, e.g.
+    /// [`GrowMaxResponseBytes`](crate::retry::GrowMaxResponseBytes), instead of [`DoubleMaxResponseBytes`].
+    pub fn with_retry_policy<NewRetryPolicy>(
+        self,
+        retry_policy: NewRetryPolicy,
+    ) -> CyclesAccountingWithRetries<ChargingPolicy, Estimator, NewRetryPolicy> {
+        CyclesAccountingWithRetries {
+            cycles_cost_estimator: self.cycles_cost_estimator,
+            charging_policy: self.charging_policy,
+            retry_policy,
+        }
+    }
+}
+
+impl<S, ChargingPolicy, Estimator, RetryPolicy> Layer<S>
+    for CyclesAccountingWithRetries<ChargingPolicy, Estimator, RetryPolicy>
+where
+    ChargingPolicy: Clone,
+    Estimator: Clone,
+    RetryPolicy: Clone,
+{
+    type Service = CyclesAccountingWithRetriesService<
+        Retry<RetryPolicy, PayFromCyclesReserve<S, Estimator>>,
+        ChargingPolicy,
+        Estimator,
+        RetryPolicy,
+    >;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        CyclesAccountingWithRetriesService {
+            inner: Retry::new(
+                self.retry_policy.clone(),
+                PayFromCyclesReserve {
+                    inner,
+                    cycles_cost_estimator: self.cycles_cost_estimator.clone(),
+                },
+            ),
+            accounting: self.clone(),
+        }
+    }
+}
+
+/// Service produced by the [`CyclesAccountingWithRetries`] middleware.
+///
+/// Charge cycles for the worst case of the retry sequence before forwarding the request
+/// to the inner service and settle the charged cycles once the inner service produced a result.
+#[derive(Clone, Debug)]
+pub struct CyclesAccountingWithRetriesService<
+    S,
+    ChargingPolicy,
+    Estimator,
+    RetryPolicy = DoubleMaxResponseBytes,
+> {
+    inner: S,
+    accounting: CyclesAccountingWithRetries<ChargingPolicy, Estimator, RetryPolicy>,
+}
+
+impl<S, ChargingPolicy, Estimator, RetryPolicy> Service<IcHttpRequest>
+    for CyclesAccountingWithRetriesService<S, ChargingPolicy, Estimator, RetryPolicy>
+where
+    S: Service<IcHttpRequestWithCyclesReserve>,
+    ChargingPolicy: CyclesChargingPolicy + Clone,
+    ChargingPolicy::Error: Into<S::Error>,
+    Estimator: CyclesEstimator,
+    RetryPolicy: MaxResponseBytesRetrySchedule,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = future::Either<
+        ResponseFuture<S::Future, ChargingPolicy>,
+        future::Ready<Result<S::Response, S::Error>>,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: IcHttpRequest) -> Self::Future {
+        let reserved_cycles = worst_case_cycles_cost(
+            &self.accounting.cycles_cost_estimator,
+            &self.accounting.retry_policy,
+            &request,
+        );
+        match self
+            .accounting
+            .charging_policy
+            .charge_cycles(&request, reserved_cycles)
+        {
+            Ok(charge) => {
+                let reserve = CyclesReserve::new(reserved_cycles);
+                future::Either::Left(ResponseFuture {
+                    response_future: self.inner.call(IcHttpRequestWithCyclesReserve {
+                        request,
+                        reserve: reserve.clone(),
+                        retry_attempt: 0,
+                    }),
+                    settlement: Settlement::new(
+                        self.accounting.charging_policy.clone(),
+                        charge,
+                        reserve,
+                    ),
+                })
+            }
+            Err(err) => future::Either::Right(future::ready(Err(err.into()))),
+        }
+    }
+}
+
+/// Response future for [`CyclesAccountingWithRetriesService`].
+#[pin_project]
+pub struct ResponseFuture<F, ChargingPolicy: CyclesChargingPolicy> {
+    // Declared before the settlement so that, when dropped, the attempt in flight
+    // gives its cycles back to the reserve before the charged cycles are settled.
+    #[pin]
+    response_future: F,
+    settlement: Settlement<ChargingPolicy, CyclesReserve>,
+}
+
+impl<F, ChargingPolicy, Response, Error> Future for ResponseFuture<F, ChargingPolicy>
+where
+    F: Future<Output = Result<Response, Error>>,
+    ChargingPolicy: CyclesChargingPolicy,
+{
+    type Output = Result<Response, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result_fut = this.response_future.poll(cx);
+        if result_fut.is_ready() {
+            this.settlement.settle();
+        }
+        result_fut
+    }
+}
+
+/// HTTPs outcall request together with the reserve of cycles paying for all its attempts.
+#[derive(Clone, Debug)]
+pub struct IcHttpRequestWithCyclesReserve {
+    /// Request to be made.
+    pub request: IcHttpRequest,
+    reserve: CyclesReserve,
+    retry_attempt: u32,
+}
+
+impl MaxResponseBytesRequestExtension for IcHttpRequestWithCyclesReserve {
+    fn set_max_response_bytes(&mut self, value: u64) {
+        self.request.set_max_response_bytes(value);
+    }
+
+    fn get_max_response_bytes(&self) -> Option<u64> {
+        self.request.get_max_response_bytes()
+    }
+}
+
+impl RetryAttemptRequestExtension for IcHttpRequestWithCyclesReserve {
+    fn set_retry_attempt(&mut self, value: u32) {
+        self.retry_attempt = value;
+    }
+
+    fn get_retry_attempt(&self) -> Option<u32> {
+        Some(self.retry_attempt)
+    }
+}
+
+/// Service paying for each attempt from the cycles reserve of the request.
+///
+/// See [`CyclesAccountingWithRetries`].
+#[derive(Clone, Debug)]
+pub struct PayFromCyclesReserve<S, Estimator> {
+    inner: S,
+    cycles_cost_estimator: Estimator,
+}
+
+impl<S, Estimator> Service<IcHttpRequestWithCyclesReserve> for PayFromCyclesReserve<S, Estimator>
+where
+    S: Service<IcHttpRequestWithCycles>,
+    Estimator: CyclesEstimator,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = PayFromCyclesReserveFuture<S::Future>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, request: IcHttpRequestWithCyclesReserve) -> Self::Future {
+        let IcHttpRequestWithCyclesReserve {
+            request,
+            reserve,
+            retry_attempt,
+        } = request;
+        let cycles = self.cycles_cost_estimator.cost_of_http_request(&request);
+        reserve.attach(cycles);
+        let request = IcHttpRequestWithCycles {
+            retry_attempt,
+            ..IcHttpRequestWithCycles::new(request, cycles)
+        };
+        PayFromCyclesReserveFuture {
+            refund: AttemptRefund {
+                reserve,
+                cycles,
+                usage: request.usage.clone(),
+                refunded: false,
+            },
+            response_future: self.inner.call(request),
+        }
+    }
+}
+
+/// Response future for [`PayFromCyclesReserve`].
+#[pin_project]
+pub struct PayFromCyclesReserveFuture<F> {
+    #[pin]
+    response_future: F,
+    refund: AttemptRefund,
+}
+
+impl<F, Response, Error> Future for PayFromCyclesReserveFuture<F>
+where
+    F: Future<Output = Result<Response, Error>>,
+{
+    type Output = Result<Response, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result_fut = this.response_future.poll(cx);
+        if result_fut.is_ready() {
+            this.refund.refund();
+        }
+        result_fut
+    }
+}
+
+/// Cycles of a single attempt not spent by the HTTPs outcall, given back to the reserve exactly once:
+/// when the response is ready or, if the response future is dropped before that, on drop.
+///
+/// All cycles of an attempt that never reached a [`Client`](crate::Client) go back to the reserve.
+struct AttemptRefund {
+    reserve: CyclesReserve,
+    cycles: u128,
+    usage: CyclesUsageRecorder,
+    refunded: bool,
+}
+
+impl AttemptRefund {
+    fn refund(&mut self) {
+        if !std::mem::replace(&mut self.refunded, true) {
+            let refunded = self.usage.get().map_or(self.cycles, |usage| usage.refunded);
+            self.reserve.refund(refunded);
+        }
+    }
+}
+
+impl Drop for AttemptRefund {
+    fn drop(&mut self) {
+        self.refund();
+    }
+}
+
+/// Cycles reserved for all attempts of a single request, shared by all attempts.
+#[derive(Clone, Debug)]
+struct CyclesReserve(Arc<Mutex<CyclesUsage>>);
+
+impl CyclesReserve {
+    fn new(reserved_cycles: u128) -> Self {
+        Self(Arc::new(Mutex::new(CyclesUsage {
+            attached: reserved_cycles,
+            refunded: reserved_cycles,
+        })))
+    }
+
+    fn attach(&self, cycles: u128) {
+        let mut usage = self.0.lock().unwrap();
+        usage.refunded = usage.refunded.saturating_sub(cycles);
+    }
+
+    fn refund(&self, cycles: u128) {
+        let mut usage = self.0.lock().unwrap();
+        usage.refunded = usage.refunded.saturating_add(cycles).min(usage.attached);
+    }
+
+    fn usage(&self) -> CyclesUsage {
+        *self.0.lock().unwrap()
+    }
+}
+
+impl SettlementUsage for CyclesReserve {
+    fn settlement_usage(&self) -> Option<CyclesUsage> {
+        Some(self.usage())
+    }
+}
+
+/// Sum of the cycles cost of all attempts that the retry policy could make for the given request.
+pub(super) fn worst_case_cycles_cost<E: CyclesEstimator, P: MaxResponseBytesRetrySchedule>(
+    estimator: &E,
+    retry_policy: &P,
+    request: &IcHttpRequest,
+) -> u128 {
+    let mut request = request.clone();
+    let mut total_cost = estimator.cost_of_http_request(&request);
+    let mut num_attempts = 1;
+    while let Some(max_response_bytes) = request.get_max_response_bytes().and_then(|previous| {
+        retry_policy
+            .retry_max_response_bytes(previous, num_attempts)
+            .filter(|next| *next > previous)
+    }) {
+        request.set_max_response_bytes(max_response_bytes);
+        total_cost = total_cost.saturating_add(estimator.cost_of_http_request(&request));
+        num_attempts += 1;
+    }
+    total_cost
+}
@@ -1,19 +1,93 @@
-use crate::cycles::CyclesCostEstimator;
-use ic_cdk::api::management_canister::http_request::CanisterHttpRequestArgument;
+use crate::cycles::retry::worst_case_cycles_cost;
+use crate::cycles::{
+    ChargeCaller, ChargeCallerError, ChargeMyself, ChargeMyselfError, ChargeMyselfWithReserve,
+    ChargePrepaidBalance, CyclesAccounting, CyclesAccountingWithRetries, CyclesChargingPolicy,
+    CyclesCostBreakdown, CyclesCostEstimator, CyclesEstimator, CyclesPricing, FixedCyclesCost,
+    InMemoryPrepaidBalances, PrepaidBalanceError, PrepaidBalances, PrepaidCharge, WithSafetyMargin,
+};
+use crate::retry::{DoubleMaxResponseBytes, GrowMaxResponseBytes};
+use crate::time::MockClock;
+use crate::{
+    CyclesUsage, IcError, IcHttpRequestWithCycles, IcHttpResponseWithCycles,
+    IsReplicatedRequestExtension, MaxResponseBytesRequestExtension,
+};
+use candid::Principal;
+use futures_util::{future, FutureExt};
+use ic_cdk::management_canister::{
+    HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse,
+};
+use ic_error_types::RejectCode;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::convert::Infallible;
+use std::rc::Rc;
+use std::sync::mpsc;
+use std::time::Duration;
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
 
 #[test]
 fn test_http_request_fee_components() {
     // Assert the calculation matches the cost table at
     // https://internetcomputer.org/docs/current/developer-docs/gas-cost#cycles-price-breakdown
+    let pricing = CyclesPricing::V1;
+    assert_eq!(pricing.base_fee(13), 49_140_000);
+    assert_eq!(pricing.request_fee(13, 1), 5_200);
+    assert_eq!(pricing.response_fee(13, 1), 10_400);
+
+    assert_eq!(pricing.base_fee(34), 171_360_000);
+    assert_eq!(pricing.request_fee(34, 1), 13_600);
+    assert_eq!(pricing.response_fee(34, 1), 27_200);
+}
+
+#[test]
+fn should_break_down_cost() {
     let estimator = CyclesCostEstimator::new(13);
-    assert_eq!(estimator.base_fee(), 49_140_000);
-    assert_eq!(estimator.request_fee(1), 5_200);
-    assert_eq!(estimator.response_fee(1), 10_400);
+    let request = request(123, 356, 4567890);
+
+    let breakdown = estimator.cost_breakdown_of_http_request(&request);
+
+    assert_eq!(
+        breakdown,
+        CyclesCostBreakdown {
+            base_fee: 49_140_000,
+            request_fee: 5_200 * (123 + 356),
+            response_fee: 10_400 * 4567890,
+        }
+    );
+    assert_eq!(breakdown.total(), estimator.cost_of_http_request(&request));
+}
 
+#[test]
+fn should_price_non_replicated_request_as_single_node() {
     let estimator = CyclesCostEstimator::new(34);
-    assert_eq!(estimator.base_fee(), 171_360_000);
-    assert_eq!(estimator.request_fee(1), 13_600);
-    assert_eq!(estimator.response_fee(1), 27_200);
+    let replicated_request = request(123, 356, 4567890);
+    let non_replicated_request = request(123, 356, 4567890).replicated(false);
+
+    assert_eq!(
+        estimator.cost_of_http_request(&replicated_request),
+        estimator.cost_of_http_request(&replicated_request.clone().replicated(true))
+    );
+    assert_eq!(
+        estimator.cost_of_http_request(&non_replicated_request),
+        CyclesCostEstimator::new(1).cost_of_http_request(&replicated_request)
+    );
+}
+
+#[test]
+fn should_use_given_pricing() {
+    let request = request(123, 356, 4567890);
+    let free = CyclesCostEstimator::new_with_pricing(34, CyclesPricing::new(0, 0, 0, 0));
+    assert_eq!(free.cost_of_http_request(&request), 0);
+
+    let only_response = CyclesCostEstimator::new_with_pricing(34, CyclesPricing::new(0, 0, 0, 1));
+    assert_eq!(
+        only_response.cost_breakdown_of_http_request(&request),
+        CyclesCostBreakdown {
+            base_fee: 0,
+            request_fee: 0,
+            response_fee: 34 * 4567890,
+        }
+    );
 }
 
 #[test]
@@ -43,19 +117,734 @@ fn test_candid_rpc_cost() {
     );
 }
 
+#[test]
+fn should_attach_cycles_given_by_estimator() {
+    let request = request(123, 356, 4567890);
+    let estimator = CyclesCostEstimator::new(34);
+    let expected_cycles = estimator.cost_of_http_request(&request);
+
+    let accounting = CyclesAccounting::new(34, ChargeMyself::default());
+    assert_eq!(
+        accounting.charge(request.clone()),
+        Ok((
+            IcHttpRequestWithCycles {
+                request: request.clone(),
+                cycles: expected_cycles,
+                ..Default::default()
+            },
+            0
+        ))
+    );
+
+    let accounting =
+        CyclesAccounting::new_with_estimator(FixedCyclesCost(42), ChargeMyself::default());
+    assert_eq!(
+        accounting.charge(request.clone()),
+        Ok((
+            IcHttpRequestWithCycles {
+                request: request.clone(),
+                cycles: 42,
+                ..Default::default()
+            },
+            0
+        ))
+    );
+
+    let accounting = CyclesAccounting::new_with_estimator(
+        WithSafetyMargin::new(estimator, 50),
+        ChargeMyself::default(),
+    );
+    assert_eq!(
+        accounting.charge(request.clone()),
+        Ok((
+            IcHttpRequestWithCycles {
+                request,
+                cycles: expected_cycles + expected_cycles / 2,
+                ..Default::default()
+            },
+            0
+        ))
+    );
+}
+
+#[test]
+fn should_add_safety_margin() {
+    let request = IcHttpRequest::default();
+    for (margin_percent, expected_cost) in [
+        (0, 1_000),
+        (1, 1_010),
+        (25, 1_250),
+        (100, 2_000),
+        (250, 3_500),
+    ] {
+        let estimator = WithSafetyMargin::new(FixedCyclesCost(1_000), margin_percent);
+        assert_eq!(estimator.cost_of_http_request(&request), expected_cost);
+    }
+
+    let estimator = WithSafetyMargin::new(FixedCyclesCost(u128::MAX), 10);
+    assert_eq!(estimator.cost_of_http_request(&request), u128::MAX);
+}
+
+#[tokio::test]
+async fn should_settle_charged_cycles_with_cycles_usage() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccounting::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            let cycles = CyclesUsage {
+                attached: request.cycles,
+                refunded: 400,
+            };
+            request.usage.record(cycles);
+            Ok::<_, BoxError>(IcHttpResponseWithCycles {
+                response: Default::default(),
+                cycles,
+            })
+        });
+
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default())
+        .await
+        .unwrap();
+
+    assert_eq!(response.cycles.consumed(), 600);
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 1_000,
+                refunded: 400
+            })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_settle_charged_cycles_with_cycles_usage_on_error() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccounting::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 1_000,
+            });
+            Err::<IcHttpResponse, BoxError>(BoxError::from("outcall failed"))
+        });
+
+    let result = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default())
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 1_000,
+                refunded: 1_000
+            })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_settle_charged_cycles_without_cycles_usage_when_unknown() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccounting::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|_request: IcHttpRequestWithCycles| async move {
+            Err::<IcHttpResponse, BoxError>(BoxError::from("request rejected"))
+        });
+
+    let result = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default())
+        .await;
+
+    assert!(result.is_err());
+    assert_eq!(policy.settled(), vec![(1_500, None)]);
+}
+
+#[tokio::test]
+async fn should_settle_charged_cycles_when_response_future_dropped() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccounting::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 0,
+            });
+            future::pending::<Result<IcHttpResponse, BoxError>>().await
+        });
+
+    let response_future = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default());
+    assert!(policy.settled().is_empty());
+
+    assert!(response_future.now_or_never().is_none());
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 1_000,
+                refunded: 0
+            })
+        )]
+    );
+}
+
+#[test]
+fn should_check_cycles_available_to_charge_caller() {
+    assert_eq!(
+        ChargeCaller::<fn(&IcHttpRequest, u128) -> u128>::check_cycles_available(1_000, 1_000),
+        Ok(())
+    );
+    assert_eq!(
+        ChargeCaller::<fn(&IcHttpRequest, u128) -> u128>::check_cycles_available(1_001, 1_000),
+        Err(ChargeCallerError::InsufficientCyclesError {
+            expected: 1_001,
+            received: 1_000
+        })
+    );
+}
+
+#[test]
+fn should_accept_spent_cycles_from_caller_with_refund() {
+    let usage = Some(CyclesUsage {
+        attached: 600,
+        refunded: 100,
+    });
+
+    let policy = ChargeCaller::new(|_request, cost| cost).with_refund();
+    assert_eq!(policy.cycles_to_accept(600, usage), 500);
+    assert_eq!(policy.cycles_to_accept(600, None), 0);
+
+    let policy = ChargeCaller::new(|_request, cost| cost);
+    assert_eq!(policy.cycles_to_accept(600, usage), 0);
+}
+
+#[test]
+fn should_estimate_worst_case_of_retries() {
+    let estimator = FixedCyclesCost(1_000);
+
+    // max_response_bytes: 0, 2048, 4096, ..., 1024 << 10, 2_000_000
+    let request = IcHttpRequest::default().max_response_bytes(0);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &DoubleMaxResponseBytes, &request),
+        12_000
+    );
+
+    let request = IcHttpRequest::default().max_response_bytes(1_000_000);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &DoubleMaxResponseBytes, &request),
+        2_000
+    );
+
+    let request = IcHttpRequest::default().max_response_bytes(2_000_000);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &DoubleMaxResponseBytes, &request),
+        1_000
+    );
+
+    let request = IcHttpRequest::default();
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &DoubleMaxResponseBytes, &request),
+        1_000
+    );
+
+    let estimator = CyclesCostEstimator::new(34);
+    let other_request = self::request(123, 356, 1_000_000);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &DoubleMaxResponseBytes, &other_request),
+        estimator.cost_of_http_request(&other_request)
+            + estimator.cost_of_http_request(&other_request.clone().max_response_bytes(2_000_000))
+    );
+
+    let estimator = FixedCyclesCost(1_000);
+    let request = IcHttpRequest::default().max_response_bytes(0);
+    // max_response_bytes: 0, 1_500_000, 2_000_000
+    let retry_policy = GrowMaxResponseBytes::default().with_steps([1_500_000]);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &retry_policy, &request),
+        3_000
+    );
+    // max_response_bytes: 0, 4096, 65536
+    let retry_policy = GrowMaxResponseBytes::default()
+        .with_factor(16)
+        .with_floor(256)
+        .with_max_attempts(3);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &retry_policy, &request),
+        3_000
+    );
+    // max_response_bytes: 0, 4096, 2_000_000 since the last retry may use the reported response size.
+    let retry_policy = retry_policy.with_reported_size_parser(|_message| Some(1_500_000));
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &retry_policy, &request),
+        3_000
+    );
+    let estimator = CyclesCostEstimator::new(34);
+    assert_eq!(
+        worst_case_cycles_cost(&estimator, &retry_policy, &request),
+        estimator.cost_of_http_request(&request)
+            + estimator.cost_of_http_request(&request.clone().max_response_bytes(4_096))
+            + estimator.cost_of_http_request(&request.clone().max_response_bytes(2_000_000))
+    );
+}
+
+#[tokio::test]
+async fn should_pay_retries_from_cycles_reserve() {
+    let (requests_tx, requests_rx) = mpsc::channel::<IcHttpRequestWithCycles>();
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccountingWithRetries::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(move |request: IcHttpRequestWithCycles| {
+            let requests_tx = requests_tx.clone();
+            async move {
+                requests_tx.send(request.clone()).unwrap();
+                match request.get_max_response_bytes() {
+                    Some(max_response_bytes) if max_response_bytes >= 8192 => {
+                        let cycles = CyclesUsage {
+                            attached: request.cycles,
+                            refunded: 300,
+                        };
+                        request.usage.record(cycles);
+                        Ok(IcHttpResponseWithCycles {
+                            response: Default::default(),
+                            cycles,
+                        })
+                    }
+                    max_response_bytes => {
+                        request.usage.record(CyclesUsage {
+                            attached: request.cycles,
+                            refunded: 100,
+                        });
+                        Err(BoxError::from(IcError {
+                            code: RejectCode::SysFatal,
+                            message: format!(
+                                "Http body exceeds size limit of {} bytes",
+                                max_response_bytes.unwrap_or_default()
+                            ),
+                        }))
+                    }
+                }
+            }
+        });
+
+    let response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default().max_response_bytes(0))
+        .await
+        .unwrap();
+
+    assert_eq!(response.cycles.consumed(), 700);
+    assert_eq!(
+        requests_rx
+            .try_iter()
+            .map(|r| (r.get_max_response_bytes().unwrap(), r.cycles))
+            .collect::<Vec<_>>(),
+        vec![(0, 1_000), (2048, 1_000), (4096, 1_000), (8192, 1_000)]
+    );
+    // 12 attempts were reserved, only 4 were made and all of them were partially refunded.
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 12_000,
+                refunded: 8_600
+            })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_pay_retries_of_custom_retry_policy_from_cycles_reserve() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(
+            CyclesAccountingWithRetries::new_with_estimator(FixedCyclesCost(1_000), policy.clone())
+                .with_retry_policy(GrowMaxResponseBytes::default().with_steps([1_500_000])),
+        )
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 1_000,
+            });
+            Err::<IcHttpResponse, _>(BoxError::from(IcError {
+                code: RejectCode::SysTransient,
+                message: "Connection refused".to_string(),
+            }))
+        });
+
+    let result = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default().max_response_bytes(0))
+        .await;
+
+    assert!(result.is_err());
+    // 3 attempts were reserved (0, 1_500_000, 2_000_000), a single one was made and fully refunded.
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 3_000,
+                refunded: 3_000
+            })
+        )]
+    );
+}
+
+#[tokio::test]
+async fn should_settle_cycles_reserve_when_response_future_dropped() {
+    let policy = RecordingChargingPolicy::new(1_500);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccountingWithRetries::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 0,
+            });
+            future::pending::<Result<IcHttpResponse, BoxError>>().await
+        });
+
+    let response_future = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default().max_response_bytes(0));
+
+    assert!(response_future.now_or_never().is_none());
+    // 12 attempts were reserved, only the first one was in flight when the future was dropped.
+    assert_eq!(
+        policy.settled(),
+        vec![(
+            1_500,
+            Some(CyclesUsage {
+                attached: 12_000,
+                refunded: 11_000
+            })
+        )]
+    );
+}
+
+#[test]
+fn should_keep_cycles_reserve() {
+    let policy = ChargeMyselfWithReserve::new(1_000);
+
+    assert_eq!(policy.try_spend(1_500, 500), Ok(()));
+    assert_eq!(policy.try_spend(1_500, 500), Ok(()));
+    assert_eq!(
+        policy.try_spend(1_500, 501),
+        Err(ChargeMyselfError::ReserveExceeded {
+            balance: 1_500,
+            cost: 501,
+            reserve: 1_000
+        })
+    );
+    assert_eq!(
+        policy.try_spend(500, 0),
+        Err(ChargeMyselfError::ReserveExceeded {
+            balance: 500,
+            cost: 0,
+            reserve: 1_000
+        })
+    );
+}
+
+#[test]
+fn should_limit_spending_within_rolling_window() {
+    let clock = MockClock::default();
+    let policy = ChargeMyselfWithReserve::new(0)
+        .with_spending_limit(1_000, Duration::from_secs(10))
+        .with_clock(clock.clone());
+    let balance = u128::MAX;
+
+    assert_eq!(policy.try_spend(balance, 600), Ok(()));
+    clock.advance(Duration::from_secs(5));
+    assert_eq!(policy.clone().try_spend(balance, 400), Ok(()));
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(
+        policy.try_spend(balance, 1),
+        Err(ChargeMyselfError::SpendingLimitExceeded {
+            spent: 1_000,
+            cost: 1,
+            limit: 1_000
+        })
+    );
+    // cycles spent at time 0 are out of the window
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(policy.try_spend(balance, 600), Ok(()));
+    clock.advance(Duration::from_secs(4));
+    assert_eq!(
+        policy.try_spend(balance, 1),
+        Err(ChargeMyselfError::SpendingLimitExceeded {
+            spent: 1_000,
+            cost: 1,
+            limit: 1_000
+        })
+    );
+    clock.advance(Duration::from_secs(1));
+    assert_eq!(policy.try_spend(balance, 400), Ok(()));
+}
+
+#[test]
+fn should_deposit_and_withdraw_prepaid_balance() {
+    let balances = InMemoryPrepaidBalances::default();
+    let policy = ChargePrepaidBalance::new(balances.clone(), |_request, cost| cost);
+    let alice = Principal::from_slice(&[1]);
+    let bob = Principal::from_slice(&[2]);
+
+    assert_eq!(policy.balance(&alice), 0);
+    assert_eq!(policy.deposit(alice, 1_000), 1_000);
+    assert_eq!(policy.deposit(alice, 500), 1_500);
+    assert_eq!(policy.balance(&alice), 1_500);
+    assert_eq!(policy.balance(&bob), 0);
+
+    assert_eq!(policy.withdraw(alice, 1_000), Ok(500));
+    assert_eq!(
+        policy.withdraw(alice, 501),
+        Err(PrepaidBalanceError::InsufficientBalance {
+            expected: 501,
+            balance: 500
+        })
+    );
+    assert_eq!(
+        policy.withdraw(bob, 1),
+        Err(PrepaidBalanceError::InsufficientBalance {
+            expected: 1,
+            balance: 0
+        })
+    );
+    assert_eq!(policy.withdraw(alice, 500), Ok(0));
+    assert_eq!(balances.snapshot(), BTreeMap::new());
+}
+
+#[test]
+fn should_restore_prepaid_balances_from_snapshot() {
+    let alice = Principal::from_slice(&[1]);
+    let balances = InMemoryPrepaidBalances::default();
+    balances.set_balance(alice, 1_000);
+
+    let restored = InMemoryPrepaidBalances::from(balances.snapshot());
+
+    assert_eq!(restored.balance(&alice), 1_000);
+    assert_eq!(restored.snapshot(), BTreeMap::from([(alice, 1_000)]));
+}
+
+#[test]
+fn should_refund_unspent_cycles_to_prepaid_balance() {
+    let policy = ChargePrepaidBalance::new(InMemoryPrepaidBalances::default(), |_request, cost| {
+        cost + 100
+    });
+    let alice = Principal::from_slice(&[1]);
+    let charge = PrepaidCharge {
+        principal: alice,
+        cycles: 1_100,
+    };
+    policy.deposit(alice, 10_000);
+    policy.withdraw(alice, 1_100).unwrap();
+
+    policy.refund(
+        charge,
+        Some(CyclesUsage {
+            attached: 1_000,
+            refunded: 400,
+        }),
+    );
+    assert_eq!(policy.balance(&alice), 9_300);
+
+    // refunds never exceed the charged amount
+    policy.withdraw(alice, 1_100).unwrap();
+    policy.refund(
+        charge,
+        Some(CyclesUsage {
+            attached: 2_000,
+            refunded: 2_000,
+        }),
+    );
+    assert_eq!(policy.balance(&alice), 9_300);
+
+    // the whole charged amount is refunded when the HTTPs outcall was not made
+    policy.withdraw(alice, 1_100).unwrap();
+    policy.refund(charge, None);
+    assert_eq!(policy.balance(&alice), 9_300);
+}
+
+#[tokio::test]
+async fn should_restore_prepaid_balance_when_response_future_dropped() {
+    let policy =
+        ChargePrepaidBalance::new(InMemoryPrepaidBalances::default(), |_request, cost| cost)
+            .with_caller(|| Principal::from_slice(&[1]));
+    let alice = Principal::from_slice(&[1]);
+    policy.deposit(alice, 100_000);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccounting::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|_request: IcHttpRequestWithCycles| {
+            future::pending::<Result<IcHttpResponse, BoxError>>()
+        });
+
+    let response_future = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default());
+    assert_eq!(policy.balance(&alice), 99_000);
+
+    assert!(response_future.now_or_never().is_none());
+    assert_eq!(policy.balance(&alice), 100_000);
+}
+
+#[tokio::test]
+async fn should_restore_unspent_prepaid_balance_when_retries_dropped() {
+    let policy =
+        ChargePrepaidBalance::new(InMemoryPrepaidBalances::default(), |_request, cost| cost)
+            .with_caller(|| Principal::from_slice(&[1]));
+    let alice = Principal::from_slice(&[1]);
+    policy.deposit(alice, 100_000);
+    let mut service = ServiceBuilder::new()
+        .layer(CyclesAccountingWithRetries::new_with_estimator(
+            FixedCyclesCost(1_000),
+            policy.clone(),
+        ))
+        .service_fn(|request: IcHttpRequestWithCycles| async move {
+            request.usage.record(CyclesUsage {
+                attached: request.cycles,
+                refunded: 0,
+            });
+            future::pending::<Result<IcHttpResponse, BoxError>>().await
+        });
+
+    let response_future = service
+        .ready()
+        .await
+        .unwrap()
+        .call(IcHttpRequest::default().max_response_bytes(0));
+    // worst case of 12 attempts
+    assert_eq!(policy.balance(&alice), 88_000);
+
+    assert!(response_future.now_or_never().is_none());
+    // only the attempt in flight is kept
+    assert_eq!(policy.balance(&alice), 99_000);
+}
+
+#[cfg(feature = "stable")]
+#[test]
+fn should_keep_prepaid_balances_in_stable_memory() {
+    use crate::cycles::StablePrepaidBalances;
+    use ic_stable_structures::VectorMemory;
+
+    let memory = VectorMemory::default();
+    let alice = Principal::from_slice(&[1]);
+    let bob = Principal::from_slice(&[2]);
+    let policy = ChargePrepaidBalance::new(
+        StablePrepaidBalances::init(memory.clone()),
+        |_request, cost| cost,
+    );
+    policy.deposit(alice, 1_000);
+    policy.deposit(bob, 500);
+    assert_eq!(policy.withdraw(bob, 500), Ok(0));
+
+    let restored = StablePrepaidBalances::init(memory);
+
+    assert_eq!(restored.balance(&alice), 1_000);
+    assert_eq!(restored.balance(&bob), 0);
+}
+
+type Settlement = (u128, Option<CyclesUsage>);
+
+#[derive(Clone)]
+struct RecordingChargingPolicy {
+    cycles_to_charge: u128,
+    settled: Rc<RefCell<Vec<Settlement>>>,
+}
+
+impl RecordingChargingPolicy {
+    fn new(cycles_to_charge: u128) -> Self {
+        Self {
+            cycles_to_charge,
+            settled: Rc::new(RefCell::new(Vec::new())),
+        }
+    }
+
+    fn settled(&self) -> Vec<Settlement> {
+        self.settled.borrow().clone()
+    }
+}
+
+impl CyclesChargingPolicy for RecordingChargingPolicy {
+    type Error = Infallible;
+    type Charge = u128;
+
+    fn charge_cycles(
+        &self,
+        _request: &IcHttpRequest,
+        _request_cycles_cost: u128,
+    ) -> Result<u128, Self::Error> {
+        Ok(self.cycles_to_charge)
+    }
+
+    fn settle_cycles(&self, charged_cycles: u128, usage: Option<CyclesUsage>) {
+        self.settled.borrow_mut().push((charged_cycles, usage));
+    }
+}
+
 fn request(
     payload_body_bytes: u32,
     extra_payload_bytes: u32,
     max_response_bytes: u64,
-) -> CanisterHttpRequestArgument {
+) -> IcHttpRequest {
     let body = Some(vec![42_u8; payload_body_bytes as usize]);
     let max_response_bytes = Some(max_response_bytes);
-    CanisterHttpRequestArgument {
+    IcHttpRequest {
         url: "a".repeat(extra_payload_bytes as usize),
         max_response_bytes,
         method: Default::default(),
         headers: vec![],
         body,
         transform: None,
+        is_replicated: None,
     }
 }
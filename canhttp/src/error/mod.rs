@@ -0,0 +1,265 @@
+#[cfg(all(test, feature = "http"))]
+mod tests;
+
+use crate::cycles::{ChargeCallerError, ChargeMyselfError, PrepaidBalanceError};
+#[cfg(feature = "json")]
+use crate::http::json::{
+    ConsistentResponseIdFilterError, JsonRequestConversionError, JsonResponseConversionError,
+};
+#[cfg(feature = "http")]
+use crate::http::{
+    FilterNonSuccessfulHttpResponseError, HttpRequestConversionError, HttpResponseConversionError,
+};
+#[cfg(feature = "multi")]
+use crate::multi::ReductionError;
+use crate::observability::ObservableError;
+use crate::{CyclesQuote, HttpsOutcallError, IcError};
+use ic_error_types::RejectCode;
+use std::convert::Infallible;
+use thiserror::Error;
+
+/// Error that can be returned by any middleware of this crate.
+///
+/// Using this error as the error type of a service stack, e.g. with [`Client::new_with_error`],
+/// avoids erasing the error type with [`BoxError`] and downcasting it afterwards.
+/// Every error of this crate converts into a [`CanHttpError`].
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{
+///     cycles::{ChargeCallerError, ChargeMyself, CyclesAccountingServiceBuilder},
+///     http::HttpConversionLayer,
+///     CanHttpError, Client, HttpsOutcallError,
+/// };
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+///     .layer(HttpConversionLayer)
+///     .cycles_accounting(34, ChargeMyself::default())
+///     .service(Client::new_with_error::<CanHttpError>());
+///
+/// let _ = service.ready().await.unwrap();
+///
+/// // Errors returned by the service can be classified without downcasting, e.g.
+/// let error = CanHttpError::from(ChargeCallerError::InsufficientCyclesError {
+///     expected: 1_000,
+///     received: 0,
+/// });
+/// assert!(error.is_caller_fault());
+/// assert!(error.is_cycles_error());
+/// assert!(!error.is_transient());
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`Client::new_with_error`]: crate::Client::new_with_error
+/// [`BoxError`]: tower::BoxError
+#[derive(Error, Clone, Debug)]
+#[non_exhaustive]
+pub enum CanHttpError {
+    /// Error returned by the Internet Computer.
+    #[error(transparent)]
+    Ic(#[from] IcError),
+    /// Error returned by the [`CyclesAccounting`](crate::cycles::CyclesAccounting) middleware
+    /// when charging the caller.
+    #[error(transparent)]
+    ChargeCaller(#[from] ChargeCallerError),
+    /// Error returned by the [`ChargeMyselfWithReserve`](crate::cycles::ChargeMyselfWithReserve)
+    /// cycles charging policy.
+    #[error(transparent)]
+    ChargeMyself(#[from] ChargeMyselfError),
+    /// Error returned by the [`ChargePrepaidBalance`](crate::cycles::ChargePrepaidBalance)
+    /// cycles charging policy.
+    #[error(transparent)]
+    PrepaidBalance(#[from] PrepaidBalanceError),
+    /// Error returned by the [`DryRunClient`](crate::DryRunClient) instead of issuing an HTTPs outcall.
+    #[error(transparent)]
+    DryRun(#[from] CyclesQuote),
+    /// Error returned when the results of multiple HTTPs outcalls could not be reduced
+    /// to a single value because they are inconsistent with each other, see [`ReductionError`].
+    ///
+    /// The inconsistent results are described by their number and the kinds of their errors
+    /// (see [`ObservableError::error_kind`]).
+    #[cfg(feature = "multi")]
+    #[error("Inconsistent results: {0}")]
+    Reduction(String),
+    /// Error returned when converting requests with `HttpRequestConverter`.
+    #[cfg(feature = "http")]
+    #[error(transparent)]
+    HttpRequestConversion(#[from] HttpRequestConversionError),
+    /// Error returned when converting responses with `HttpResponseConverter`.
+    #[cfg(feature = "http")]
+    #[error(transparent)]
+    HttpResponseConversion(#[from] HttpResponseConversionError),
+    /// Error returned when filtering responses with `FilterNonSuccessfulHttpResponse`.
+    #[cfg(feature = "http")]
+    #[error(transparent)]
+    UnsuccessfulHttpResponse(#[from] FilterNonSuccessfulHttpResponseError<Vec<u8>>),
+    /// Error returned when converting requests with `JsonRequestConverter`.
+    #[cfg(feature = "json")]
+    #[error(transparent)]
+    JsonRequestConversion(#[from] JsonRequestConversionError),
+    /// Error returned when converting responses with `JsonResponseConverter`.
+    #[cfg(feature = "json")]
+    #[error(transparent)]
+    JsonResponseConversion(#[from] JsonResponseConversionError),
+    /// Error returned when filtering responses with `ConsistentJsonRpcIdFilter`.
+    #[cfg(feature = "json")]
+    #[error(transparent)]
+    ConsistentResponseIdFilter(#[from] ConsistentResponseIdFilterError),
+}
+
+impl From<Infallible> for CanHttpError {
+    fn from(value: Infallible) -> Self {
+        match value {}
+    }
+}
+
+/// A [`ReductionError::ConsistentError`] is converted into the error shared by all results.
+///
+/// Inconsistent results are only described by their number and the kinds of their errors,
+/// since the results themselves may contain sensitive data, such as headers or bodies.
+#[cfg(feature = "multi")]
+impl<K, V, E: Into<CanHttpError>> From<ReductionError<K, V, E>> for CanHttpError {
+    fn from(error: ReductionError<K, V, E>) -> Self {
+        match error {
+            ReductionError::ConsistentError(error) => error.into(),
+            ReductionError::InconsistentResults(results) => {
+                let num_results = results.len();
+                let error_kinds: Vec<_> = results
+                    .into_iter()
+                    .filter_map(|(_key, result)| result.err())
+                    .map(|error| error.into().error_kind())
+                    .collect();
+                CanHttpError::Reduction(format!(
+                    "{num_results} results, errors: [{}]",
+                    error_kinds.join(", ")
+                ))
+            }
+        }
+    }
+}
+
+impl CanHttpError {
+    /// Determines whether the error is due to the request being invalid,
+    /// e.g. it could not be converted, it was rejected by the Internet Computer
+    /// or the server responded with a `4xx` status code.
+    ///
+    /// Retrying the same request will not help.
+    pub fn is_client_error(&self) -> bool {
+        match self {
+            CanHttpError::Ic(error) => matches!(
+                error.code,
+                RejectCode::DestinationInvalid | RejectCode::CanisterReject
+            ),
+            #[cfg(feature = "http")]
+            CanHttpError::HttpRequestConversion(_) => true,
+            #[cfg(feature = "http")]
+            CanHttpError::UnsuccessfulHttpResponse(
+                FilterNonSuccessfulHttpResponseError::UnsuccessfulResponse(response),
+            ) => response.status().is_client_error(),
+            #[cfg(feature = "json")]
+            CanHttpError::JsonRequestConversion(_) => true,
+            _ => false,
+        }
+    }
+
+    /// Determines whether the error is the fault of the caller of the canister method,
+    /// e.g. the caller did not attach enough cycles or does not have a sufficient prepaid balance.
+    pub fn is_caller_fault(&self) -> bool {
+        matches!(
+            self,
+            CanHttpError::ChargeCaller(_) | CanHttpError::PrepaidBalance(_)
+        )
+    }
+
+    /// Determines whether the error is due to cycles, e.g. the caller or the canister
+    /// cannot pay for the HTTPs outcall, or not enough cycles were attached to it.
+    ///
+    /// Errors returned by the Internet Computer are recognized by their reject message:
+    /// * `http_request request sent with {attached} cycles, but {required} cycles are required.`,
+    ///   when the management canister rejects an HTTPs outcall with too few cycles attached;
+    /// * `Canister {id} is out of cycles...`, when the canister cannot pay for the call;
+    /// * `insufficient liquid cycles balance...` (with code [`RejectCode::SysFatal`]), when [`Client`](crate::Client)
+    ///   cannot attach the cycles to the HTTPs outcall.
+    pub fn is_cycles_error(&self) -> bool {
+        match self {
+            CanHttpError::Ic(error) => is_insufficient_cycles_reject(error),
+            CanHttpError::ChargeCaller(_)
+            | CanHttpError::ChargeMyself(_)
+            | CanHttpError::PrepaidBalance(_) => true,
+            _ => false,
+        }
+    }
+}
+
+fn is_insufficient_cycles_reject(error: &IcError) -> bool {
+    let message = error.message.as_str();
+    let too_few_cycles_attached = error.code == RejectCode::CanisterReject
+        && message.starts_with("http_request request sent with ")
+        && message.contains(" cycles, but ")
+        && message.ends_with(" cycles are required.");
+    let out_of_cycles = message.starts_with("Canister ") && message.contains(" is out of cycles");
+    // Returned by `Client` when the canister cannot afford the cycles to attach.
+    let insufficient_liquid_balance = error.code == RejectCode::SysFatal
+        && message.starts_with("insufficient liquid cycles balance");
+    too_few_cycles_attached || out_of_cycles || insufficient_liquid_balance
+}
+
+impl HttpsOutcallError for CanHttpError {
+    fn is_response_too_large(&self) -> bool {
+        match self {
+            CanHttpError::Ic(error) => error.is_response_too_large(),
+            _ => false,
+        }
+    }
+
+    /// Determines whether the error is transient, i.e. the Internet Computer returned a
+    /// transient error or the server responded with status code `429`, `502`, `503` or `504`.
+    fn is_transient(&self) -> bool {
+        match self {
+            CanHttpError::Ic(error) => error.is_transient(),
+            #[cfg(feature = "http")]
+            CanHttpError::UnsuccessfulHttpResponse(
+                FilterNonSuccessfulHttpResponseError::UnsuccessfulResponse(response),
+            ) => crate::retry::RetryableResponse::is_transient_failure(response),
+            _ => false,
+        }
+    }
+
+    fn reject_message(&self) -> Option<&str> {
+        match self {
+            CanHttpError::Ic(error) => error.reject_message(),
+            _ => None,
+        }
+    }
+}
+
+impl ObservableError for CanHttpError {
+    fn error_kind(&self) -> String {
+        match self {
+            CanHttpError::Ic(error) => error.error_kind(),
+            CanHttpError::ChargeCaller(_)
+            | CanHttpError::ChargeMyself(_)
+            | CanHttpError::PrepaidBalance(_) => "cycles".to_string(),
+            CanHttpError::DryRun(_) => "dry_run".to_string(),
+            #[cfg(feature = "multi")]
+            CanHttpError::Reduction(_) => "reduction".to_string(),
+            #[cfg(feature = "http")]
+            CanHttpError::HttpRequestConversion(_) | CanHttpError::HttpResponseConversion(_) => {
+                "conversion".to_string()
+            }
+            #[cfg(feature = "http")]
+            CanHttpError::UnsuccessfulHttpResponse(error) => error.error_kind(),
+            #[cfg(feature = "json")]
+            CanHttpError::JsonRequestConversion(_) | CanHttpError::JsonResponseConversion(_) => {
+                "conversion".to_string()
+            }
+            #[cfg(feature = "json")]
+            CanHttpError::ConsistentResponseIdFilter(_) => "inconsistent_response_id".to_string(),
+        }
+    }
+}
@@ -0,0 +1,213 @@
+use crate::cycles::{ChargeCallerError, ChargeMyselfError, PrepaidBalanceError};
+use crate::http::{FilterNonSuccessfulHttpResponseError, HttpRequestConversionError};
+use crate::observability::ObservableError;
+use crate::{CanHttpError, CyclesQuote, HttpsOutcallError, IcError};
+use assert_matches::assert_matches;
+use ic_cdk::call::InsufficientLiquidCycleBalance;
+use ic_error_types::RejectCode;
+use tower::BoxError;
+
+#[test]
+fn should_convert_errors() {
+    assert_matches!(
+        CanHttpError::from(ic_error(RejectCode::SysTransient, "busy")),
+        CanHttpError::Ic(_)
+    );
+    assert_matches!(
+        CanHttpError::from(HttpRequestConversionError::UnsupportedHttpMethod(
+            "PUT".to_string()
+        )),
+        CanHttpError::HttpRequestConversion(_)
+    );
+    assert_matches!(
+        CanHttpError::from(ChargeMyselfError::ReserveExceeded {
+            balance: 1,
+            cost: 2,
+            reserve: 3
+        }),
+        CanHttpError::ChargeMyself(_)
+    );
+    assert_matches!(
+        CanHttpError::from(CyclesQuote {
+            request: Default::default(),
+            cycles: 1_000,
+        }),
+        CanHttpError::DryRun(_)
+    );
+}
+
+#[cfg(feature = "multi")]
+#[test]
+fn should_convert_reduction_errors() {
+    use crate::multi::{MultiResults, ReductionError};
+
+    assert_matches!(
+        CanHttpError::from(ReductionError::<u8, u8, IcError>::ConsistentError(
+            ic_error(RejectCode::SysTransient, "busy")
+        )),
+        CanHttpError::Ic(_)
+    );
+
+    let error = CanHttpError::from(ReductionError::<u8, u8, IcError>::InconsistentResults(
+        MultiResults::from_non_empty_iter([
+            (0, Ok(1)),
+            (1, Ok(2)),
+            (2, Err(ic_error(RejectCode::SysTransient, "api_key=secret"))),
+        ]),
+    ));
+    assert_matches!(error, CanHttpError::Reduction(_));
+    assert_eq!(
+        error.to_string(),
+        "Inconsistent results: 3 results, errors: [SysTransient]"
+    );
+    assert_eq!(error.error_kind(), "reduction");
+    assert!(!error.is_transient() && !error.is_cycles_error());
+}
+
+#[test]
+fn should_classify_errors() {
+    struct Classification {
+        transient: bool,
+        client_error: bool,
+        caller_fault: bool,
+        cycles_error: bool,
+    }
+
+    fn classify(error: CanHttpError) -> Classification {
+        Classification {
+            transient: error.is_transient(),
+            client_error: error.is_client_error(),
+            caller_fault: error.is_caller_fault(),
+            cycles_error: error.is_cycles_error(),
+        }
+    }
+
+    let transient = classify(ic_error(RejectCode::SysTransient, "busy").into());
+    assert!(transient.transient && !transient.client_error && !transient.cycles_error);
+
+    let not_enough_cycles = classify(
+        ic_error(
+            RejectCode::CanisterReject,
+            "http_request request sent with 0 cycles, but 1_000 cycles are required.",
+        )
+        .into(),
+    );
+    assert!(not_enough_cycles.client_error && not_enough_cycles.cycles_error);
+    assert!(!not_enough_cycles.transient && !not_enough_cycles.caller_fault);
+
+    let out_of_cycles = classify(
+        ic_error(
+            RejectCode::SysTransient,
+            "Canister aaaaa-aa is out of cycles: please top up the canister with at least 1_000 additional cycles",
+        )
+        .into(),
+    );
+    assert!(out_of_cycles.cycles_error && out_of_cycles.transient);
+
+    let insufficient_liquid_balance = classify(
+        ic_error(
+            RejectCode::SysFatal,
+            &InsufficientLiquidCycleBalance {
+                available: 0,
+                required: 1_000,
+            }
+            .to_string(),
+        )
+        .into(),
+    );
+    assert!(insufficient_liquid_balance.cycles_error && !insufficient_liquid_balance.transient);
+
+    let unrelated_reject = classify(
+        ic_error(
+            RejectCode::CanisterReject,
+            "failed to parse the number of cycles in the request",
+        )
+        .into(),
+    );
+    assert!(unrelated_reject.client_error && !unrelated_reject.cycles_error);
+
+    let insufficient_balance = classify(
+        PrepaidBalanceError::InsufficientBalance {
+            expected: 1_000,
+            balance: 0,
+        }
+        .into(),
+    );
+    assert!(insufficient_balance.caller_fault && insufficient_balance.cycles_error);
+
+    let insufficient_cycles = classify(
+        ChargeCallerError::InsufficientCyclesError {
+            expected: 1_000,
+            received: 0,
+        }
+        .into(),
+    );
+    assert!(insufficient_cycles.caller_fault && insufficient_cycles.cycles_error);
+
+    let too_many_requests = classify(unsuccessful_response(429).into());
+    assert!(too_many_requests.transient && too_many_requests.client_error);
+
+    let not_found = classify(unsuccessful_response(404).into());
+    assert!(!not_found.transient && not_found.client_error);
+
+    let unavailable = classify(unsuccessful_response(503).into());
+    assert!(unavailable.transient && !unavailable.client_error);
+}
+
+#[test]
+fn should_name_error_kinds() {
+    assert_eq!(
+        CanHttpError::from(unsuccessful_response(404)).error_kind(),
+        "http_4xx"
+    );
+    assert_eq!(
+        CanHttpError::from(unsuccessful_response(503)).error_kind(),
+        "http_5xx"
+    );
+    assert_eq!(
+        CanHttpError::from(HttpRequestConversionError::UnsupportedHttpMethod(
+            "PUT".to_string()
+        ))
+        .error_kind(),
+        "conversion"
+    );
+    assert_eq!(
+        BoxError::from(unsuccessful_response(503)).error_kind(),
+        "http_5xx"
+    );
+}
+
+#[test]
+fn should_delegate_to_ic_error() {
+    let error = CanHttpError::from(ic_error(
+        RejectCode::SysFatal,
+        "Http body exceeds size limit of 2000 bytes",
+    ));
+
+    assert!(error.is_response_too_large());
+    assert_eq!(
+        error.reject_message(),
+        Some("Http body exceeds size limit of 2000 bytes")
+    );
+    assert_eq!(error.error_kind(), "SysFatal");
+    assert_eq!(
+        error.to_string(),
+        "Error from ICP: (code SysFatal, message Http body exceeds size limit of 2000 bytes)"
+    );
+}
+
+fn ic_error(code: RejectCode, message: &str) -> IcError {
+    IcError {
+        code,
+        message: message.to_string(),
+    }
+}
+
+fn unsuccessful_response(status: u16) -> FilterNonSuccessfulHttpResponseError<Vec<u8>> {
+    FilterNonSuccessfulHttpResponseError::UnsuccessfulResponse(
+        http::Response::builder()
+            .status(status)
+            .body(vec![])
+            .unwrap(),
+    )
+}
@@ -1,6 +1,7 @@
 use crate::convert::{Convert, CreateResponseFilter, Filter};
 use crate::http::json::{HttpJsonRpcRequest, Id, Version};
 use crate::http::HttpResponse;
+use crate::redact::RedactionPolicy;
 use assert_matches::assert_matches;
 use serde::de::DeserializeOwned;
 use serde::{Deserialize, Serialize};
@@ -48,7 +49,7 @@ pub enum JsonResponseConversionError {
     InvalidJsonResponse {
         /// Response status code
         status: u16,
-        /// Response body
+        /// Response body, truncated according to the default [`RedactionPolicy`].
         body: String,
         /// Deserialization error
         parsing_error: String,
@@ -67,7 +68,7 @@ where
         let json_body: T = serde_json::from_slice(&body).map_err(|e| {
             JsonResponseConversionError::InvalidJsonResponse {
                 status: parts.status.as_u16(),
-                body: String::from_utf8_lossy(&body).to_string(),
+                body: RedactionPolicy::default().redact_body(&body),
                 parsing_error: e.to_string(),
             }
         })?;
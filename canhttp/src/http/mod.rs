@@ -30,7 +30,7 @@
 //!
 //! ```rust
 //! use canhttp::{http::{HttpConversionLayer }, IcError, MaxResponseBytesRequestExtension};
-//! use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument as IcHttpRequest, HttpResponse as IcHttpResponse};
+//! use ic_cdk::management_canister::{HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse};
 //! use tower::{Service, ServiceBuilder, ServiceExt, BoxError};
 //!
 //! async fn always_200_ok(request: IcHttpRequest) -> Result<IcHttpResponse, BoxError> {
@@ -59,13 +59,16 @@
 //! # }
 //! ```
 //!
-//! [`IcHttpRequest`]: ic_cdk::api::management_canister::http_request::CanisterHttpRequestArgument
-//! [`IcHttpResponse`]: ic_cdk::api::management_canister::http_request::HttpResponse
+//! [`IcHttpRequest`]: ic_cdk::management_canister::HttpRequestArgs
+//! [`IcHttpResponse`]: ic_cdk::management_canister::HttpRequestResult
 
 #[cfg(test)]
 mod tests;
 
-pub use request::{HttpRequest, HttpRequestConversionError, HttpRequestConverter};
+pub use request::{
+    HttpRequest, HttpRequestConversionError, HttpRequestConverter, HttpRequestWithCyclesConverter,
+    IcHttpRequestConverter, ValidateHttpRequestLimits,
+};
 pub use response::{
     FilterNonSuccessfulHttpResponse, FilterNonSuccessfulHttpResponseError, HttpResponse,
     HttpResponseConversionError, HttpResponseConverter,
@@ -1,8 +1,13 @@
-use crate::convert::Convert;
-use crate::{MaxResponseBytesRequestExtension, TransformContextRequestExtension};
-use ic_cdk::api::management_canister::http_request::{
-    CanisterHttpRequestArgument as IcHttpRequest, HttpHeader as IcHttpHeader,
-    HttpMethod as IcHttpMethod, TransformContext,
+use crate::convert::{Convert, Filter};
+use crate::redact::RedactionPolicy;
+use crate::retry::HTTP_MAX_SIZE;
+use crate::{
+    IcHttpRequestWithCycles, IsReplicatedRequestExtension, MaxResponseBytesRequestExtension,
+    RetryAttemptRequestExtension, TransformContextRequestExtension,
+};
+use ic_cdk::management_canister::{
+    HttpHeader as IcHttpHeader, HttpMethod as IcHttpMethod, HttpRequestArgs as IcHttpRequest,
+    TransformContext,
 };
 use thiserror::Error;
 
@@ -67,12 +72,74 @@ impl TransformContextRequestExtension for http::request::Builder {
     }
 }
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct IsReplicatedExtension(pub bool);
+
+impl<T> IsReplicatedRequestExtension for http::Request<T> {
+    fn set_is_replicated(&mut self, value: bool) {
+        let extensions = self.extensions_mut();
+        extensions.insert(IsReplicatedExtension(value));
+    }
+
+    fn get_is_replicated(&self) -> Option<bool> {
+        self.extensions()
+            .get::<IsReplicatedExtension>()
+            .map(|e| e.0)
+    }
+}
+
+impl IsReplicatedRequestExtension for http::request::Builder {
+    fn set_is_replicated(&mut self, value: bool) {
+        if let Some(extensions) = self.extensions_mut() {
+            extensions.insert(IsReplicatedExtension(value));
+        }
+    }
+
+    fn get_is_replicated(&self) -> Option<bool> {
+        self.extensions_ref()
+            .and_then(|extensions| extensions.get::<IsReplicatedExtension>().map(|e| e.0))
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct RetryAttemptExtension(pub u32);
+
+impl<T> RetryAttemptRequestExtension for http::Request<T> {
+    fn set_retry_attempt(&mut self, value: u32) {
+        let extensions = self.extensions_mut();
+        extensions.insert(RetryAttemptExtension(value));
+    }
+
+    fn get_retry_attempt(&self) -> Option<u32> {
+        self.extensions()
+            .get::<RetryAttemptExtension>()
+            .map(|e| e.0)
+    }
+}
+
 /// Error return when converting requests with [`HttpRequestConverter`].
 #[derive(Error, Clone, Debug, Eq, PartialEq)]
 pub enum HttpRequestConversionError {
     /// HTTP method is not supported
     #[error("HTTP method `{0}` is not supported")]
     UnsupportedHttpMethod(String),
+    /// URL is invalid.
+    #[error("URL `{url}` is invalid: {reason}")]
+    InvalidUrl {
+        /// URL, redacted with the default [`RedactionPolicy`]
+        /// since it may contain secrets such as API keys.
+        url: String,
+        /// Reason for URL being invalid.
+        reason: String,
+    },
+    /// Header name is invalid.
+    #[error("HTTP header `{name}` is invalid: {reason}")]
+    InvalidHttpHeaderName {
+        /// Header name
+        name: String,
+        /// Reason for being invalid.
+        reason: String,
+    },
     /// Header name is invalid.
     #[error("HTTP header `{name}` has an invalid value: {reason}")]
     InvalidHttpHeaderValue {
@@ -81,6 +148,54 @@ pub enum HttpRequestConversionError {
         /// Reason for header value being invalid.
         reason: String,
     },
+    /// URL is too long.
+    #[error("URL is {length} bytes long, exceeding the maximum of {max} bytes")]
+    UrlTooLong {
+        /// Length of the URL in bytes.
+        length: usize,
+        /// Maximum length of the URL in bytes.
+        max: usize,
+    },
+    /// Request has too many headers.
+    #[error("Request has {count} headers, exceeding the maximum of {max} headers")]
+    TooManyHeaders {
+        /// Number of headers.
+        count: usize,
+        /// Maximum number of headers.
+        max: usize,
+    },
+    /// Total size of the header names and values is too large.
+    #[error("Headers are {size} bytes large, exceeding the maximum of {max} bytes")]
+    HeadersTooLarge {
+        /// Total size of the header names and values in bytes.
+        size: usize,
+        /// Maximum total size of the header names and values in bytes.
+        max: usize,
+    },
+    /// Request body is too large.
+    #[error("Request body is {size} bytes large, exceeding the maximum of {max} bytes")]
+    BodyTooLarge {
+        /// Size of the body in bytes.
+        size: usize,
+        /// Maximum size of the body in bytes.
+        max: usize,
+    },
+    /// Total size of the URL, headers, body and transform context is too large.
+    #[error("Request is {size} bytes large, exceeding the maximum of {max} bytes")]
+    RequestTooLarge {
+        /// Total size of the request in bytes.
+        size: usize,
+        /// Maximum total size of the request in bytes.
+        max: usize,
+    },
+    /// Value of `max_response_bytes` is too large.
+    #[error("max_response_bytes is {value}, exceeding the maximum of {max}")]
+    MaxResponseBytesTooLarge {
+        /// Value of `max_response_bytes`.
+        value: u64,
+        /// Maximum value of `max_response_bytes`.
+        max: u64,
+    },
 }
 
 /// Convert requests of type [`HttpRequest`] into [`IcHttpRequest`].
@@ -119,6 +234,7 @@ impl Convert<HttpRequest> for HttpRequestConverter {
             })
             .collect::<Result<Vec<_>, _>>()?;
         let transform = request.get_transform_context().cloned();
+        let is_replicated = request.get_is_replicated();
         let body = Some(request.into_body());
         Ok(IcHttpRequest {
             url,
@@ -127,6 +243,210 @@ impl Convert<HttpRequest> for HttpRequestConverter {
             headers,
             body,
             transform,
+            is_replicated,
         })
     }
 }
+
+/// Convert requests of type [`HttpRequest`] into [`IcHttpRequestWithCycles`], like [`HttpRequestConverter`]
+/// while keeping the retry attempt of the request (see [`RetryAttemptRequestExtension`]).
+///
+/// The resulting requests have no cycles attached yet, which is usually done below this converter by
+/// [`CyclesAccounting`](crate::cycles::CyclesAccounting) configured with
+/// [`with_requests_with_cycles`](crate::cycles::CyclesAccounting::with_requests_with_cycles),
+/// so that middlewares further below can observe the retry attempt, see [`crate::observability`].
+#[derive(Clone, Debug)]
+pub struct HttpRequestWithCyclesConverter;
+
+impl Convert<HttpRequest> for HttpRequestWithCyclesConverter {
+    type Output = IcHttpRequestWithCycles;
+    type Error = HttpRequestConversionError;
+
+    fn try_convert(&mut self, request: HttpRequest) -> Result<Self::Output, Self::Error> {
+        let retry_attempt = request.get_retry_attempt().unwrap_or_default();
+        let request = HttpRequestConverter.try_convert(request)?;
+        Ok(IcHttpRequestWithCycles {
+            retry_attempt,
+            ..IcHttpRequestWithCycles::new(request, 0)
+        })
+    }
+}
+
+/// Convert requests of type [`IcHttpRequest`] into [`HttpRequest`], which is the reverse of [`HttpRequestConverter`].
+///
+/// The IC-specific parameters of the [`IcHttpRequest`] (e.g. `max_response_bytes`) are stored
+/// as extensions of the [`HttpRequest`], see [`MaxResponseBytesRequestExtension`],
+/// [`TransformContextRequestExtension`] and [`IsReplicatedRequestExtension`].
+/// Header names are lower-cased and the body is empty if the request had none.
+#[derive(Clone, Debug)]
+pub struct IcHttpRequestConverter;
+
+impl Convert<IcHttpRequest> for IcHttpRequestConverter {
+    type Output = HttpRequest;
+    type Error = HttpRequestConversionError;
+
+    fn try_convert(&mut self, request: IcHttpRequest) -> Result<Self::Output, Self::Error> {
+        use http::{HeaderName, HeaderValue, Method, Uri};
+
+        let uri =
+            Uri::try_from(&request.url).map_err(|e| HttpRequestConversionError::InvalidUrl {
+                url: RedactionPolicy::default().redact_url(&request.url),
+                reason: e.to_string(),
+            })?;
+        let method = match request.method {
+            IcHttpMethod::GET => Method::GET,
+            IcHttpMethod::POST => Method::POST,
+            IcHttpMethod::HEAD => Method::HEAD,
+        };
+
+        let mut builder = http::Request::builder().method(method).uri(uri);
+        if let Some(headers) = builder.headers_mut() {
+            for IcHttpHeader { name, value } in request.headers {
+                headers.append(
+                    HeaderName::try_from(&name).map_err(|e| {
+                        HttpRequestConversionError::InvalidHttpHeaderName {
+                            name: name.clone(),
+                            reason: e.to_string(),
+                        }
+                    })?,
+                    HeaderValue::try_from(&value).map_err(|e| {
+                        HttpRequestConversionError::InvalidHttpHeaderValue {
+                            name,
+                            reason: e.to_string(),
+                        }
+                    })?,
+                );
+            }
+        }
+        if let Some(max_response_bytes) = request.max_response_bytes {
+            builder = builder.max_response_bytes(max_response_bytes);
+        }
+        if let Some(transform) = request.transform {
+            builder = builder.transform_context(transform);
+        }
+        if let Some(is_replicated) = request.is_replicated {
+            builder = builder.replicated(is_replicated);
+        }
+
+        Ok(builder
+            .body(request.body.unwrap_or_default())
+            .expect("BUG: builder should have been modified only with validated data"))
+    }
+}
+
+// These constants come from the IC specification:
+// > The url must be valid URI per RFC-3986 and its length must not exceed 8192 characters.
+// > The total number of headers must not exceed 64.
+// > The total number of bytes representing the header names and values must not exceed 48KiB.
+// > The size of the request, including the request body, must not exceed 2MB.
+const MAX_URL_LENGTH: usize = 8_192;
+const MAX_HEADERS_COUNT: usize = 64;
+const MAX_HEADERS_SIZE: usize = 48 * 1_024;
+const MAX_BODY_SIZE: usize = HTTP_MAX_SIZE as usize;
+const MAX_REQUEST_SIZE: usize = HTTP_MAX_SIZE as usize;
+
+/// Validate that requests of type [`IcHttpRequest`] are within the limits of the management canister.
+///
+/// Requests exceeding these limits would be rejected by the management canister,
+/// but only after the cycles for the HTTPs outcall were attached.
+/// Validating requests up front, e.g. before any cycles accounting, makes such errors cheap.
+/// The following limits are enforced:
+/// * the URL is at most 8192 bytes long;
+/// * there are at most 64 headers;
+/// * the header names and values are at most 48KiB large in total;
+/// * the body is at most 2MB (`2_000_000` bytes) large;
+/// * the URL, header names and values, body and transform context (method name and context)
+///   are at most 2MB large in total;
+/// * `max_response_bytes`, if any, is at most 2MB (`2_000_000`).
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{
+///     http::{HttpRequestConversionError, HttpRequestConverter, ValidateHttpRequestLimits},
+///     ConvertServiceBuilder, MaxResponseBytesRequestExtension,
+/// };
+/// use ic_cdk::management_canister::HttpRequestArgs as IcHttpRequest;
+/// use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+///     .convert_request(HttpRequestConverter)
+///     .convert_request(ValidateHttpRequestLimits)
+///     .service_fn(|request: IcHttpRequest| async move { Ok::<_, BoxError>(request) });
+///
+/// let request = http::Request::get("https://internetcomputer.org/")
+///     .max_response_bytes(3_000_000)
+///     .body(vec![])
+///     .unwrap();
+///
+/// let error = service.ready().await.unwrap().call(request).await.unwrap_err();
+/// assert_eq!(
+///     error.downcast_ref::<HttpRequestConversionError>(),
+///     Some(&HttpRequestConversionError::MaxResponseBytesTooLarge {
+///         value: 3_000_000,
+///         max: 2_000_000,
+///     })
+/// );
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct ValidateHttpRequestLimits;
+
+impl Filter<IcHttpRequest> for ValidateHttpRequestLimits {
+    type Error = HttpRequestConversionError;
+
+    fn filter(&mut self, request: IcHttpRequest) -> Result<IcHttpRequest, Self::Error> {
+        if request.url.len() > MAX_URL_LENGTH {
+            return Err(HttpRequestConversionError::UrlTooLong {
+                length: request.url.len(),
+                max: MAX_URL_LENGTH,
+            });
+        }
+        if request.headers.len() > MAX_HEADERS_COUNT {
+            return Err(HttpRequestConversionError::TooManyHeaders {
+                count: request.headers.len(),
+                max: MAX_HEADERS_COUNT,
+            });
+        }
+        let headers_size = request
+            .headers
+            .iter()
+            .map(|header| header.name.len() + header.value.len())
+            .sum::<usize>();
+        if headers_size > MAX_HEADERS_SIZE {
+            return Err(HttpRequestConversionError::HeadersTooLarge {
+                size: headers_size,
+                max: MAX_HEADERS_SIZE,
+            });
+        }
+        let body_size = request.body.as_ref().map_or(0, Vec::len);
+        if body_size > MAX_BODY_SIZE {
+            return Err(HttpRequestConversionError::BodyTooLarge {
+                size: body_size,
+                max: MAX_BODY_SIZE,
+            });
+        }
+        let transform_size = request.transform.as_ref().map_or(0, |transform| {
+            transform.function.0.method.len() + transform.context.len()
+        });
+        let request_size = request.url.len() + headers_size + body_size + transform_size;
+        if request_size > MAX_REQUEST_SIZE {
+            return Err(HttpRequestConversionError::RequestTooLarge {
+                size: request_size,
+                max: MAX_REQUEST_SIZE,
+            });
+        }
+        match request.max_response_bytes {
+            Some(value) if value > HTTP_MAX_SIZE => {
+                Err(HttpRequestConversionError::MaxResponseBytesTooLarge {
+                    value,
+                    max: HTTP_MAX_SIZE,
+                })
+            }
+            _ => Ok(request),
+        }
+    }
+}
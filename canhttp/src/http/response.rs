@@ -1,11 +1,29 @@
 use crate::convert::{Convert, Filter};
+use crate::redact::Redacted;
+use crate::{CyclesUsage, CyclesUsageResponseExtension, IcHttpResponseWithCycles};
 use http::Response;
-use ic_cdk::api::management_canister::http_request::HttpResponse as IcHttpResponse;
+use ic_cdk::management_canister::HttpRequestResult as IcHttpResponse;
+use std::fmt;
+use std::fmt::{Debug, Display, Formatter};
 use thiserror::Error;
 
 /// HTTP response with a body made of bytes.
 pub type HttpResponse = http::Response<Vec<u8>>;
 
+#[derive(Clone, Debug, PartialEq, Eq)]
+struct CyclesUsageExtension(pub CyclesUsage);
+
+impl<T> CyclesUsageResponseExtension for http::Response<T> {
+    fn set_cycles_usage(&mut self, value: CyclesUsage) {
+        let extensions = self.extensions_mut();
+        extensions.insert(CyclesUsageExtension(value));
+    }
+
+    fn get_cycles_usage(&self) -> Option<CyclesUsage> {
+        self.extensions().get::<CyclesUsageExtension>().map(|e| e.0)
+    }
+}
+
 /// Error returned when converting respones with [`HttpResponseConverter`].
 #[derive(Error, Clone, Debug, Eq, PartialEq)]
 #[allow(clippy::enum_variant_names)] //current variants reflect invalid data and so start with the prefix Invalid.
@@ -31,7 +49,16 @@ pub enum HttpResponseConversionError {
     },
 }
 
-/// Convert responses of type [`IcHttpResponse`] into [HttpResponse].
+/// Convert responses of type [`IcHttpResponse`] into [HttpResponse], and back.
+///
+/// Responses of type [`IcHttpResponseWithCycles`] are also supported, in which case
+/// the cycles usage is available as an extension of the converted response
+/// (see [`CyclesUsageResponseExtension`]).
+///
+/// Headers with the same name (e.g. several `Set-Cookie` or `Link` headers) are all kept in their original order.
+/// Converting an [HttpResponse] back into an [`IcHttpResponse`] yields lower-cased header names,
+/// with the headers grouped by name in the order in which each name first appeared.
+/// Extensions of the [HttpResponse] are dropped.
 #[derive(Debug, Clone)]
 pub struct HttpResponseConverter;
 
@@ -41,7 +68,7 @@ impl Convert<IcHttpResponse> for HttpResponseConverter {
 
     fn try_convert(&mut self, response: IcHttpResponse) -> Result<Self::Output, Self::Error> {
         use http::{HeaderMap, HeaderName, HeaderValue, StatusCode};
-        use ic_cdk::api::management_canister::http_request::HttpHeader as IcHttpHeader;
+        use ic_cdk::management_canister::HttpHeader as IcHttpHeader;
         use num_traits::ToPrimitive;
 
         let status = response
@@ -55,7 +82,7 @@ impl Convert<IcHttpResponse> for HttpResponseConverter {
         if let Some(headers) = builder.headers_mut() {
             let mut response_headers = HeaderMap::with_capacity(response.headers.len());
             for IcHttpHeader { name, value } in response.headers {
-                response_headers.insert(
+                response_headers.append(
                     HeaderName::try_from(&name).map_err(|e| {
                         HttpResponseConversionError::InvalidHttpHeaderName {
                             name: name.clone(),
@@ -79,14 +106,87 @@ impl Convert<IcHttpResponse> for HttpResponseConverter {
     }
 }
 
+impl Convert<HttpResponse> for HttpResponseConverter {
+    type Output = IcHttpResponse;
+    type Error = HttpResponseConversionError;
+
+    fn try_convert(&mut self, response: HttpResponse) -> Result<Self::Output, Self::Error> {
+        use ic_cdk::management_canister::HttpHeader as IcHttpHeader;
+
+        let status = response.status().as_u16().into();
+        let headers = response
+            .headers()
+            .iter()
+            .map(
+                |(name, value)| match std::str::from_utf8(value.as_bytes()) {
+                    Ok(value) => Ok(IcHttpHeader {
+                        name: name.to_string(),
+                        value: value.to_string(),
+                    }),
+                    Err(e) => Err(HttpResponseConversionError::InvalidHttpHeaderValue {
+                        name: name.to_string(),
+                        reason: e.to_string(),
+                    }),
+                },
+            )
+            .collect::<Result<Vec<_>, _>>()?;
+        Ok(IcHttpResponse {
+            status,
+            headers,
+            body: response.into_body(),
+        })
+    }
+}
+
+impl Convert<IcHttpResponseWithCycles> for HttpResponseConverter {
+    type Output = HttpResponse;
+    type Error = HttpResponseConversionError;
+
+    fn try_convert(
+        &mut self,
+        IcHttpResponseWithCycles { response, cycles }: IcHttpResponseWithCycles,
+    ) -> Result<Self::Output, Self::Error> {
+        let mut response = self.try_convert(response)?;
+        response.set_cycles_usage(cycles);
+        Ok(response)
+    }
+}
+
 /// Error returned when converting responses with [`FilterNonSuccessfulHttpResponse`].
-#[derive(Error, Clone, Debug)]
+///
+/// The response is formatted with the default [`RedactionPolicy`](crate::redact::RedactionPolicy)
+/// to avoid leaking sensitive headers (e.g. `Set-Cookie`) into logs.
+#[derive(Clone)]
 pub enum FilterNonSuccessfulHttpResponseError<T> {
     /// Response has a non-successful status code.
-    #[error("HTTP response is not successful: {0:?}")]
     UnsuccessfulResponse(http::Response<T>),
 }
 
+impl<T: Debug + 'static> Display for FilterNonSuccessfulHttpResponseError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsuccessfulResponse(response) => write!(
+                f,
+                "HTTP response is not successful: {:?}",
+                Redacted::new(response)
+            ),
+        }
+    }
+}
+
+impl<T: Debug + 'static> Debug for FilterNonSuccessfulHttpResponseError<T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::UnsuccessfulResponse(response) => f
+                .debug_tuple("UnsuccessfulResponse")
+                .field(&Redacted::new(response))
+                .finish(),
+        }
+    }
+}
+
+impl<T: Debug + 'static> std::error::Error for FilterNonSuccessfulHttpResponseError<T> {}
+
 /// Filter out non-successful responses.
 #[derive(Clone, Debug)]
 pub struct FilterNonSuccessfulHttpResponse;
@@ -1,18 +1,23 @@
+use crate::convert::Convert;
 use crate::http::request::HttpRequestConversionError;
 use crate::http::response::{HttpResponse, HttpResponseConversionError};
-use crate::http::{HttpConversionLayer, HttpRequestConverter, HttpResponseConverter};
+use crate::http::{
+    HttpConversionLayer, HttpRequestConverter, HttpResponseConverter, IcHttpRequestConverter,
+    ValidateHttpRequestLimits,
+};
 use crate::{
-    ConvertServiceBuilder, IcError, MaxResponseBytesRequestExtension,
+    ConvertServiceBuilder, CyclesUsage, CyclesUsageResponseExtension, IcError,
+    IcHttpResponseWithCycles, IsReplicatedRequestExtension, MaxResponseBytesRequestExtension,
     TransformContextRequestExtension,
 };
 use assert_matches::assert_matches;
 use candid::{Decode, Encode, Principal};
 use http::StatusCode;
-use ic_cdk::api::management_canister::http_request::{
-    CanisterHttpRequestArgument as IcHttpRequest, HttpHeader as IcHttpHeader,
-    HttpMethod as IcHttpMethod, HttpResponse as IcHttpResponse,
+use ic_cdk::management_canister::{
+    HttpHeader as IcHttpHeader, HttpMethod as IcHttpMethod, HttpRequestArgs as IcHttpRequest,
+    HttpRequestResult as IcHttpResponse,
 };
-use ic_cdk::api::management_canister::http_request::{TransformContext, TransformFunc};
+use ic_cdk::management_canister::{TransformContext, TransformFunc};
 use ic_error_types::RejectCode;
 use std::error::Error;
 use std::fmt::Debug;
@@ -58,11 +63,162 @@ async fn should_convert_http_request() {
                 }],
                 body: Some(body.clone()),
                 transform: Some(transform_context.clone()),
+                is_replicated: None,
             }
         )
     }
 }
 
+#[test]
+fn should_convert_ic_http_request_back() {
+    let transform_context = TransformContext {
+        function: TransformFunc::new(Principal::management_canister(), "sanitize".to_string()),
+        context: vec![35_u8; 20],
+    };
+
+    for (method, expected_method) in [
+        (IcHttpMethod::POST, http::Method::POST),
+        (IcHttpMethod::GET, http::Method::GET),
+        (IcHttpMethod::HEAD, http::Method::HEAD),
+    ] {
+        let request = IcHttpRequest {
+            url: "https://internetcomputer.org/?page=2".to_string(),
+            max_response_bytes: Some(1_000),
+            method,
+            headers: vec![
+                IcHttpHeader {
+                    name: "accept".to_string(),
+                    value: "application/json".to_string(),
+                },
+                IcHttpHeader {
+                    name: "x-id".to_string(),
+                    value: "1".to_string(),
+                },
+                IcHttpHeader {
+                    name: "accept".to_string(),
+                    value: "text/plain".to_string(),
+                },
+            ],
+            body: Some(vec![42_u8; 32]),
+            transform: Some(transform_context.clone()),
+            is_replicated: Some(false),
+        };
+
+        let converted_request = IcHttpRequestConverter.try_convert(request.clone()).unwrap();
+
+        assert_eq!(converted_request.method(), expected_method);
+        assert_eq!(
+            converted_request.uri(),
+            "https://internetcomputer.org/?page=2"
+        );
+        assert_eq!(
+            converted_request
+                .headers()
+                .get_all("accept")
+                .iter()
+                .collect::<Vec<_>>(),
+            vec!["application/json", "text/plain"]
+        );
+        assert_eq!(converted_request.get_max_response_bytes(), Some(1_000));
+        assert_eq!(
+            converted_request.get_transform_context(),
+            Some(&transform_context)
+        );
+        assert_eq!(converted_request.get_is_replicated(), Some(false));
+        assert_eq!(converted_request.body(), &vec![42_u8; 32]);
+
+        let round_tripped_request = HttpRequestConverter.try_convert(converted_request).unwrap();
+
+        assert_eq!(
+            round_tripped_request,
+            IcHttpRequest {
+                headers: vec![
+                    request.headers[0].clone(),
+                    request.headers[2].clone(),
+                    request.headers[1].clone(),
+                ],
+                ..request
+            }
+        );
+    }
+
+    let converted_request = IcHttpRequestConverter
+        .try_convert(IcHttpRequest {
+            url: "https://internetcomputer.org/".to_string(),
+            ..Default::default()
+        })
+        .unwrap();
+    assert_eq!(converted_request.body(), &Vec::<u8>::new());
+    assert_eq!(converted_request.get_max_response_bytes(), None);
+    assert_eq!(converted_request.get_transform_context(), None);
+    assert_eq!(converted_request.get_is_replicated(), None);
+}
+
+#[test]
+fn should_fail_to_convert_ic_http_request_back() {
+    let request = IcHttpRequest {
+        url: "https://internetcomputer.org/".to_string(),
+        ..Default::default()
+    };
+    let header = |name: &str, value: &str| IcHttpHeader {
+        name: name.to_string(),
+        value: value.to_string(),
+    };
+
+    let error = IcHttpRequestConverter
+        .try_convert(IcHttpRequest {
+            url: "https://internet computer.org/?api_key=secret".to_string(),
+            ..request.clone()
+        })
+        .unwrap_err();
+    assert_matches!(
+        error,
+        HttpRequestConversionError::InvalidUrl { ref url, .. }
+            if url == "https://internet computer.org/?api_key=[REDACTED]"
+    );
+
+    let error = IcHttpRequestConverter
+        .try_convert(IcHttpRequest {
+            headers: vec![header("invalid name", "value")],
+            ..request.clone()
+        })
+        .unwrap_err();
+    assert_matches!(
+        error,
+        HttpRequestConversionError::InvalidHttpHeaderName { .. }
+    );
+
+    let error = IcHttpRequestConverter
+        .try_convert(IcHttpRequest {
+            headers: vec![header("x-id", "invalid\nvalue")],
+            ..request
+        })
+        .unwrap_err();
+    assert_matches!(
+        error,
+        HttpRequestConversionError::InvalidHttpHeaderValue { .. }
+    );
+}
+
+#[tokio::test]
+async fn should_convert_non_replicated_http_request() {
+    let mut service = ServiceBuilder::new()
+        .convert_request(HttpRequestConverter)
+        .service_fn(echo_request);
+
+    for is_replicated in [true, false] {
+        let request = http::Request::get("https://internetcomputer.org/")
+            .replicated(is_replicated)
+            .body(vec![])
+            .unwrap();
+        assert_eq!(request.get_is_replicated(), Some(is_replicated));
+
+        let converted_request = service.ready().await.unwrap().call(request).await.unwrap();
+
+        assert_eq!(converted_request.is_replicated, Some(is_replicated));
+    }
+}
+
 #[tokio::test]
 async fn should_fail_when_http_method_unsupported() {
     let mut service = ServiceBuilder::new()
@@ -93,6 +249,115 @@ async fn should_fail_when_http_method_unsupported() {
     }
 }
 
+#[tokio::test]
+async fn should_validate_http_request_limits() {
+    let mut service = ServiceBuilder::new()
+        .convert_request(ValidateHttpRequestLimits)
+        .service_fn(echo_request);
+    let header = |name: String, value_len: usize| IcHttpHeader {
+        name,
+        value: "a".repeat(value_len),
+    };
+    let request_within_limits = IcHttpRequest {
+        url: format!("https://internetcomputer.org/{}", "a".repeat(8_192 - 29)),
+        max_response_bytes: Some(2_000_000),
+        headers: (0..64)
+            .map(|i| header(format!("x-header-{i:02}"), 768 - 11))
+            .collect(),
+        // The URL, headers and body are at most 2MB large in total.
+        body: Some(vec![42; 2_000_000 - 8_192 - 48 * 1_024]),
+        ..Default::default()
+    };
+
+    let request = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request_within_limits.clone())
+        .await
+        .unwrap();
+    assert_eq!(request, request_within_limits);
+
+    for (request, expected_error) in [
+        (
+            IcHttpRequest {
+                url: format!("{}a", request_within_limits.url),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::UrlTooLong {
+                length: 8_193,
+                max: 8_192,
+            },
+        ),
+        (
+            IcHttpRequest {
+                headers: (0..65).map(|i| header(format!("x-{i}"), 1)).collect(),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::TooManyHeaders { count: 65, max: 64 },
+        ),
+        (
+            IcHttpRequest {
+                headers: vec![header("x-header".to_string(), 48 * 1_024 - 7)],
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::HeadersTooLarge {
+                size: 48 * 1_024 + 1,
+                max: 48 * 1_024,
+            },
+        ),
+        (
+            IcHttpRequest {
+                body: Some(vec![42; 2_000_001]),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::BodyTooLarge {
+                size: 2_000_001,
+                max: 2_000_000,
+            },
+        ),
+        (
+            IcHttpRequest {
+                body: Some(vec![42; 2_000_000 - 8_192 - 48 * 1_024 + 1]),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::RequestTooLarge {
+                size: 2_000_001,
+                max: 2_000_000,
+            },
+        ),
+        (
+            IcHttpRequest {
+                transform: Some(TransformContext {
+                    function: TransformFunc::new(Principal::anonymous(), "t".to_string()),
+                    context: vec![],
+                }),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::RequestTooLarge {
+                size: 2_000_001,
+                max: 2_000_000,
+            },
+        ),
+        (
+            IcHttpRequest {
+                max_response_bytes: Some(2_000_001),
+                ..request_within_limits.clone()
+            },
+            HttpRequestConversionError::MaxResponseBytesTooLarge {
+                value: 2_000_001,
+                max: 2_000_000,
+            },
+        ),
+    ] {
+        let error = expect_error::<_, HttpRequestConversionError>(
+            service.ready().await.unwrap().call(request).await,
+        );
+
+        assert_eq!(error, expected_error);
+    }
+}
+
 #[tokio::test]
 async fn should_convert_http_response() {
     let mut service = ServiceBuilder::new()
@@ -120,6 +385,133 @@ async fn should_convert_http_response() {
     )
 }
 
+#[tokio::test]
+async fn should_preserve_repeated_http_response_headers() {
+    let mut service = ServiceBuilder::new()
+        .convert_response(HttpResponseConverter)
+        .service_fn(echo_response);
+    let header = |name: &str, value: &str| IcHttpHeader {
+        name: name.to_string(),
+        value: value.to_string(),
+    };
+    let response = IcHttpResponse {
+        status: 200_u8.into(),
+        headers: vec![
+            header(
+                "Link",
+                "<https://api.example.com/items?page=2>; rel=\"next\"",
+            ),
+            header("Set-Cookie", "a=1"),
+            header(
+                "Link",
+                "<https://api.example.com/items?page=5>; rel=\"last\"",
+            ),
+            header("Set-Cookie", "b=2"),
+            header("content-type", "application/json"),
+        ],
+        body: vec![42; 32],
+    };
+
+    let converted_response = service
+        .ready()
+        .await
+        .unwrap()
+        .call(response.clone())
+        .await
+        .unwrap();
+
+    assert_eq!(
+        converted_response
+            .headers()
+            .get_all("link")
+            .iter()
+            .collect::<Vec<_>>(),
+        vec![
+            "<https://api.example.com/items?page=2>; rel=\"next\"",
+            "<https://api.example.com/items?page=5>; rel=\"last\""
+        ]
+    );
+    assert_eq!(
+        converted_response
+            .headers()
+            .get_all("set-cookie")
+            .iter()
+            .collect::<Vec<_>>(),
+        vec!["a=1", "b=2"]
+    );
+
+    let round_tripped_response = HttpResponseConverter
+        .try_convert(converted_response)
+        .unwrap();
+
+    assert_eq!(
+        round_tripped_response,
+        IcHttpResponse {
+            headers: vec![
+                header(
+                    "link",
+                    "<https://api.example.com/items?page=2>; rel=\"next\""
+                ),
+                header(
+                    "link",
+                    "<https://api.example.com/items?page=5>; rel=\"last\""
+                ),
+                header("set-cookie", "a=1"),
+                header("set-cookie", "b=2"),
+                header("content-type", "application/json"),
+            ],
+            ..response
+        }
+    );
+}
+
+#[test]
+fn should_fail_to_convert_http_response_back_when_header_value_not_utf8() {
+    let response = http::Response::builder()
+        .status(200)
+        .header("x-invalid", http::HeaderValue::from_bytes(&[0xff]).unwrap())
+        .body(vec![])
+        .unwrap();
+
+    let error = HttpResponseConverter.try_convert(response).unwrap_err();
+
+    assert_matches!(
+        error,
+        HttpResponseConversionError::InvalidHttpHeaderValue { name, .. } if name == "x-invalid"
+    );
+}
+
+#[tokio::test]
+async fn should_convert_http_response_with_cycles() {
+    let mut service = ServiceBuilder::new()
+        .convert_response(HttpResponseConverter)
+        .service_fn(
+            |response: IcHttpResponseWithCycles| async move { Ok::<_, BoxError>(response) },
+        );
+    let cycles = CyclesUsage {
+        attached: 1_000,
+        refunded: 100,
+    };
+    let response = IcHttpResponseWithCycles {
+        response: IcHttpResponse {
+            status: 200_u8.into(),
+            headers: vec![],
+            body: vec![42; 32],
+        },
+        cycles,
+    };
+
+    let converted_response = service.ready().await.unwrap().call(response).await.unwrap();
+
+    assert_eq!(converted_response.status(), StatusCode::OK);
+    assert_eq!(converted_response.body(), &vec![42; 32]);
+    assert_eq!(converted_response.get_cycles_usage(), Some(cycles));
+    assert_eq!(
+        converted_response.get_cycles_usage().unwrap().consumed(),
+        900
+    );
+}
+
 #[tokio::test]
 async fn should_fail_to_convert_http_response() {
     let invalid_response = IcHttpResponse {
@@ -206,6 +598,7 @@ async fn should_convert_both_request_and_responses() {
             }],
             body: Some(body.clone()),
             transform: Some(transform_context.clone()),
+            is_replicated: None,
         }
     );
 
@@ -6,17 +6,28 @@
 #![forbid(missing_docs)]
 
 pub use client::{
-    Client, HttpsOutcallError, IcError, IcHttpRequestWithCycles, MaxResponseBytesRequestExtension,
-    TransformContextRequestExtension,
+    Client, CyclesQuote, CyclesUsage, CyclesUsageRecorder, CyclesUsageResponseExtension,
+    DryRunClient, HttpsOutcallError, IcError, IcHttpRequestWithCycles, IcHttpResponseWithCycles,
+    IsReplicatedRequestExtension, MaxResponseBytesRequestExtension, ReportCyclesUsage,
+    ReportCyclesUsageLayer, RetryAttemptRequestExtension, TransformContextRequestExtension,
 };
 pub use convert::ConvertServiceBuilder;
+pub use error::CanHttpError;
 
 mod client;
 pub mod convert;
 pub mod cycles;
+mod error;
 #[cfg(feature = "http")]
 pub mod http;
 #[cfg(feature = "multi")]
 pub mod multi;
 pub mod observability;
+pub mod redact;
+#[cfg(feature = "multi")]
+pub mod response_size;
 pub mod retry;
+pub mod time;
+#[cfg(feature = "tracing")]
+pub mod trace;
+pub mod transform;
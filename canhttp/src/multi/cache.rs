@@ -1,3 +1,4 @@
+use crate::time::{Clock, IcClock, Timestamp};
 use std::borrow::Borrow;
 use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::num::NonZeroUsize;
@@ -10,14 +11,16 @@ use std::time::Duration;
 /// 1. The timestamp for the insertion of `u` is before the timestamp for the insertion of `v`.
 /// 2. Or, if they both have the same timestamp for insertion, `u` was inserted before `v`.
 ///
-/// # Examples
-///
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct TimedSizedVec<T> {
+/// The current time can either be given explicitly (e.g. [`Self::insert_evict`]) or be read from
+/// a [`Clock`] (e.g. [`Self::insert_evict_now`]), which defaults to [`IcClock`]
+/// and can be changed with [`Self::with_clock`].
+#[derive(Clone, Debug)]
+pub struct TimedSizedVec<T, C = IcClock> {
     expiration: Duration,
     capacity: NonZeroUsize,
     size: usize,
     store: BTreeMap<Timestamp, VecDeque<T>>,
+    clock: C,
 }
 
 impl<T> TimedSizedVec<T> {
@@ -42,6 +45,38 @@ impl<T> TimedSizedVec<T> {
             capacity,
             size: 0,
             store: BTreeMap::default(),
+            clock: IcClock,
+        }
+    }
+}
+
+impl<T, C> TimedSizedVec<T, C> {
+    /// Use the given clock to read the current time, see [`Self::insert_evict_now`] and [`Self::evict_expired_now`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::num::NonZeroUsize;
+    /// use std::time::Duration;
+    /// use canhttp::multi::{TimedSizedVec, Timestamp};
+    /// use canhttp::time::MockClock;
+    ///
+    /// let clock = MockClock::new(Timestamp::UNIX_EPOCH);
+    /// let mut vec = TimedSizedVec::new(Duration::from_secs(10), NonZeroUsize::new(3).unwrap())
+    ///     .with_clock(clock.clone());
+    ///
+    /// let _evicted = vec.insert_evict_now("a");
+    /// clock.advance(Duration::from_secs(11));
+    ///
+    /// assert_eq!(vec.evict_expired_now().into_values().flatten().collect::<Vec<_>>(), vec!["a"]);
+    /// ```
+    pub fn with_clock<NewClock: Clock>(self, clock: NewClock) -> TimedSizedVec<T, NewClock> {
+        TimedSizedVec {
+            expiration: self.expiration,
+            capacity: self.capacity,
+            size: self.size,
+            store: self.store,
+            clock,
         }
     }
 
@@ -220,49 +255,46 @@ impl<T> TimedSizedVec<T> {
     }
 }
 
-/// Time in nanoseconds since the epoch (1970-01-01).
-#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
-pub struct Timestamp(Duration);
-
-impl Timestamp {
-    /// The Unix epoch.
-    pub const UNIX_EPOCH: Timestamp = Timestamp::from_nanos_since_unix_epoch(0);
-
-    /// Create a new [`Timestamp`] from a number of nanoseconds since the Unix epoch.
-    pub const fn from_nanos_since_unix_epoch(nanos: u64) -> Self {
-        Timestamp::from_unix_epoch(Duration::from_nanos(nanos))
+impl<T, C: Clock> TimedSizedVec<T, C> {
+    /// Insert a new element at the current time of the clock and return evicted elements.
+    ///
+    /// See [`Self::insert_evict`].
+    pub fn insert_evict_now(&mut self, value: T) -> BTreeMap<Timestamp, VecDeque<T>> {
+        let now = self.clock.now();
+        self.insert_evict(now, value)
     }
 
-    /// Create a new [`Timestamp`] from a [`Duration`] since the Unix epoch.
-    pub const fn from_unix_epoch(duration: Duration) -> Self {
-        Timestamp(duration)
+    /// Evict elements expired at the current time of the clock.
+    ///
+    /// See [`Self::evict_expired`].
+    pub fn evict_expired_now(&mut self) -> BTreeMap<Timestamp, VecDeque<T>> {
+        let now = self.clock.now();
+        self.evict_expired(now)
     }
+}
 
-    /// Checked `Time` subtraction with a `Duration`. Computes `self - rhs`,
-    /// returning [`None`] if underflow occurs.
-    ///
-    /// # Examples
-    ///
-    /// Basic usage:
-    ///
-    /// ```
-    /// use std::time::Duration;
-    /// use canhttp::multi::Timestamp;
-    ///
-    /// assert_eq!(Timestamp::from_nanos_since_unix_epoch(3).checked_sub(Duration::from_nanos(2)), Some(Timestamp::from_nanos_since_unix_epoch(1)));
-    /// assert_eq!(Timestamp::from_nanos_since_unix_epoch(2).checked_sub(Duration::from_nanos(3)), None);
-    /// ```
-    pub fn checked_sub(self, rhs: Duration) -> Option<Timestamp> {
-        self.0.checked_sub(rhs).map(Timestamp::from_unix_epoch)
+/// The clock is not compared.
+impl<T: PartialEq, C> PartialEq for TimedSizedVec<T, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.expiration == other.expiration
+            && self.capacity == other.capacity
+            && self.size == other.size
+            && self.store == other.store
     }
 }
 
+impl<T: Eq, C> Eq for TimedSizedVec<T, C> {}
+
 /// A map where values are limited-size vectors with older elements evicted first.
-#[derive(Clone, Debug, PartialEq, Eq)]
-pub struct TimedSizedMap<K, V> {
+///
+/// Like [`TimedSizedVec`], the current time can either be given explicitly or be read from
+/// a [`Clock`], which defaults to [`IcClock`] and can be changed with [`Self::with_clock`].
+#[derive(Clone, Debug)]
+pub struct TimedSizedMap<K, V, C = IcClock> {
     expiration: Duration,
     capacity: NonZeroUsize,
     store: BTreeMap<K, TimedSizedVec<V>>,
+    clock: C,
 }
 
 impl<K, V> TimedSizedMap<K, V> {
@@ -273,6 +305,42 @@ impl<K, V> TimedSizedMap<K, V> {
             expiration,
             capacity,
             store: BTreeMap::default(),
+            clock: IcClock,
+        }
+    }
+}
+
+impl<K, V, C> TimedSizedMap<K, V, C> {
+    /// Use the given clock to read the current time, see [`Self::insert_evict_now`] and [`Self::evict_expired_now`].
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::num::NonZeroUsize;
+    /// use std::time::Duration;
+    /// use canhttp::multi::{TimedSizedMap, Timestamp};
+    /// use canhttp::time::MockClock;
+    ///
+    /// let clock = MockClock::new(Timestamp::UNIX_EPOCH);
+    /// let mut map = TimedSizedMap::new(Duration::from_secs(10), NonZeroUsize::new(3).unwrap())
+    ///     .with_clock(clock.clone());
+    ///
+    /// let _evicted = map.insert_evict_now("key1", "a");
+    /// clock.advance(Duration::from_secs(5));
+    /// let _evicted = map.insert_evict_now("key1", "b");
+    /// clock.advance(Duration::from_secs(6));
+    ///
+    /// let expired = map.evict_expired_now(&["key1"]);
+    ///
+    /// assert_eq!(expired[&"key1"].values().flatten().collect::<Vec<_>>(), vec![&"a"]);
+    /// assert_eq!(map.iter().map(|(_key, _timestamp, value)| *value).collect::<Vec<_>>(), vec!["b"]);
+    /// ```
+    pub fn with_clock<NewClock: Clock>(self, clock: NewClock) -> TimedSizedMap<K, V, NewClock> {
+        TimedSizedMap {
+            expiration: self.expiration,
+            capacity: self.capacity,
+            store: self.store,
+            clock,
         }
     }
 
@@ -381,6 +449,35 @@ impl<K, V> TimedSizedMap<K, V> {
         sorted_keys.into_iter().map(|(_sort_key, key)| key)
     }
 
+    /// Return the elements stored for the given key, if any.
+    ///
+    /// To avoid containing expired elements, call [`Self::evict_expired`] first to remove expired elements.
+    ///
+    /// # Examples
+    ///
+    /// ```rust
+    /// use std::num::NonZeroUsize;
+    /// use std::time::Duration;
+    /// use canhttp::multi::{TimedSizedMap, Timestamp};
+    ///
+    /// let mut map = TimedSizedMap::new(Duration::from_secs(10), NonZeroUsize::new(3).unwrap());
+    /// let _ = map.insert_evict(Timestamp::from_unix_epoch(Duration::from_secs(1)), "key1", "a");
+    /// let _ = map.insert_evict(Timestamp::from_unix_epoch(Duration::from_secs(2)), "key1", "b");
+    ///
+    /// assert_eq!(
+    ///     map.get("key1").unwrap().iter().map(|(_timestamp, value)| *value).collect::<Vec<_>>(),
+    ///     vec!["a", "b"]
+    /// );
+    /// assert!(map.get("key2").is_none());
+    /// ```
+    pub fn get<Q>(&self, key: &Q) -> Option<&TimedSizedVec<V>>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord + ?Sized,
+    {
+        self.store.get(key)
+    }
+
     /// Evict expired entries for the given keys.
     ///
     /// # Examples
@@ -483,3 +580,42 @@ impl<K, V> TimedSizedMap<K, V> {
         })
     }
 }
+
+impl<K, V, C: Clock> TimedSizedMap<K, V, C> {
+    /// Insert a new element at the current time of the clock and return evicted elements for **that** key.
+    ///
+    /// See [`Self::insert_evict`].
+    pub fn insert_evict_now(&mut self, key: K, value: V) -> BTreeMap<Timestamp, VecDeque<V>>
+    where
+        K: Ord,
+    {
+        let now = self.clock.now();
+        self.insert_evict(now, key, value)
+    }
+
+    /// Evict entries expired at the current time of the clock for the given keys.
+    ///
+    /// See [`Self::evict_expired`].
+    pub fn evict_expired_now<'a, Q>(
+        &mut self,
+        keys: &'a [Q],
+    ) -> BTreeMap<&'a Q, BTreeMap<Timestamp, VecDeque<V>>>
+    where
+        K: Borrow<Q> + Ord,
+        Q: Ord,
+    {
+        let now = self.clock.now();
+        self.evict_expired(keys, now)
+    }
+}
+
+/// The clock is not compared.
+impl<K: PartialEq, V: PartialEq, C> PartialEq for TimedSizedMap<K, V, C> {
+    fn eq(&self, other: &Self) -> bool {
+        self.expiration == other.expiration
+            && self.capacity == other.capacity
+            && self.store == other.store
+    }
+}
+
+impl<K: Eq, V: Eq, C> Eq for TimedSizedMap<K, V, C> {}
@@ -1,7 +1,8 @@
 //! Make multiple calls in parallel to a [`tower::Service`] and handle their multiple results.
 //! See [`parallel_call`].
 
-pub use cache::{TimedSizedMap, TimedSizedVec, Timestamp};
+pub use crate::time::Timestamp;
+pub use cache::{TimedSizedMap, TimedSizedVec};
 pub use reduce::{Reduce, ReduceWithEquality, ReduceWithThreshold, ReducedResult, ReductionError};
 
 mod cache;
@@ -51,6 +52,10 @@ use tower::{Service, ServiceExt};
 /// # Panics
 ///
 /// If two requests produced by the iterator have the same request ID.
+#[cfg_attr(
+    feature = "tracing",
+    tracing::instrument(level = "debug", skip_all, fields(num_requests))
+)]
 pub async fn parallel_call<S, I, RequestId, Request, Response, Error>(
     service: S,
     requests: I,
@@ -76,6 +81,12 @@ where
     while let Some((id, response)) = zip.next().await {
         results.insert_once(id, response);
     }
+    #[cfg(feature = "tracing")]
+    {
+        let (num_ok, num_errors) = (results.ok_results.len(), results.errors.len());
+        tracing::Span::current().record("num_requests", num_ok + num_errors);
+        tracing::debug!(num_ok, num_errors, "Parallel calls completed");
+    }
     let (_, parallel_service) = zip.into_inner();
     (parallel_service.into_inner(), results)
 }
@@ -236,6 +236,7 @@ mod reduce_with_threshold {
 mod timed_size_vec {
     use crate::multi::cache::TimedSizedVec;
     use crate::multi::tests::timestamp;
+    use crate::time::MockClock;
     use maplit::btreemap;
     use proptest::collection::vec;
     use proptest::prelude::any;
@@ -253,6 +254,29 @@ mod timed_size_vec {
         assert_eq!(vec.iter().collect::<Vec<_>>(), vec![]);
     }
 
+    #[test]
+    fn should_read_current_time_from_clock() {
+        let clock = MockClock::new(timestamp(0));
+        let mut vec = TimedSizedVec::new(Duration::from_nanos(60), NonZeroUsize::new(5).unwrap())
+            .with_clock(clock.clone());
+
+        assert_eq!(vec.insert_evict_now("a"), BTreeMap::default());
+        clock.advance(Duration::from_nanos(10));
+        assert_eq!(vec.insert_evict_now("b"), BTreeMap::default());
+
+        assert_eq!(
+            vec.iter().collect::<Vec<_>>(),
+            vec![(&timestamp(0), &"a"), (&timestamp(10), &"b")]
+        );
+
+        clock.advance(Duration::from_nanos(60));
+        assert_eq!(
+            vec.evict_expired_now(),
+            btreemap! {timestamp(0) => VecDeque::from(["a"])}
+        );
+        assert_eq!(vec.iter().collect::<Vec<_>>(), vec![(&timestamp(10), &"b")]);
+    }
+
     #[test]
     fn should_evict_when_too_many() {
         let mut vec: TimedSizedVec<&str> =
@@ -439,6 +463,7 @@ mod timed_sized_map {
     use crate::multi::cache::TimedSizedMap;
     use crate::multi::tests::timestamp;
     use crate::multi::TimedSizedVec;
+    use crate::time::MockClock;
     use itertools::Itertools;
     use maplit::btreemap;
     use std::collections::{BTreeMap, VecDeque};
@@ -453,6 +478,27 @@ mod timed_sized_map {
         assert_eq!(map.iter().next(), None);
     }
 
+    #[test]
+    fn should_read_current_time_from_clock() {
+        let clock = MockClock::new(timestamp(0));
+        let mut map = TimedSizedMap::new(Duration::from_nanos(60), NonZeroUsize::new(5).unwrap())
+            .with_clock(clock.clone());
+
+        assert_eq!(map.insert_evict_now(Keys::Key1, "a"), BTreeMap::default());
+        clock.advance(Duration::from_nanos(10));
+        assert_eq!(map.insert_evict_now(Keys::Key2, "b"), BTreeMap::default());
+
+        clock.advance(Duration::from_nanos(60));
+        assert_eq!(
+            map.evict_expired_now(&[Keys::Key1, Keys::Key2]),
+            btreemap! {&Keys::Key1 => btreemap! {timestamp(0) => VecDeque::from(["a"])}}
+        );
+        assert_eq!(
+            map.iter().collect::<Vec<_>>(),
+            vec![(&Keys::Key2, &timestamp(10), &"b")]
+        );
+    }
+
     #[test]
     fn should_be_stable_when_sorting() {
         let mut map = TimedSizedMap::new(Duration::from_nanos(60), NonZeroUsize::new(5).unwrap());
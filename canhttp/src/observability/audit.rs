@@ -0,0 +1,529 @@
+use crate::observability::{
+    ContextualResponseObserver, ObservabilityLayer, ObservableError, ObservableRequest,
+    ObservableResponse, ObservationContext, RequestObserver, WithObservationContext,
+};
+use crate::time::{Clock, IcClock, Timestamp};
+use candid::{CandidType, Deserialize, Principal};
+use ic_stable_structures::storable::Bound;
+use ic_stable_structures::{Memory, StableCell, StableVec, Storable};
+use std::borrow::Cow;
+use std::cell::RefCell;
+use std::fmt;
+use std::rc::Rc;
+
+const MAX_METHOD_LEN: usize = 16;
+const MAX_HOST_LEN: usize = 253;
+const MAX_ERROR_KIND_LEN: usize = 64;
+// Upper bound on the Candid encoding of an entry whose strings are truncated.
+const MAX_ENTRY_SIZE: u32 = 1_024;
+const UNKNOWN: &str = "unknown";
+
+/// Audit log of HTTPs outcalls, kept in stable memory so that it survives canister upgrades.
+///
+/// The log is a ring buffer with a fixed capacity: once full, appending an entry evicts the oldest one.
+/// Entries are identified by their index, which starts at 0 and increases by one for each appended entry,
+/// so that the log can be queried page by page (see [`StableAuditLog::page`]).
+///
+/// Entries are appended by the observers of an [`ObservabilityLayer`] (see [`StableAuditLog::observability_layer`])
+/// and record the time at which the outcome of the request was received, the caller of the canister method,
+/// the HTTP method, the host, the status code or the kind of error, the cycles attached and the response size.
+///
+/// The capacity and the index of the next entry are kept in a [`StableCell`],
+/// while the entries are kept in a [`StableVec`], each in its own memory.
+/// All clones share the same log.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{observability::StableAuditLog, time::MockClock, Client};
+/// use candid::Principal;
+/// use ic_stable_structures::VectorMemory;
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// // Inside a canister, use virtual memories from `ic_stable_structures::memory_manager` instead.
+/// let audit_log = StableAuditLog::init(VectorMemory::default(), VectorMemory::default(), 10_000)
+///     // Only needed outside a canister: by default, the caller is given by `ic_cdk::api::msg_caller`
+///     // and the time by `ic_cdk::api::time`.
+///     .with_caller(Principal::anonymous)
+///     .with_clock(MockClock::default());
+///
+/// let mut service = ServiceBuilder::new()
+///     .layer(audit_log.observability_layer())
+///     .service(Client::new_with_box_error());
+///
+/// let _ = service.ready().await.unwrap();
+///
+/// // E.g., in a query endpoint of the canister:
+/// let page = audit_log.page(None, 100);
+/// assert!(page.entries.is_empty());
+/// assert_eq!(page.next, None);
+/// # Ok(())
+/// # }
+/// ```
+pub struct StableAuditLog<M: Memory, C = IcClock> {
+    storage: Rc<RefCell<AuditLogStorage<M>>>,
+    clock: C,
+    caller: fn() -> Principal,
+}
+
+struct AuditLogStorage<M: Memory> {
+    metadata: StableCell<AuditLogMetadata, M>,
+    entries: StableVec<AuditLogEntry, M>,
+}
+
+impl<M: Memory> StableAuditLog<M> {
+    /// Initialize an audit log holding up to `capacity` entries, whose metadata is kept
+    /// in `metadata_memory` and whose entries are kept in `entries_memory`.
+    ///
+    /// If the memories already contain an audit log, e.g. after a canister upgrade,
+    /// its entries and its capacity are kept and the given `capacity` is ignored.
+    ///
+    /// # Panics
+    ///
+    /// If `capacity` is zero, if `capacity` entries cannot be addressed in stable memory,
+    /// or if the memories contain something else than an audit log.
+    pub fn init(metadata_memory: M, entries_memory: M, capacity: u64) -> Self {
+        assert!(capacity > 0, "ERROR: audit log capacity must be positive");
+        assert!(
+            capacity.checked_mul(MAX_ENTRY_SIZE as u64).is_some(),
+            "ERROR: audit log capacity is too large"
+        );
+        let entries =
+            StableVec::init(entries_memory).expect("ERROR: memory does not contain an audit log");
+        let metadata = StableCell::init(
+            metadata_memory,
+            AuditLogMetadata {
+                capacity,
+                next_index: 0,
+            },
+        )
+        .expect("ERROR: memory does not contain an audit log");
+        Self {
+            storage: Rc::new(RefCell::new(AuditLogStorage { metadata, entries })),
+            clock: IcClock,
+            caller: ic_cdk::api::msg_caller,
+        }
+    }
+}
+
+impl<M: Memory, C> StableAuditLog<M, C> {
+    /// Use the given clock to timestamp the entries, instead of the time given by the Internet Computer.
+    pub fn with_clock<D: Clock>(self, clock: D) -> StableAuditLog<M, D> {
+        StableAuditLog {
+            storage: self.storage,
+            clock,
+            caller: self.caller,
+        }
+    }
+
+    /// Use the given function to determine the caller recorded in the entries,
+    /// instead of the caller of the current canister method.
+    pub fn with_caller(mut self, caller: fn() -> Principal) -> Self {
+        self.caller = caller;
+        self
+    }
+
+    /// Maximum number of entries held by the log.
+    pub fn capacity(&self) -> u64 {
+        self.metadata().capacity
+    }
+
+    /// Number of entries currently held by the log.
+    pub fn len(&self) -> u64 {
+        self.storage.borrow().entries.len()
+    }
+
+    /// Whether the log holds no entries.
+    pub fn is_empty(&self) -> bool {
+        self.storage.borrow().entries.is_empty()
+    }
+
+    /// Index of the oldest entry held by the log.
+    pub fn first_index(&self) -> u64 {
+        let metadata = self.metadata();
+        metadata.next_index.saturating_sub(metadata.capacity)
+    }
+
+    /// Index of the next entry to be appended to the log.
+    pub fn next_index(&self) -> u64 {
+        self.metadata().next_index
+    }
+
+    /// Append an entry to the log, evicting the oldest entry if the log is full.
+    ///
+    /// Entries should be appended in chronological order for [`StableAuditLog::first_index_since`]
+    /// to be meaningful, which is the case for the entries appended by the observers of this log.
+    pub fn append(&self, entry: &AuditLogEntry) {
+        let mut storage = self.storage.borrow_mut();
+        let metadata = *storage.metadata.get();
+        let slot = metadata.next_index % metadata.capacity;
+        if slot < storage.entries.len() {
+            storage.entries.set(slot, entry);
+        } else {
+            storage
+                .entries
+                .push(entry)
+                .expect("ERROR: failed to grow memory for the audit log");
+        }
+        storage
+            .metadata
+            .set(AuditLogMetadata {
+                next_index: metadata.next_index + 1,
+                ..metadata
+            })
+            .expect("BUG: audit log metadata has a fixed size");
+    }
+
+    /// Entry with the given index, if still held by the log.
+    pub fn get(&self, index: u64) -> Option<AuditLogEntry> {
+        let storage = self.storage.borrow();
+        let metadata = storage.metadata.get();
+        if index < metadata.next_index.saturating_sub(metadata.capacity)
+            || index >= metadata.next_index
+        {
+            return None;
+        }
+        storage.entries.get(index % metadata.capacity)
+    }
+
+    /// Up to `limit` entries, from the oldest to the newest, starting at the given index.
+    ///
+    /// If no index is given, or if the entry with that index was already evicted,
+    /// the page starts with the oldest entry held by the log.
+    /// The next page can be retrieved by starting at [`AuditLogPage::next`].
+    pub fn page(&self, start: Option<u64>, limit: usize) -> AuditLogPage {
+        let first_index = self.first_index();
+        let next_index = self.next_index();
+        let start = start.unwrap_or(first_index).max(first_index);
+        let end = start
+            .saturating_add(limit as u64)
+            .min(next_index)
+            .max(start);
+        AuditLogPage {
+            start,
+            entries: (start..end)
+                .map(|index| {
+                    self.get(index)
+                        .expect("BUG: entry should be held by the log")
+                })
+                .collect(),
+            next: Some(end).filter(|end| *end < next_index),
+        }
+    }
+
+    /// Index of the oldest entry held by the log whose timestamp is at least `since`,
+    /// or [`StableAuditLog::next_index`] if there is no such entry.
+    ///
+    /// Entries appended by the observers of this log are timestamped when they are appended,
+    /// so that they are sorted by timestamp, even when several HTTPs outcalls are in flight at the same time.
+    /// Use with [`StableAuditLog::page`] to query the entries since a given time.
+    pub fn first_index_since(&self, since: Timestamp) -> u64 {
+        let (mut low, mut high) = (self.first_index(), self.next_index());
+        while low < high {
+            let middle = low + (high - low) / 2;
+            let entry = self
+                .get(middle)
+                .expect("BUG: entry should be held by the log");
+            if entry.timestamp < since {
+                low = middle + 1;
+            } else {
+                high = middle;
+            }
+        }
+        low
+    }
+
+    fn metadata(&self) -> AuditLogMetadata {
+        *self.storage.borrow().metadata.get()
+    }
+}
+
+impl<M: Memory, C: Clock + Clone> StableAuditLog<M, C> {
+    /// Observer recording the request data, to be used with [`ObservabilityLayer::on_request`].
+    pub fn request_observer(&self) -> AuditRequestObserver {
+        AuditRequestObserver {
+            caller: self.caller,
+        }
+    }
+
+    /// Observer appending an entry for each response, to be used with [`ObservabilityLayer::on_response`].
+    pub fn response_observer(&self) -> AuditResponseObserver<M, C> {
+        AuditResponseObserver(self.clone())
+    }
+
+    /// Observer appending an entry for each error, to be used with [`ObservabilityLayer::on_error`].
+    pub fn error_observer(&self) -> AuditErrorObserver<M, C> {
+        AuditErrorObserver(self.clone())
+    }
+
+    /// Create an [`ObservabilityLayer`] appending an entry to this log for each response or error.
+    pub fn observability_layer(&self) -> AuditObservabilityLayer<M, C> {
+        ObservabilityLayer::new()
+            .on_request(self.request_observer())
+            .on_response(self.response_observer())
+            .on_error(self.error_observer())
+            .with_observation_context()
+    }
+}
+
+impl<M: Memory, C: Clone> Clone for StableAuditLog<M, C> {
+    fn clone(&self) -> Self {
+        Self {
+            storage: Rc::clone(&self.storage),
+            clock: self.clock.clone(),
+            caller: self.caller,
+        }
+    }
+}
+
+impl<M: Memory, C: fmt::Debug> fmt::Debug for StableAuditLog<M, C> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let metadata = self.metadata();
+        f.debug_struct("StableAuditLog")
+            .field("capacity", &metadata.capacity)
+            .field("next_index", &metadata.next_index)
+            .field("clock", &self.clock)
+            .finish_non_exhaustive()
+    }
+}
+
+/// [`ObservabilityLayer`] created by [`StableAuditLog::observability_layer`].
+pub type AuditObservabilityLayer<M, C> = ObservabilityLayer<
+    AuditRequestObserver,
+    AuditResponseObserver<M, C>,
+    AuditErrorObserver<M, C>,
+    IcClock,
+    WithObservationContext,
+>;
+
+/// A page of entries of a [`StableAuditLog`], see [`StableAuditLog::page`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditLogPage {
+    /// Index of the first entry of the page.
+    pub start: u64,
+    /// Entries, from the oldest to the newest.
+    pub entries: Vec<AuditLogEntry>,
+    /// Index of the first entry of the next page, if any.
+    pub next: Option<u64>,
+}
+
+/// An entry of a [`StableAuditLog`], recording a single HTTPs outcall.
+///
+/// The method, the host and the kind of error are truncated to respectively 16, 253 and 64 bytes.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditLogEntry {
+    /// Time at which the response or the error was received and the entry appended.
+    pub timestamp: Timestamp,
+    /// Caller of the canister method that made the request.
+    pub caller: Principal,
+    /// HTTP method of the request (e.g. `GET`).
+    pub method: String,
+    /// Host targeted by the request.
+    pub host: String,
+    /// Outcome of the request.
+    pub outcome: AuditOutcome,
+    /// Number of cycles attached to the request, if known.
+    pub cycles_attached: Option<u128>,
+    /// Size of the response in bytes, if known.
+    pub response_size: Option<u64>,
+}
+
+/// Outcome of an HTTPs outcall recorded in an [`AuditLogEntry`].
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Deserialize)]
+pub enum AuditOutcome {
+    /// A response was received.
+    Response {
+        /// HTTP status code of the response.
+        status: u16,
+    },
+    /// The request failed.
+    Error {
+        /// Kind of error, see [`ObservableError::error_kind`].
+        kind: String,
+    },
+}
+
+/// Candid encoding of an [`AuditLogEntry`] in stable memory.
+#[derive(CandidType, Deserialize)]
+struct StoredAuditLogEntry {
+    timestamp: u64,
+    caller: Principal,
+    method: String,
+    host: String,
+    outcome: AuditOutcome,
+    cycles_attached: Option<u128>,
+    response_size: Option<u64>,
+}
+
+impl Storable for AuditLogEntry {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let outcome = match &self.outcome {
+            AuditOutcome::Response { status } => AuditOutcome::Response { status: *status },
+            AuditOutcome::Error { kind } => AuditOutcome::Error {
+                kind: truncate(kind, MAX_ERROR_KIND_LEN).to_string(),
+            },
+        };
+        let stored = StoredAuditLogEntry {
+            timestamp: self.timestamp.as_nanos_since_unix_epoch(),
+            caller: self.caller,
+            method: truncate(&self.method, MAX_METHOD_LEN).to_string(),
+            host: truncate(&self.host, MAX_HOST_LEN).to_string(),
+            outcome,
+            cycles_attached: self.cycles_attached,
+            response_size: self.response_size,
+        };
+        Cow::Owned(candid::encode_one(stored).expect("BUG: failed to encode audit log entry"))
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let stored: StoredAuditLogEntry =
+            candid::decode_one(&bytes).expect("BUG: failed to decode audit log entry");
+        Self {
+            timestamp: Timestamp::from_nanos_since_unix_epoch(stored.timestamp),
+            caller: stored.caller,
+            method: stored.method,
+            host: stored.host,
+            outcome: stored.outcome,
+            cycles_attached: stored.cycles_attached,
+            response_size: stored.response_size,
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: MAX_ENTRY_SIZE,
+        is_fixed_size: false,
+    };
+}
+
+/// Capacity of a [`StableAuditLog`] and index of its next entry.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+struct AuditLogMetadata {
+    capacity: u64,
+    next_index: u64,
+}
+
+impl Storable for AuditLogMetadata {
+    fn to_bytes(&self) -> Cow<'_, [u8]> {
+        let mut bytes = Vec::with_capacity(16);
+        bytes.extend_from_slice(&self.capacity.to_le_bytes());
+        bytes.extend_from_slice(&self.next_index.to_le_bytes());
+        Cow::Owned(bytes)
+    }
+
+    fn from_bytes(bytes: Cow<[u8]>) -> Self {
+        let (capacity, next_index) = bytes.split_at(8);
+        Self {
+            capacity: u64::from_le_bytes(capacity.try_into().expect("BUG: expected 8 bytes")),
+            next_index: u64::from_le_bytes(next_index.try_into().expect("BUG: expected 8 bytes")),
+        }
+    }
+
+    const BOUND: Bound = Bound::Bounded {
+        max_size: 16,
+        is_fixed_size: true,
+    };
+}
+
+/// Data extracted from a request by [`AuditRequestObserver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct AuditRequestData {
+    caller: Principal,
+    method: String,
+    host: String,
+}
+
+impl AuditRequestData {
+    fn into_entry(
+        self,
+        timestamp: Timestamp,
+        outcome: AuditOutcome,
+        cycles_attached: Option<u128>,
+        response_size: Option<u64>,
+    ) -> AuditLogEntry {
+        AuditLogEntry {
+            timestamp,
+            caller: self.caller,
+            method: self.method,
+            host: self.host,
+            outcome,
+            cycles_attached,
+            response_size,
+        }
+    }
+}
+
+/// Request observer of [`StableAuditLog`], see [`StableAuditLog::request_observer`].
+#[derive(Clone, Debug)]
+pub struct AuditRequestObserver {
+    caller: fn() -> Principal,
+}
+
+impl<Request: ObservableRequest> RequestObserver<Request> for AuditRequestObserver {
+    type ObservableRequestData = AuditRequestData;
+
+    fn observe_request(&self, request: &Request) -> Self::ObservableRequestData {
+        AuditRequestData {
+            caller: (self.caller)(),
+            method: request.method().unwrap_or(UNKNOWN).to_string(),
+            host: request.host().unwrap_or(UNKNOWN).to_string(),
+        }
+    }
+}
+
+/// Response observer of [`StableAuditLog`], see [`StableAuditLog::response_observer`].
+#[derive(Clone, Debug)]
+pub struct AuditResponseObserver<M: Memory, C>(StableAuditLog<M, C>);
+
+impl<M: Memory, C: Clock, Response: ObservableResponse>
+    ContextualResponseObserver<AuditRequestData, Response> for AuditResponseObserver<M, C>
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: AuditRequestData,
+        response: &Response,
+        context: &ObservationContext,
+    ) {
+        self.0.append(&request_data.into_entry(
+            self.0.clock.now(),
+            AuditOutcome::Response {
+                status: response.status_code(),
+            },
+            context.cycles_attached.or(response.cycles_attached()),
+            response.response_size(),
+        ));
+    }
+}
+
+/// Error observer of [`StableAuditLog`], see [`StableAuditLog::error_observer`].
+#[derive(Clone, Debug)]
+pub struct AuditErrorObserver<M: Memory, C>(StableAuditLog<M, C>);
+
+impl<M: Memory, C: Clock, Error: ObservableError>
+    ContextualResponseObserver<AuditRequestData, Error> for AuditErrorObserver<M, C>
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: AuditRequestData,
+        error: &Error,
+        context: &ObservationContext,
+    ) {
+        self.0.append(&request_data.into_entry(
+            self.0.clock.now(),
+            AuditOutcome::Error {
+                kind: error.error_kind(),
+            },
+            context.cycles_attached,
+            None,
+        ));
+    }
+}
+
+fn truncate(value: &str, max_len: usize) -> &str {
+    let mut end = value.len().min(max_len);
+    while !value.is_char_boundary(end) {
+        end -= 1;
+    }
+    &value[..end]
+}
@@ -0,0 +1,438 @@
+use crate::observability::{
+    ContextualResponseObserver, ObservabilityLayer, ObservableError, ObservableRequest,
+    ObservableResponse, ObservationContext, RequestObserver, WithObservationContext,
+};
+use crate::time::IcClock;
+use std::collections::BTreeMap;
+use std::fmt::Write;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+const DEFAULT_RESPONSE_SIZE_BUCKETS: [u64; 7] = [
+    1_000, 10_000, 50_000, 100_000, 500_000, 1_000_000, 2_000_000,
+];
+const DEFAULT_LATENCY_BUCKETS_MILLIS: [u64; 8] =
+    [250, 500, 1_000, 2_000, 5_000, 10_000, 30_000, 60_000];
+const UNKNOWN_HOST: &str = "unknown";
+
+/// Metrics about HTTPs outcalls, collected by an [`ObservabilityLayer`]
+/// and rendered in the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+///
+/// The following metrics are collected for each host, and labeled with the host and the configured labels:
+/// * `canhttp_requests_total`: number of requests;
+/// * `canhttp_responses_total`: number of responses, by status class (e.g. `2xx`);
+/// * `canhttp_errors_total`: number of errors, by kind (see [`ObservableError`]);
+/// * `canhttp_cycles_attached_total`: number of cycles attached to the requests,
+///   when known (see [`ObservationContext::cycles_attached`]);
+/// * `canhttp_response_size_bytes`: histogram of the response sizes;
+/// * `canhttp_latency_seconds`: histogram of the latencies, when measured
+///   (see [`ObservabilityLayer::with_clock`]).
+///
+/// All clones share the same metrics, unless reconfigured: the `with_*` methods start new empty metrics,
+/// since metrics collected with different labels or buckets cannot be merged.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{observability::OutcallMetrics, time::IcClock, Client, cycles::{ChargeMyself, CyclesAccountingServiceBuilder}};
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let metrics = OutcallMetrics::new().with_label("canister", "my_canister");
+///
+/// let mut service = ServiceBuilder::new()
+///     .cycles_accounting(34, ChargeMyself::default())
+///     .layer(metrics.observability_layer().with_clock(IcClock))
+///     .service(Client::new_with_box_error());
+///
+/// let _ = service.ready().await.unwrap();
+///
+/// // E.g., in the `http_request` query endpoint of the canister:
+/// let body = metrics.encode_prometheus();
+/// assert!(body.contains("# TYPE canhttp_requests_total counter"));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct OutcallMetrics {
+    labels: Vec<(String, String)>,
+    response_size_buckets: Vec<u64>,
+    // Upper bounds of the latency buckets in nanoseconds.
+    latency_buckets: Vec<u64>,
+    hosts: Arc<Mutex<BTreeMap<String, HostMetrics>>>,
+}
+
+impl Default for OutcallMetrics {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl OutcallMetrics {
+    /// Create new empty metrics.
+    ///
+    /// By default, the response size histogram has buckets ranging from 1kB to 2MB
+    /// and the latency histogram has buckets ranging from 250ms to 1min.
+    pub fn new() -> Self {
+        Self {
+            labels: Vec::new(),
+            response_size_buckets: DEFAULT_RESPONSE_SIZE_BUCKETS.to_vec(),
+            latency_buckets: DEFAULT_LATENCY_BUCKETS_MILLIS
+                .into_iter()
+                .map(|millis| millis * 1_000_000)
+                .collect(),
+            hosts: Arc::new(Mutex::new(BTreeMap::new())),
+        }
+    }
+
+    /// Add a label with a constant value to all metrics.
+    pub fn with_label(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.labels.push((name.into(), value.into()));
+        self.reset()
+    }
+
+    /// Use the given upper bounds (in bytes) for the buckets of the response size histogram.
+    pub fn with_response_size_buckets(mut self, mut buckets: Vec<u64>) -> Self {
+        buckets.sort_unstable();
+        buckets.dedup();
+        self.response_size_buckets = buckets;
+        self.reset()
+    }
+
+    /// Use the given upper bounds for the buckets of the latency histogram.
+    pub fn with_latency_buckets(mut self, buckets: Vec<Duration>) -> Self {
+        let mut buckets: Vec<u64> = buckets.into_iter().map(duration_nanos).collect();
+        buckets.sort_unstable();
+        buckets.dedup();
+        self.latency_buckets = buckets;
+        self.reset()
+    }
+
+    fn reset(self) -> Self {
+        Self {
+            hosts: Arc::default(),
+            ..self
+        }
+    }
+
+    /// Observer counting requests, to be used with [`ObservabilityLayer::on_request`].
+    pub fn request_observer(&self) -> MetricsRequestObserver {
+        MetricsRequestObserver(self.clone())
+    }
+
+    /// Observer recording responses, to be used with [`ObservabilityLayer::on_response`].
+    pub fn response_observer(&self) -> MetricsResponseObserver {
+        MetricsResponseObserver(self.clone())
+    }
+
+    /// Observer counting errors, to be used with [`ObservabilityLayer::on_error`].
+    pub fn error_observer(&self) -> MetricsErrorObserver {
+        MetricsErrorObserver(self.clone())
+    }
+
+    /// Create an [`ObservabilityLayer`] collecting these metrics.
+    pub fn observability_layer(
+        &self,
+    ) -> ObservabilityLayer<
+        MetricsRequestObserver,
+        MetricsResponseObserver,
+        MetricsErrorObserver,
+        IcClock,
+        WithObservationContext,
+    > {
+        ObservabilityLayer::new()
+            .on_request(self.request_observer())
+            .on_response(self.response_observer())
+            .on_error(self.error_observer())
+            .with_observation_context()
+    }
+
+    /// Render the metrics in the Prometheus text format.
+    pub fn encode_prometheus(&self) -> String {
+        let hosts = self.hosts.lock().unwrap();
+        let mut encoder = PrometheusEncoder {
+            labels: &self.labels,
+            output: String::new(),
+        };
+
+        encoder.header(
+            "canhttp_requests_total",
+            "counter",
+            "Number of HTTPs outcall requests.",
+        );
+        for (host, metrics) in hosts.iter() {
+            encoder.sample("canhttp_requests_total", host, &[], metrics.requests);
+        }
+
+        encoder.header(
+            "canhttp_responses_total",
+            "counter",
+            "Number of HTTPs outcall responses by status class.",
+        );
+        for (host, metrics) in hosts.iter() {
+            for (status_class, count) in &metrics.responses {
+                encoder.sample(
+                    "canhttp_responses_total",
+                    host,
+                    &[("status_class", status_class)],
+                    count,
+                );
+            }
+        }
+
+        encoder.header(
+            "canhttp_errors_total",
+            "counter",
+            "Number of HTTPs outcall errors by kind.",
+        );
+        for (host, metrics) in hosts.iter() {
+            for (kind, count) in &metrics.errors {
+                encoder.sample("canhttp_errors_total", host, &[("error", kind)], count);
+            }
+        }
+
+        encoder.header(
+            "canhttp_cycles_attached_total",
+            "counter",
+            "Number of cycles attached to HTTPs outcalls.",
+        );
+        for (host, metrics) in hosts.iter() {
+            encoder.sample(
+                "canhttp_cycles_attached_total",
+                host,
+                &[],
+                metrics.cycles_attached,
+            );
+        }
+
+        encoder.header(
+            "canhttp_response_size_bytes",
+            "histogram",
+            "Size of HTTPs outcall responses in bytes.",
+        );
+        for (host, metrics) in hosts.iter() {
+            encoder.histogram(
+                "canhttp_response_size_bytes",
+                host,
+                &metrics.response_sizes,
+                self.response_size_buckets
+                    .iter()
+                    .map(|bound| bound.to_string()),
+                metrics.response_sizes.sum.to_string(),
+            );
+        }
+
+        encoder.header(
+            "canhttp_latency_seconds",
+            "histogram",
+            "Latency of HTTPs outcalls in seconds.",
+        );
+        for (host, metrics) in hosts.iter() {
+            encoder.histogram(
+                "canhttp_latency_seconds",
+                host,
+                &metrics.latencies,
+                self.latency_buckets
+                    .iter()
+                    .map(|bound| seconds(*bound as u128)),
+                seconds(metrics.latencies.sum),
+            );
+        }
+
+        encoder.output
+    }
+
+    fn record<F: FnOnce(&mut HostMetrics)>(&self, host: &str, update: F) {
+        let mut hosts = self.hosts.lock().unwrap();
+        update(hosts.entry(host.to_string()).or_default());
+    }
+
+    fn record_outcome(
+        &self,
+        host: &str,
+        context: &ObservationContext,
+        cycles_attached: Option<u128>,
+    ) {
+        self.record(host, |metrics| {
+            if let Some(cycles) = context.cycles_attached.or(cycles_attached) {
+                metrics.cycles_attached = metrics.cycles_attached.saturating_add(cycles);
+            }
+            if let Some(elapsed) = context.elapsed {
+                metrics
+                    .latencies
+                    .observe(&self.latency_buckets, duration_nanos(elapsed));
+            }
+        });
+    }
+}
+
+/// Data extracted from a request by [`MetricsRequestObserver`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct MetricsRequestData {
+    host: String,
+}
+
+/// Request observer of [`OutcallMetrics`], see [`OutcallMetrics::request_observer`].
+#[derive(Clone, Debug)]
+pub struct MetricsRequestObserver(OutcallMetrics);
+
+impl<Request: ObservableRequest> RequestObserver<Request> for MetricsRequestObserver {
+    type ObservableRequestData = MetricsRequestData;
+
+    fn observe_request(&self, request: &Request) -> Self::ObservableRequestData {
+        let host = request.host().unwrap_or(UNKNOWN_HOST).to_string();
+        self.0.record(&host, |metrics| metrics.requests += 1);
+        MetricsRequestData { host }
+    }
+}
+
+/// Response observer of [`OutcallMetrics`], see [`OutcallMetrics::response_observer`].
+#[derive(Clone, Debug)]
+pub struct MetricsResponseObserver(OutcallMetrics);
+
+impl<Response: ObservableResponse> ContextualResponseObserver<MetricsRequestData, Response>
+    for MetricsResponseObserver
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: MetricsRequestData,
+        response: &Response,
+        context: &ObservationContext,
+    ) {
+        let status_class = format!("{}xx", response.status_code() / 100);
+        self.0.record(&request_data.host, |metrics| {
+            *metrics.responses.entry(status_class).or_default() += 1;
+            if let Some(size) = response.response_size() {
+                metrics
+                    .response_sizes
+                    .observe(&self.0.response_size_buckets, size);
+            }
+        });
+        self.0
+            .record_outcome(&request_data.host, context, response.cycles_attached());
+    }
+}
+
+/// Error observer of [`OutcallMetrics`], see [`OutcallMetrics::error_observer`].
+#[derive(Clone, Debug)]
+pub struct MetricsErrorObserver(OutcallMetrics);
+
+impl<Error: ObservableError> ContextualResponseObserver<MetricsRequestData, Error>
+    for MetricsErrorObserver
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: MetricsRequestData,
+        error: &Error,
+        context: &ObservationContext,
+    ) {
+        let kind = error.error_kind();
+        self.0.record(&request_data.host, |metrics| {
+            *metrics.errors.entry(kind).or_default() += 1;
+        });
+        self.0.record_outcome(&request_data.host, context, None);
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+struct HostMetrics {
+    requests: u64,
+    responses: BTreeMap<String, u64>,
+    errors: BTreeMap<String, u64>,
+    cycles_attached: u128,
+    response_sizes: Histogram,
+    latencies: Histogram,
+}
+
+#[derive(Clone, Debug, Default)]
+struct Histogram {
+    // Number of observations per bucket (not cumulative), where the last bucket is `+Inf`.
+    buckets: Vec<u64>,
+    sum: u128,
+    count: u64,
+}
+
+impl Histogram {
+    fn observe(&mut self, bounds: &[u64], value: u64) {
+        if self.buckets.len() <= bounds.len() {
+            self.buckets.resize(bounds.len() + 1, 0);
+        }
+        let bucket = bounds.partition_point(|bound| *bound < value);
+        self.buckets[bucket] += 1;
+        self.sum = self.sum.saturating_add(value as u128);
+        self.count += 1;
+    }
+}
+
+struct PrometheusEncoder<'a> {
+    labels: &'a [(String, String)],
+    output: String,
+}
+
+impl PrometheusEncoder<'_> {
+    fn header(&mut self, name: &str, metric_type: &str, help: &str) {
+        writeln!(self.output, "# HELP {name} {help}").unwrap();
+        writeln!(self.output, "# TYPE {name} {metric_type}").unwrap();
+    }
+
+    fn sample<V: std::fmt::Display>(
+        &mut self,
+        name: &str,
+        host: &str,
+        extra_labels: &[(&str, &str)],
+        value: V,
+    ) {
+        let mut labels = format!("host=\"{}\"", escape_label_value(host));
+        for (label_name, label_value) in self
+            .labels
+            .iter()
+            .map(|(name, value)| (name.as_str(), value.as_str()))
+            .chain(extra_labels.iter().copied())
+        {
+            write!(
+                labels,
+                ",{label_name}=\"{}\"",
+                escape_label_value(label_value)
+            )
+            .unwrap();
+        }
+        writeln!(self.output, "{name}{{{labels}}} {value}").unwrap();
+    }
+
+    fn histogram(
+        &mut self,
+        name: &str,
+        host: &str,
+        histogram: &Histogram,
+        bounds: impl Iterator<Item = String>,
+        sum: String,
+    ) {
+        let bucket_name = format!("{name}_bucket");
+        let mut cumulative_count = 0;
+        for (index, bound) in bounds
+            .chain(std::iter::once("+Inf".to_string()))
+            .enumerate()
+        {
+            cumulative_count += histogram.buckets.get(index).copied().unwrap_or_default();
+            self.sample(&bucket_name, host, &[("le", &bound)], cumulative_count);
+        }
+        self.sample(&format!("{name}_sum"), host, &[], sum);
+        self.sample(&format!("{name}_count"), host, &[], histogram.count);
+    }
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn seconds(nanos: u128) -> String {
+    (nanos as f64 / 1e9).to_string()
+}
+
+fn duration_nanos(duration: Duration) -> u64 {
+    u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX)
+}
@@ -5,18 +5,24 @@
 //! The reason for not using this middleware directly is it cannot be used inside a canister:
 //! 1. It measures the latency of a call by calling
 //!    [`Instant::now`](https://github.com/tower-rs/tower-http/blob/469bdac3193ed22da9ea524a454d8cda93ffa0d5/tower-http/src/trace/service.rs#L302),
-//!    which will fail when run from a canister.
+//!    which will fail when run from a canister. Instead, this middleware measures the latency of a call
+//!    with a [`Clock`], see [`ObservabilityLayer::with_clock`].
 //! 2. It can deal with streaming responses, which is unnecessary for HTTPs outcalls,
 //!    since the response is available to a canister at once. This flexibility brings some complexity
 //!    (body can only be fetched asynchronously, end of stream errors, etc.) which is not useful in a canister environment.
 //!
 //! # Examples
 //!
+//! Metrics about HTTPs outcalls (number of requests, responses and errors, cycles attached, response sizes and latencies)
+//! are readily available with [`OutcallMetrics`], which can be served in the Prometheus text format.
+//! The number of instructions used by the stages of the service stack (e.g. converting responses)
+//! can be measured with a [`ProfilingLayer`], e.g. to find out which stage uses up the instruction limit.
+//!
 //! To add a basic observability layer, for example tracking the number of request and responses/errors inside a canister:
 //!
 //! ```rust
 //! use canhttp::{IcError, observability::ObservabilityLayer};
-//! use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument as IcHttpRequest, HttpResponse as IcHttpResponse};
+//! use ic_cdk::management_canister::{HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse};
 //! use tower::{Service, ServiceBuilder, ServiceExt};
 //! use std::cell::RefCell;
 //!
@@ -76,7 +82,7 @@
 //! The previous example can be refined by extracting request data (such as the request URL) to observe the responses/errors:
 //! ```rust
 //! use canhttp::{IcError, observability::ObservabilityLayer};
-//! use ic_cdk::api::management_canister::http_request::{CanisterHttpRequestArgument as IcHttpRequest, HttpResponse as IcHttpResponse};
+//! use ic_cdk::management_canister::{HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse};
 //! use maplit::btreemap;
 //! use tower::{Service, ServiceBuilder, ServiceExt};
 //! use std::cell::RefCell;
@@ -157,13 +163,92 @@
 //! # }
 //! ```
 //!
+//! The number of cycles actually spent for an HTTPs outcall can be observed from the response
+//! when the [`ReportCyclesUsageLayer`](crate::ReportCyclesUsageLayer) is added on top of the [`Client`](crate::Client),
+//! see [`CyclesUsageResponseExtension`]:
+//! ```rust
+//! use canhttp::{CyclesUsageResponseExtension, observability::ObservabilityLayer};
+//!
+//! let layer = ObservabilityLayer::new()
+//!     .on_response(|_req_data: (), response: &http::Response<Vec<u8>>| {
+//!         if let Some(cycles) = response.get_cycles_usage() {
+//!             ic_cdk::println!("Consumed {} cycles", cycles.consumed());
+//!         }
+//!     });
+//! ```
+//!
+//! Observers can also be given an [`ObservationContext`] containing the latency of the call,
+//! the retry attempt and the number of cycles attached to the request,
+//! by configuring the layer with [`ObservabilityLayer::with_observation_context`].
+//! Observers must then implement [`ContextualResponseObserver`], e.g. closures wrapped in [`ObserveWithContext`]
+//! or any [`ResponseObserver`] wrapped in [`IgnoreContext`].
+//! The latency is only measured when the layer is given a clock, e.g. [`IcClock`] inside a canister:
+//! ```rust
+//! use canhttp::{IcError, IcHttpRequestWithCycles, IcHttpResponseWithCycles};
+//! use canhttp::observability::{
+//!     IgnoreContext, ObservabilityLayer, ObservationContext, ObserveWithContext,
+//! };
+//! use canhttp::time::IcClock;
+//!
+//! let layer = ObservabilityLayer::new()
+//!     .on_response(ObserveWithContext(
+//!         |_req_data: (), _response: &IcHttpResponseWithCycles, context: &ObservationContext| {
+//!             ic_cdk::println!(
+//!                 "Attempt {} with {:?} cycles took {:?}",
+//!                 context.retry_attempt,
+//!                 context.cycles_attached,
+//!                 context.elapsed
+//!             );
+//!         },
+//!     ))
+//!     .on_error(IgnoreContext(|_req_data: (), error: &IcError| {
+//!         ic_cdk::println!("Error {error}");
+//!     }))
+//!     .with_clock(IcClock)
+//!     .with_observation_context();
+//! ```
+//!
 //! [`Service`]: tower::Service
 //! [`tower_http`]: https://crates.io/crates/tower-http
 
+#[cfg(feature = "audit")]
+pub use audit::{
+    AuditErrorObserver, AuditLogEntry, AuditLogPage, AuditObservabilityLayer, AuditOutcome,
+    AuditRequestData, AuditRequestObserver, AuditResponseObserver, StableAuditLog,
+};
+pub use metrics::{
+    MetricsErrorObserver, MetricsRequestData, MetricsRequestObserver, MetricsResponseObserver,
+    OutcallMetrics,
+};
+#[cfg(feature = "multi")]
+pub use profiling::ProfiledReducer;
+pub use profiling::{
+    IcInstructionCounter, InstructionCounter, InstructionProfile, InstructionsObserver,
+    MockInstructionCounter, Profiling, ProfilingFuture, ProfilingLayer, StageInstructions,
+};
+
+#[cfg(feature = "audit")]
+mod audit;
+mod metrics;
+mod profiling;
+#[cfg(test)]
+mod tests;
+
+use crate::time::{Clock, IcClock, Timestamp};
+use crate::{
+    CyclesUsageResponseExtension, IcError, IcHttpRequestWithCycles, IcHttpResponseWithCycles,
+    RetryAttemptRequestExtension,
+};
+use ic_cdk::management_canister::{
+    HttpMethod, HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse,
+};
 use pin_project::pin_project;
 use std::future::Future;
+use std::marker::PhantomData;
 use std::pin::Pin;
 use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::BoxError;
 use tower::{Layer, Service};
 
 /// [`Layer`] that adds high level observability to a [`Service`].
@@ -173,19 +258,25 @@ use tower::{Layer, Service};
 /// [`Layer`]: tower::Layer
 /// [`Service`]: tower::Service
 #[derive(Clone, Debug)]
-pub struct ObservabilityLayer<OnRequest, OnResponse, OnError> {
+pub struct ObservabilityLayer<OnRequest, OnResponse, OnError, C = IcClock, ContextMode = ()> {
     on_request: OnRequest,
     on_response: OnResponse,
     on_error: OnError,
+    clock: Option<C>,
+    context: ContextMode,
 }
 
 impl ObservabilityLayer<(), (), ()> {
     /// Creates a new [`ObservabilityLayer`] that does nothing.
+    ///
+    /// The latency of calls is not measured until a clock is given with [`ObservabilityLayer::with_clock`].
     pub fn new() -> Self {
         Self {
             on_request: (),
             on_response: (),
             on_error: (),
+            clock: None,
+            context: (),
         }
     }
 }
@@ -196,58 +287,102 @@ impl Default for ObservabilityLayer<(), (), ()> {
     }
 }
 
-impl<OnRequest, OnResponse, OnError> ObservabilityLayer<OnRequest, OnResponse, OnError> {
+impl<OnRequest, OnResponse, OnError, C, ContextMode>
+    ObservabilityLayer<OnRequest, OnResponse, OnError, C, ContextMode>
+{
     /// Customize what to do when a request is received.
     ///
     /// `NewOnRequest` is expected to implement [`RequestObserver`].
     pub fn on_request<NewOnRequest>(
         self,
         new_on_request: NewOnRequest,
-    ) -> ObservabilityLayer<NewOnRequest, OnResponse, OnError> {
+    ) -> ObservabilityLayer<NewOnRequest, OnResponse, OnError, C, ContextMode> {
         ObservabilityLayer {
             on_request: new_on_request,
             on_response: self.on_response,
             on_error: self.on_error,
+            clock: self.clock,
+            context: self.context,
         }
     }
 
     /// Customize what to do when a response has been produced.
     ///
-    /// `NewOnResponse` is expected to implement [`ResponseObserver`].
+    /// `NewOnResponse` is expected to implement [`ResponseObserver`],
+    /// or [`ContextualResponseObserver`] if the layer gives an [`ObservationContext`]
+    /// (see [`ObservabilityLayer::with_observation_context`]).
     pub fn on_response<NewOnResponse>(
         self,
         new_on_response: NewOnResponse,
-    ) -> ObservabilityLayer<OnRequest, NewOnResponse, OnError> {
+    ) -> ObservabilityLayer<OnRequest, NewOnResponse, OnError, C, ContextMode> {
         ObservabilityLayer {
             on_request: self.on_request,
             on_response: new_on_response,
             on_error: self.on_error,
+            clock: self.clock,
+            context: self.context,
         }
     }
 
     /// Customize what to do when an error has been produced.
     ///
-    /// `NewOnError` is expected to implement [`ResponseObserver`].
+    /// `NewOnError` is expected to implement [`ResponseObserver`],
+    /// or [`ContextualResponseObserver`] if the layer gives an [`ObservationContext`]
+    /// (see [`ObservabilityLayer::with_observation_context`]).
     pub fn on_error<NewOnError>(
         self,
         new_on_error: NewOnError,
-    ) -> ObservabilityLayer<OnRequest, OnResponse, NewOnError> {
+    ) -> ObservabilityLayer<OnRequest, OnResponse, NewOnError, C, ContextMode> {
         ObservabilityLayer {
             on_request: self.on_request,
             on_response: self.on_response,
             on_error: new_on_error,
+            clock: self.clock,
+            context: self.context,
+        }
+    }
+
+    /// Measure the latency of calls with the given clock,
+    /// which is then available in the [`ObservationContext`] given to the observers
+    /// (see [`ObservabilityLayer::with_observation_context`]).
+    pub fn with_clock<NewClock: Clock>(
+        self,
+        clock: NewClock,
+    ) -> ObservabilityLayer<OnRequest, OnResponse, OnError, NewClock, ContextMode> {
+        ObservabilityLayer {
+            on_request: self.on_request,
+            on_response: self.on_response,
+            on_error: self.on_error,
+            clock: Some(clock),
+            context: self.context,
+        }
+    }
+
+    /// Give the observers the [`ObservationContext`] in which a response or an error was produced,
+    /// which requires requests to implement [`ObservableRequest`]
+    /// and observers to implement [`ContextualResponseObserver`].
+    pub fn with_observation_context(
+        self,
+    ) -> ObservabilityLayer<OnRequest, OnResponse, OnError, C, WithObservationContext> {
+        ObservabilityLayer {
+            on_request: self.on_request,
+            on_response: self.on_response,
+            on_error: self.on_error,
+            clock: self.clock,
+            context: WithObservationContext,
         }
     }
 }
 
-impl<S, OnRequest, OnResponse, OnError> Layer<S>
-    for ObservabilityLayer<OnRequest, OnResponse, OnError>
+impl<S, OnRequest, OnResponse, OnError, C, ContextMode> Layer<S>
+    for ObservabilityLayer<OnRequest, OnResponse, OnError, C, ContextMode>
 where
     OnRequest: Clone,
     OnResponse: Clone,
     OnError: Clone,
+    C: Clone,
 {
-    type Service = Observability<S, OnRequest, OnResponse, OnError>;
+    type Service = Observability<S, OnRequest, OnResponse, OnError, C, ContextMode>;
 
     fn layer(&self, inner: S) -> Self::Service {
         Self::Service {
@@ -255,6 +390,8 @@ where
             on_request: self.on_request.clone(),
             on_response: self.on_response.clone(),
             on_error: self.on_error.clone(),
+            clock: self.clock.clone(),
+            _context: PhantomData,
         }
     }
 }
@@ -265,24 +402,75 @@ where
 ///
 /// [`Service`]: tower::Service
 #[derive(Clone, Debug)]
-pub struct Observability<S, OnRequest, OnResponse, OnError> {
+pub struct Observability<S, OnRequest, OnResponse, OnError, C = IcClock, ContextMode = ()> {
     inner: S,
     on_request: OnRequest,
     on_response: OnResponse,
     on_error: OnError,
+    clock: Option<C>,
+    _context: PhantomData<ContextMode>,
 }
 
-impl<S, Request, Response, OnRequest, RequestData, OnResponse, OnError> Service<Request>
-    for Observability<S, OnRequest, OnResponse, OnError>
+impl<S, OnRequest, OnResponse, OnError, C: Clock + Clone, ContextMode>
+    Observability<S, OnRequest, OnResponse, OnError, C, ContextMode>
+{
+    fn start(&self) -> Option<(C, Timestamp)> {
+        self.clock.clone().map(|clock| {
+            let now = clock.now();
+            (clock, now)
+        })
+    }
+}
+
+impl<S, Request, Response, OnRequest, RequestData, OnResponse, OnError, C> Service<Request>
+    for Observability<S, OnRequest, OnResponse, OnError, C>
 where
     S: Service<Request, Response = Response>,
     OnRequest: RequestObserver<Request, ObservableRequestData = RequestData>,
     OnResponse: ResponseObserver<RequestData, S::Response> + Clone,
     OnError: ResponseObserver<RequestData, S::Error> + Clone,
+    C: Clock + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<
+        S::Future,
+        RequestData,
+        IgnoreContext<OnResponse>,
+        IgnoreContext<OnError>,
+        C,
+    >;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let req_data = self.on_request.observe_request(&req);
+        ResponseFuture {
+            start: self.start(),
+            response_future: self.inner.call(req),
+            request_data: Some(req_data),
+            on_response: IgnoreContext(self.on_response.clone()),
+            on_error: IgnoreContext(self.on_error.clone()),
+            context: ObservationContext::default(),
+        }
+    }
+}
+
+impl<S, Request, Response, OnRequest, RequestData, OnResponse, OnError, C> Service<Request>
+    for Observability<S, OnRequest, OnResponse, OnError, C, WithObservationContext>
+where
+    S: Service<Request, Response = Response>,
+    Request: ObservableRequest,
+    OnRequest: RequestObserver<Request, ObservableRequestData = RequestData>,
+    OnResponse: ContextualResponseObserver<RequestData, S::Response> + Clone,
+    OnError: ContextualResponseObserver<RequestData, S::Error> + Clone,
+    C: Clock + Clone,
 {
     type Response = S::Response;
     type Error = S::Error;
-    type Future = ResponseFuture<S::Future, RequestData, OnResponse, OnError>;
+    type Future = ResponseFuture<S::Future, RequestData, OnResponse, OnError, C>;
 
     fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
         self.inner.poll_ready(cx)
@@ -290,15 +478,26 @@ where
 
     fn call(&mut self, req: Request) -> Self::Future {
         let req_data = self.on_request.observe_request(&req);
+        let context = ObservationContext {
+            elapsed: None,
+            retry_attempt: req.retry_attempt().unwrap_or_default(),
+            cycles_attached: req.cycles_attached(),
+        };
         ResponseFuture {
+            start: self.start(),
             response_future: self.inner.call(req),
             request_data: Some(req_data),
             on_response: self.on_response.clone(),
             on_error: self.on_error.clone(),
+            context,
         }
     }
 }
 
+/// Marker given to [`ObservabilityLayer`] by [`ObservabilityLayer::with_observation_context`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct WithObservationContext;
+
 /// Trait used to tell [`Observability`] what to do when a request is received.
 pub trait RequestObserver<Request> {
     /// Type of data that can be observed from the request (e.g., URL, host, etc.)
@@ -329,6 +528,229 @@ where
     }
 }
 
+/// Metadata of a request that [`Observability`] makes available to observers, see [`ObservationContext`].
+pub trait ObservableRequest {
+    /// Retry attempt of the request, see [`RetryAttemptRequestExtension`].
+    fn retry_attempt(&self) -> Option<u32> {
+        None
+    }
+
+    /// Number of cycles attached to the request, if known.
+    fn cycles_attached(&self) -> Option<u128> {
+        None
+    }
+
+    /// Host targeted by the request, if known.
+    fn host(&self) -> Option<&str> {
+        None
+    }
+
+    /// HTTP method of the request (e.g. `GET`), if known.
+    fn method(&self) -> Option<&str> {
+        None
+    }
+}
+
+impl ObservableRequest for IcHttpRequest {
+    fn host(&self) -> Option<&str> {
+        url_host(&self.url)
+    }
+
+    fn method(&self) -> Option<&str> {
+        Some(match self.method {
+            HttpMethod::GET => "GET",
+            HttpMethod::POST => "POST",
+            HttpMethod::HEAD => "HEAD",
+        })
+    }
+}
+
+impl ObservableRequest for IcHttpRequestWithCycles {
+    fn retry_attempt(&self) -> Option<u32> {
+        self.get_retry_attempt()
+    }
+
+    fn cycles_attached(&self) -> Option<u128> {
+        Some(self.cycles)
+    }
+
+    fn host(&self) -> Option<&str> {
+        self.request.host()
+    }
+
+    fn method(&self) -> Option<&str> {
+        self.request.method()
+    }
+}
+
+#[cfg(feature = "http")]
+impl<T> ObservableRequest for http::Request<T> {
+    fn retry_attempt(&self) -> Option<u32> {
+        self.get_retry_attempt()
+    }
+
+    fn host(&self) -> Option<&str> {
+        self.uri().host()
+    }
+
+    fn method(&self) -> Option<&str> {
+        Some(http::Request::method(self).as_str())
+    }
+}
+
+/// Metadata of a response that can be observed, e.g. by [`OutcallMetrics`].
+pub trait ObservableResponse {
+    /// HTTP status code of the response.
+    fn status_code(&self) -> u16;
+
+    /// Size of the response in bytes, i.e. the size of its body plus the size of its headers.
+    fn response_size(&self) -> Option<u64> {
+        None
+    }
+
+    /// Number of cycles attached to the request that produced the response, if known.
+    fn cycles_attached(&self) -> Option<u128> {
+        None
+    }
+}
+
+impl ObservableResponse for IcHttpResponse {
+    fn status_code(&self) -> u16 {
+        u16::try_from(&self.status.0).unwrap_or(u16::MAX)
+    }
+
+    fn response_size(&self) -> Option<u64> {
+        let headers_size: usize = self
+            .headers
+            .iter()
+            .map(|header| header.name.len() + header.value.len())
+            .sum();
+        Some((headers_size + self.body.len()) as u64)
+    }
+}
+
+impl ObservableResponse for IcHttpResponseWithCycles {
+    fn status_code(&self) -> u16 {
+        self.response.status_code()
+    }
+
+    fn response_size(&self) -> Option<u64> {
+        self.response.response_size()
+    }
+
+    fn cycles_attached(&self) -> Option<u128> {
+        Some(self.cycles.attached)
+    }
+}
+
+#[cfg(feature = "http")]
+impl<T: AsRef<[u8]>> ObservableResponse for http::Response<T> {
+    fn status_code(&self) -> u16 {
+        self.status().as_u16()
+    }
+
+    fn response_size(&self) -> Option<u64> {
+        let headers_size: usize = self
+            .headers()
+            .iter()
+            .map(|(name, value)| name.as_str().len() + value.len())
+            .sum();
+        Some((headers_size + self.body().as_ref().len()) as u64)
+    }
+
+    fn cycles_attached(&self) -> Option<u128> {
+        self.get_cycles_usage().map(|usage| usage.attached)
+    }
+}
+
+/// Classification of errors that can be observed, e.g. by [`OutcallMetrics`].
+pub trait ObservableError {
+    /// Short name of the kind of error, suitable as a metric label.
+    ///
+    /// Errors from the Internet Computer are named after their reject code (e.g. `SysTransient`),
+    /// non-successful HTTP responses after the class of their status code (e.g. `http_5xx`),
+    /// while errors converting requests or responses are named `conversion`.
+    fn error_kind(&self) -> String;
+}
+
+#[cfg(feature = "http")]
+impl<T> ObservableError for crate::http::FilterNonSuccessfulHttpResponseError<T> {
+    fn error_kind(&self) -> String {
+        match self {
+            Self::UnsuccessfulResponse(response) => {
+                format!("http_{}xx", response.status().as_u16() / 100)
+            }
+        }
+    }
+}
+
+impl ObservableError for IcError {
+    fn error_kind(&self) -> String {
+        format!("{:?}", self.code)
+    }
+}
+
+impl ObservableError for BoxError {
+    fn error_kind(&self) -> String {
+        if let Some(error) = self.downcast_ref::<IcError>() {
+            return error.error_kind();
+        }
+        #[cfg(feature = "http")]
+        if let Some(error) =
+            self.downcast_ref::<crate::http::FilterNonSuccessfulHttpResponseError<Vec<u8>>>()
+        {
+            return error.error_kind();
+        }
+        #[cfg(feature = "http")]
+        if self.is::<crate::http::HttpRequestConversionError>()
+            || self.is::<crate::http::HttpResponseConversionError>()
+        {
+            return "conversion".to_string();
+        }
+        #[cfg(feature = "json")]
+        if self.is::<crate::http::json::JsonRequestConversionError>()
+            || self.is::<crate::http::json::JsonResponseConversionError>()
+        {
+            return "conversion".to_string();
+        }
+        "other".to_string()
+    }
+}
+
+/// Host of the given URL, without user information and port.
+fn url_host(url: &str) -> Option<&str> {
+    let authority = url.split_once("://").map_or(url, |(_scheme, rest)| rest);
+    let authority = &authority[..authority.find(['/', '?', '#']).unwrap_or(authority.len())];
+    let host_and_port = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_user_info, host_and_port)| host_and_port);
+    let host = match host_and_port.strip_prefix('[') {
+        Some(ipv6) => &host_and_port[..ipv6.find(']').map_or(host_and_port.len(), |end| end + 2)],
+        None => host_and_port
+            .split_once(':')
+            .map_or(host_and_port, |(host, _port)| host),
+    };
+    Some(host).filter(|host| !host.is_empty())
+}
+
+/// Context in which a response or an error was produced.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ObservationContext {
+    /// Time elapsed between the call and the response or error,
+    /// if the layer was given a clock (see [`ObservabilityLayer::with_clock`]).
+    pub elapsed: Option<Duration>,
+    /// Retry attempt of the request, where 0 denotes the first attempt.
+    ///
+    /// Only tracked when the layer was configured with [`ObservabilityLayer::with_observation_context`],
+    /// sits below a retry policy wrapped in [`TrackRetryAttempt`](crate::retry::TrackRetryAttempt)
+    /// and the requests can carry that information (see [`RetryAttemptRequestExtension`]).
+    pub retry_attempt: u32,
+    /// Number of cycles attached to the request,
+    /// if the layer was configured with [`ObservabilityLayer::with_observation_context`]
+    /// and sits below [`CyclesAccounting`](crate::cycles::CyclesAccounting).
+    pub cycles_attached: Option<u128>,
+}
+
 /// Trait used to tell [`Observability`] what to do when a response is received.
 pub trait ResponseObserver<RequestData, Response> {
     /// Observe the response (typically an instance of [`std::result::Result`]) and the request data produced by a [`RequestObserver`].
@@ -350,22 +772,90 @@ where
     }
 }
 
+/// Trait used to tell [`Observability`] what to do when a response is received,
+/// given the [`ObservationContext`] in which it was produced
+/// (see [`ObservabilityLayer::with_observation_context`]).
+pub trait ContextualResponseObserver<RequestData, Response> {
+    /// Observe the response (typically an instance of [`std::result::Result`]) and the request data produced by a [`RequestObserver`],
+    /// together with the context in which the response was produced.
+    fn observe_response_with_context(
+        &self,
+        request_data: RequestData,
+        value: &Response,
+        context: &ObservationContext,
+    );
+}
+
+impl<RequestData, Response> ContextualResponseObserver<RequestData, Response> for () {
+    fn observe_response_with_context(
+        &self,
+        _request_data: RequestData,
+        _value: &Response,
+        _context: &ObservationContext,
+    ) {
+        //NOP
+    }
+}
+
+/// [`ContextualResponseObserver`] from a [`ResponseObserver`] ignoring the [`ObservationContext`].
+#[derive(Clone, Debug)]
+pub struct IgnoreContext<O>(pub O);
+
+impl<O, RequestData, Response> ContextualResponseObserver<RequestData, Response>
+    for IgnoreContext<O>
+where
+    O: ResponseObserver<RequestData, Response>,
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: RequestData,
+        value: &Response,
+        _context: &ObservationContext,
+    ) {
+        self.0.observe_response(request_data, value);
+    }
+}
+
+/// Response observer from a closure that also takes the [`ObservationContext`].
+///
+/// See the [module docs](crate::observability) for an example.
+#[derive(Clone, Debug)]
+pub struct ObserveWithContext<F>(pub F);
+
+impl<F, RequestData, Response> ContextualResponseObserver<RequestData, Response>
+    for ObserveWithContext<F>
+where
+    F: Fn(RequestData, &Response, &ObservationContext),
+{
+    fn observe_response_with_context(
+        &self,
+        request_data: RequestData,
+        value: &Response,
+        context: &ObservationContext,
+    ) {
+        (self.0)(request_data, value, context);
+    }
+}
+
 /// Response future for [`Observability`].
 #[pin_project]
-pub struct ResponseFuture<F, RequestData, OnResponse, OnError> {
+pub struct ResponseFuture<F, RequestData, OnResponse, OnError, C = IcClock> {
     #[pin]
     response_future: F,
     request_data: Option<RequestData>,
     on_response: OnResponse,
     on_error: OnError,
+    start: Option<(C, Timestamp)>,
+    context: ObservationContext,
 }
 
-impl<F, RequestData, OnResponse, OnError, Response, Error> Future
-    for ResponseFuture<F, RequestData, OnResponse, OnError>
+impl<F, RequestData, OnResponse, OnError, C, Response, Error> Future
+    for ResponseFuture<F, RequestData, OnResponse, OnError, C>
 where
     F: Future<Output = Result<Response, Error>>,
-    OnResponse: ResponseObserver<RequestData, Response>,
-    OnError: ResponseObserver<RequestData, Error>,
+    OnResponse: ContextualResponseObserver<RequestData, Response>,
+    OnError: ContextualResponseObserver<RequestData, Error>,
+    C: Clock,
 {
     type Output = Result<Response, Error>;
 
@@ -375,12 +865,24 @@ where
         match &result_fut {
             Poll::Ready(result) => {
                 let request_data = this.request_data.take().unwrap();
+                let context = ObservationContext {
+                    elapsed: this
+                        .start
+                        .as_ref()
+                        .map(|(clock, start)| clock.now().saturating_duration_since(*start)),
+                    ..this.context.clone()
+                };
                 match result {
                     Ok(response) => {
-                        this.on_response.observe_response(request_data, response);
+                        this.on_response.observe_response_with_context(
+                            request_data,
+                            response,
+                            &context,
+                        );
                     }
                     Err(error) => {
-                        this.on_error.observe_response(request_data, error);
+                        this.on_error
+                            .observe_response_with_context(request_data, error, &context);
                     }
                 }
             }
@@ -0,0 +1,341 @@
+use pin_project::pin_project;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use tower::{Layer, Service};
+
+/// Source of the number of instructions executed so far.
+pub trait InstructionCounter {
+    /// Number of instructions executed so far.
+    fn instruction_count(&self) -> u64;
+}
+
+/// [`InstructionCounter`] counting the instructions executed by the current message of the canister,
+/// as given by [`ic_cdk::api::performance_counter`].
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IcInstructionCounter;
+
+impl InstructionCounter for IcInstructionCounter {
+    fn instruction_count(&self) -> u64 {
+        ic_cdk::api::performance_counter(0)
+    }
+}
+
+/// [`InstructionCounter`] whose count is set manually, e.g. in tests.
+///
+/// All clones share the same count.
+#[derive(Clone, Debug, Default)]
+pub struct MockInstructionCounter(Arc<AtomicU64>);
+
+impl MockInstructionCounter {
+    /// Increase the count by the given number of instructions.
+    pub fn advance(&self, instructions: u64) {
+        self.0.fetch_add(instructions, Ordering::Relaxed);
+    }
+}
+
+impl InstructionCounter for MockInstructionCounter {
+    fn instruction_count(&self) -> u64 {
+        self.0.load(Ordering::Relaxed)
+    }
+}
+
+thread_local! {
+    /// For each stage being measured, number of instructions used by the profiled stages nested in it.
+    static NESTED_INSTRUCTIONS: RefCell<Vec<u64>> = const { RefCell::new(Vec::new()) };
+}
+
+/// Run the given function and return the number of instructions it used,
+/// excluding the instructions used by the profiled stages nested in it.
+fn measure<R>(counter: &impl InstructionCounter, f: impl FnOnce() -> R) -> (R, u64) {
+    NESTED_INSTRUCTIONS.with_borrow_mut(|stack| stack.push(0));
+    let start = counter.instruction_count();
+    let result = f();
+    let instructions = counter.instruction_count().saturating_sub(start);
+    let nested = NESTED_INSTRUCTIONS.with_borrow_mut(|stack| {
+        let nested = stack.pop().unwrap_or_default();
+        if let Some(parent) = stack.last_mut() {
+            *parent = parent.saturating_add(instructions);
+        }
+        nested
+    });
+    (result, instructions.saturating_sub(nested))
+}
+
+/// Observer of the number of instructions used by a stage of a service stack, see [`ProfilingLayer`].
+pub trait InstructionsObserver {
+    /// Observe that a single run of the given stage used the given number of instructions.
+    fn observe_instructions(&self, stage: &'static str, instructions: u64);
+}
+
+impl<F: Fn(&'static str, u64)> InstructionsObserver for F {
+    fn observe_instructions(&self, stage: &'static str, instructions: u64) {
+        self(stage, instructions)
+    }
+}
+
+/// [`Layer`] that measures the number of instructions used by a stage of a service stack
+/// and reports them to an [`InstructionsObserver`].
+///
+/// The instructions used by a call are the instructions executed synchronously by [`Service::call`]
+/// and by each poll of the returned future, which are measured with [`ic_cdk::api::performance_counter`] by default.
+/// The instructions used by profiled stages further down the stack (i.e., below another [`ProfilingLayer`]
+/// or in a `ProfiledReducer`) are excluded, so that a stage extends from the layer down to the next
+/// profiled stage, or to the end of the stack if there is none.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{
+///     http::{FilterNonSuccessfulHttpResponse, HttpRequestConverter, HttpResponseConverter},
+///     observability::InstructionProfile,
+///     ConvertServiceBuilder,
+/// };
+/// use tower::ServiceBuilder;
+///
+/// let profile = InstructionProfile::default();
+///
+/// let builder = ServiceBuilder::new()
+///     .layer(profile.layer("filter"))
+///     .convert_response(FilterNonSuccessfulHttpResponse)
+///     .layer(profile.layer("conversion"))
+///     .convert_request(HttpRequestConverter)
+///     .convert_response(HttpResponseConverter)
+///     .layer(profile.layer("client"));
+///
+/// // E.g., in a query endpoint of the canister:
+/// for (stage, instructions) in profile.stages() {
+///     println!("{stage}: {} instructions at most", instructions.max);
+/// }
+/// ```
+///
+/// [`Layer`]: tower::Layer
+#[derive(Clone, Debug)]
+pub struct ProfilingLayer<O, I = IcInstructionCounter> {
+    stage: &'static str,
+    observer: O,
+    counter: I,
+}
+
+impl<O> ProfilingLayer<O> {
+    /// Create a new [`ProfilingLayer`] reporting the instructions used by the given stage to the given observer.
+    pub fn new(stage: &'static str, observer: O) -> Self {
+        Self {
+            stage,
+            observer,
+            counter: IcInstructionCounter,
+        }
+    }
+}
+
+impl<O, I> ProfilingLayer<O, I> {
+    /// Count instructions with the given counter, instead of the performance counter of the Internet Computer.
+    pub fn with_counter<J: InstructionCounter>(self, counter: J) -> ProfilingLayer<O, J> {
+        ProfilingLayer {
+            stage: self.stage,
+            observer: self.observer,
+            counter,
+        }
+    }
+}
+
+impl<S, O: Clone, I: Clone> Layer<S> for ProfilingLayer<O, I> {
+    type Service = Profiling<S, O, I>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        Profiling {
+            inner,
+            stage: self.stage,
+            observer: self.observer.clone(),
+            counter: self.counter.clone(),
+        }
+    }
+}
+
+/// Middleware that measures the number of instructions used by a [`Service`].
+///
+/// See [`ProfilingLayer`] for more details.
+///
+/// [`Service`]: tower::Service
+#[derive(Clone, Debug)]
+pub struct Profiling<S, O, I = IcInstructionCounter> {
+    inner: S,
+    stage: &'static str,
+    observer: O,
+    counter: I,
+}
+
+impl<S, O, I, Request> Service<Request> for Profiling<S, O, I>
+where
+    S: Service<Request>,
+    O: InstructionsObserver + Clone,
+    I: InstructionCounter + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ProfilingFuture<S::Future, O, I>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, req: Request) -> Self::Future {
+        let (response_future, instructions) = measure(&self.counter, || self.inner.call(req));
+        ProfilingFuture {
+            response_future,
+            stage: self.stage,
+            observer: self.observer.clone(),
+            instructions,
+            counter: self.counter.clone(),
+        }
+    }
+}
+
+/// Response future for [`Profiling`].
+#[pin_project]
+pub struct ProfilingFuture<F, O, I> {
+    #[pin]
+    response_future: F,
+    stage: &'static str,
+    observer: O,
+    counter: I,
+    instructions: u64,
+}
+
+impl<F, O, I> Future for ProfilingFuture<F, O, I>
+where
+    F: Future,
+    O: InstructionsObserver,
+    I: InstructionCounter,
+{
+    type Output = F::Output;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let (result, instructions) = measure(this.counter, || this.response_future.poll(cx));
+        *this.instructions = this.instructions.saturating_add(instructions);
+        if result.is_ready() {
+            this.observer
+                .observe_instructions(this.stage, *this.instructions);
+        }
+        result
+    }
+}
+
+/// Wraps a [`Reduce`] and reports the number of instructions used by each reduction
+/// to an [`InstructionsObserver`].
+///
+/// Reducing is synchronous and the number of instructions is measured with
+/// [`ic_cdk::api::performance_counter`] by default.
+///
+/// [`Reduce`]: crate::multi::Reduce
+#[cfg(feature = "multi")]
+#[derive(Clone, Debug)]
+pub struct ProfiledReducer<R, O, I = IcInstructionCounter> {
+    reducer: R,
+    stage: &'static str,
+    observer: O,
+    counter: I,
+}
+
+#[cfg(feature = "multi")]
+impl<R, O> ProfiledReducer<R, O> {
+    /// Wrap the given reducer, reporting the instructions it uses as the given stage to the given observer.
+    pub fn new(stage: &'static str, reducer: R, observer: O) -> Self {
+        Self {
+            reducer,
+            stage,
+            observer,
+            counter: IcInstructionCounter,
+        }
+    }
+}
+
+#[cfg(feature = "multi")]
+impl<R, O, I> ProfiledReducer<R, O, I> {
+    /// Count instructions with the given counter, instead of the performance counter of the Internet Computer.
+    pub fn with_counter<J: InstructionCounter>(self, counter: J) -> ProfiledReducer<R, O, J> {
+        ProfiledReducer {
+            reducer: self.reducer,
+            stage: self.stage,
+            observer: self.observer,
+            counter,
+        }
+    }
+}
+
+#[cfg(feature = "multi")]
+impl<R, O, I, K, V, E> crate::multi::Reduce<K, V, E> for ProfiledReducer<R, O, I>
+where
+    R: crate::multi::Reduce<K, V, E>,
+    O: InstructionsObserver,
+    I: InstructionCounter,
+{
+    fn reduce(
+        &self,
+        results: crate::multi::MultiResults<K, V, E>,
+    ) -> crate::multi::ReducedResult<K, V, E> {
+        let (result, instructions) = measure(&self.counter, || self.reducer.reduce(results));
+        self.observer.observe_instructions(self.stage, instructions);
+        result
+    }
+}
+
+/// Number of instructions used by a stage, aggregated over all its runs.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct StageInstructions {
+    /// Number of runs of the stage.
+    pub runs: u64,
+    /// Total number of instructions used by all runs.
+    pub total: u64,
+    /// Maximum number of instructions used by a single run.
+    pub max: u64,
+}
+
+/// [`InstructionsObserver`] aggregating the number of instructions used by each stage.
+///
+/// All clones share the same aggregates.
+#[derive(Clone, Debug, Default)]
+pub struct InstructionProfile(Arc<Mutex<BTreeMap<&'static str, StageInstructions>>>);
+
+impl InstructionProfile {
+    /// Create a [`ProfilingLayer`] aggregating the instructions used by the given stage in this profile.
+    pub fn layer(&self, stage: &'static str) -> ProfilingLayer<InstructionProfile> {
+        ProfilingLayer::new(stage, self.clone())
+    }
+
+    /// Wrap the given reducer so that the instructions used by each reduction
+    /// are aggregated as the given stage in this profile.
+    #[cfg(feature = "multi")]
+    pub fn reducer<R>(
+        &self,
+        stage: &'static str,
+        reducer: R,
+    ) -> ProfiledReducer<R, InstructionProfile> {
+        ProfiledReducer::new(stage, reducer, self.clone())
+    }
+
+    /// Number of instructions used by each stage so far.
+    pub fn stages(&self) -> BTreeMap<&'static str, StageInstructions> {
+        self.0.lock().unwrap().clone()
+    }
+
+    /// Clear all aggregates.
+    pub fn reset(&self) {
+        self.0.lock().unwrap().clear();
+    }
+}
+
+impl InstructionsObserver for InstructionProfile {
+    fn observe_instructions(&self, stage: &'static str, instructions: u64) {
+        let mut stages = self.0.lock().unwrap();
+        let aggregate = stages.entry(stage).or_default();
+        aggregate.runs += 1;
+        aggregate.total = aggregate.total.saturating_add(instructions);
+        aggregate.max = aggregate.max.max(instructions);
+    }
+}
@@ -0,0 +1,377 @@
+//! Redact sensitive information (e.g. API keys) from requests and responses before they are logged.
+//!
+//! HTTPs outcalls often carry credentials, either in headers (e.g. `Authorization`) or in the query
+//! of the URL (e.g. `?api_key=...`), which should not end up in the logs of a canister.
+//! A [`RedactionPolicy`] specifies which headers and query parameters must be hidden and how much
+//! of a body may be shown, and is applied by the [`Redacted`] wrapper when formatting a value with [`Debug`].
+//!
+//! The error types of this crate that contain a response (e.g. `FilterNonSuccessfulHttpResponseError`)
+//! are formatted with the default policy.
+//!
+//! # Examples
+//!
+//! ```rust
+//! use canhttp::redact::{Redacted, RedactionPolicy};
+//! use ic_cdk::management_canister::{HttpHeader, HttpRequestArgs as IcHttpRequest};
+//!
+//! let request = IcHttpRequest {
+//!     url: "https://example.com/v1?api_key=secret&chain=1".to_string(),
+//!     headers: vec![HttpHeader {
+//!         name: "Authorization".to_string(),
+//!         value: "Bearer secret".to_string(),
+//!     }],
+//!     ..Default::default()
+//! };
+//!
+//! let log = format!("{:?}", Redacted::new(&request));
+//! assert!(!log.contains("secret"));
+//! assert!(log.contains("https://example.com/v1?api_key=[REDACTED]&chain=1"));
+//!
+//! // Observers can use a custom policy
+//! let policy = RedactionPolicy::default()
+//!     .with_redacted_query_param("chain")
+//!     .with_max_body_len(100);
+//! let log = format!("{:?}", policy.redact(&request));
+//! assert!(log.contains("https://example.com/v1?api_key=[REDACTED]&chain=[REDACTED]"));
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+use crate::{IcHttpRequestWithCycles, IcHttpResponseWithCycles};
+use ic_cdk::management_canister::{
+    HttpHeader, HttpRequestArgs as IcHttpRequest, HttpRequestResult as IcHttpResponse,
+};
+use std::borrow::Cow;
+use std::collections::BTreeSet;
+use std::fmt;
+use std::fmt::{Debug, Formatter};
+
+/// Replacement for redacted values.
+pub const REDACTED: &str = "[REDACTED]";
+
+const DEFAULT_REDACTED_HEADERS: [&str; 6] = [
+    "authorization",
+    "proxy-authorization",
+    "cookie",
+    "set-cookie",
+    "x-api-key",
+    "api-key",
+];
+const DEFAULT_REDACTED_QUERY_PARAMS: [&str; 8] = [
+    "api_key",
+    "api-key",
+    "apikey",
+    "key",
+    "token",
+    "access_token",
+    "secret",
+    "password",
+];
+const DEFAULT_MAX_BODY_LEN: usize = 1_024;
+
+/// Specify which parts of requests and responses must be redacted.
+///
+/// By default:
+/// * the values of common authentication headers (e.g. `Authorization`, `Cookie`, `X-Api-Key`) are redacted;
+/// * the values of common query parameters holding credentials (e.g. `api_key`, `token`) are redacted,
+///   as well as the user information of URLs;
+/// * bodies are truncated to 1024 bytes.
+///
+/// Header names and query parameters are compared case-insensitively.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct RedactionPolicy {
+    redacted_headers: BTreeSet<String>,
+    redacted_query_params: BTreeSet<String>,
+    max_body_len: Option<usize>,
+}
+
+impl Default for RedactionPolicy {
+    fn default() -> Self {
+        Self {
+            redacted_headers: DEFAULT_REDACTED_HEADERS.map(String::from).into(),
+            redacted_query_params: DEFAULT_REDACTED_QUERY_PARAMS.map(String::from).into(),
+            max_body_len: Some(DEFAULT_MAX_BODY_LEN),
+        }
+    }
+}
+
+impl RedactionPolicy {
+    /// Policy that does not redact anything.
+    pub fn none() -> Self {
+        Self {
+            redacted_headers: BTreeSet::new(),
+            redacted_query_params: BTreeSet::new(),
+            max_body_len: None,
+        }
+    }
+
+    /// Additionally redact the value of the header with the given name.
+    pub fn with_redacted_header(mut self, name: &str) -> Self {
+        self.redacted_headers.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Additionally redact the value of the query parameter with the given name.
+    pub fn with_redacted_query_param(mut self, name: &str) -> Self {
+        self.redacted_query_params.insert(name.to_ascii_lowercase());
+        self
+    }
+
+    /// Truncate bodies to the given number of bytes.
+    pub fn with_max_body_len(self, max_body_len: usize) -> Self {
+        Self {
+            max_body_len: Some(max_body_len),
+            ..self
+        }
+    }
+
+    /// Show bodies in full.
+    pub fn without_max_body_len(self) -> Self {
+        Self {
+            max_body_len: None,
+            ..self
+        }
+    }
+
+    /// Determines whether the value of the header with the given name must be redacted.
+    pub fn is_redacted_header(&self, name: &str) -> bool {
+        self.redacted_headers.contains(&name.to_ascii_lowercase())
+    }
+
+    /// Determines whether the value of the query parameter with the given name must be redacted.
+    pub fn is_redacted_query_param(&self, name: &str) -> bool {
+        self.redacted_query_params
+            .contains(&name.to_ascii_lowercase())
+    }
+
+    /// Redact the user information and the values of redacted query parameters of the given URL.
+    pub fn redact_url(&self, url: &str) -> String {
+        let (url, fragment) = match url.split_once('#') {
+            Some((url, fragment)) => (url, Some(fragment)),
+            None => (url, None),
+        };
+        let (url, query) = match url.split_once('?') {
+            Some((url, query)) => (url, Some(query)),
+            None => (url, None),
+        };
+        let mut redacted = match url.split_once("://") {
+            Some((scheme, rest)) => {
+                let authority_end = rest.find('/').unwrap_or(rest.len());
+                let (authority, path) = rest.split_at(authority_end);
+                match authority.rsplit_once('@') {
+                    Some((_user_info, host)) => format!("{scheme}://{REDACTED}@{host}{path}"),
+                    None => url.to_string(),
+                }
+            }
+            None => url.to_string(),
+        };
+        if let Some(query) = query {
+            redacted.push('?');
+            let params: Vec<_> = query
+                .split('&')
+                .map(|param| match param.split_once('=') {
+                    Some((name, _value)) if self.is_redacted_query_param(name) => {
+                        Cow::Owned(format!("{name}={REDACTED}"))
+                    }
+                    _ => Cow::Borrowed(param),
+                })
+                .collect();
+            redacted.push_str(&params.join("&"));
+        }
+        if let Some(fragment) = fragment {
+            redacted.push('#');
+            redacted.push_str(fragment);
+        }
+        redacted
+    }
+
+    /// Truncate the given body, interpreted as UTF-8, to the maximum body length.
+    pub fn redact_body(&self, body: &[u8]) -> String {
+        self.truncate(&String::from_utf8_lossy(body), body.len())
+    }
+
+    /// Wrap the given value so that it is formatted according to this policy.
+    pub fn redact<'a, T: Redact + ?Sized>(&'a self, value: &'a T) -> Redacted<'a, T> {
+        Redacted {
+            value,
+            policy: Cow::Borrowed(self),
+        }
+    }
+
+    fn truncate(&self, text: &str, len: usize) -> String {
+        match self.max_body_len {
+            Some(max_len) if len > max_len => {
+                let mut end = max_len.min(text.len());
+                while !text.is_char_boundary(end) {
+                    end -= 1;
+                }
+                format!("{}... ({} bytes truncated)", &text[..end], len - max_len)
+            }
+            _ => text.to_string(),
+        }
+    }
+
+    fn header_value<'a>(&self, name: &str, value: &'a str) -> &'a str {
+        if self.is_redacted_header(name) {
+            REDACTED
+        } else {
+            value
+        }
+    }
+}
+
+/// Format a value with [`Debug`], where sensitive information is redacted according to a [`RedactionPolicy`].
+pub trait Redact {
+    /// Format the value with the given redaction policy.
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result;
+}
+
+/// Wrapper of a value whose [`Debug`] implementation redacts sensitive information.
+///
+/// See the [module docs](crate::redact) for an example.
+pub struct Redacted<'a, T: ?Sized> {
+    value: &'a T,
+    policy: Cow<'a, RedactionPolicy>,
+}
+
+impl<'a, T: Redact + ?Sized> Redacted<'a, T> {
+    /// Wrap the given value so that it is formatted according to the default [`RedactionPolicy`].
+    pub fn new(value: &'a T) -> Self {
+        Self {
+            value,
+            policy: Cow::Owned(RedactionPolicy::default()),
+        }
+    }
+}
+
+impl<T: Redact + ?Sized> Debug for Redacted<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        self.value.fmt_redacted(&self.policy, f)
+    }
+}
+
+impl Redact for IcHttpRequest {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpRequestArgs")
+            .field("url", &policy.redact_url(&self.url))
+            .field("max_response_bytes", &self.max_response_bytes)
+            .field("method", &self.method)
+            .field("headers", &IcHeaders(policy, &self.headers))
+            .field(
+                "body",
+                &self.body.as_ref().map(|body| policy.redact_body(body)),
+            )
+            .field("is_replicated", &self.is_replicated)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for IcHttpRequestWithCycles {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcHttpRequestWithCycles")
+            .field("request", &policy.redact(&self.request))
+            .field("cycles", &self.cycles)
+            .field("retry_attempt", &self.retry_attempt)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Redact for IcHttpResponse {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HttpRequestResult")
+            .field("status", &self.status)
+            .field("headers", &IcHeaders(policy, &self.headers))
+            .field("body", &policy.redact_body(&self.body))
+            .finish()
+    }
+}
+
+impl Redact for IcHttpResponseWithCycles {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("IcHttpResponseWithCycles")
+            .field("response", &policy.redact(&self.response))
+            .field("cycles", &self.cycles)
+            .finish()
+    }
+}
+
+/// A `Vec<u8>` body is interpreted as UTF-8 and truncated with [`RedactionPolicy::redact_body`],
+/// while other bodies are formatted with [`Debug`] before being truncated.
+#[cfg(feature = "http")]
+impl<T: Debug + 'static> Redact for http::Request<T> {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Request")
+            .field("method", self.method())
+            .field("uri", &policy.redact_url(&self.uri().to_string()))
+            .field("version", &self.version())
+            .field("headers", &HttpHeaders(policy, self.headers()))
+            .field("body", &DebugBody(policy, self.body()))
+            .finish()
+    }
+}
+
+/// A `Vec<u8>` body is interpreted as UTF-8 and truncated with [`RedactionPolicy::redact_body`],
+/// while other bodies are formatted with [`Debug`] before being truncated.
+#[cfg(feature = "http")]
+impl<T: Debug + 'static> Redact for http::Response<T> {
+    fn fmt_redacted(&self, policy: &RedactionPolicy, f: &mut Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Response")
+            .field("status", &self.status())
+            .field("version", &self.version())
+            .field("headers", &HttpHeaders(policy, self.headers()))
+            .field("body", &DebugBody(policy, self.body()))
+            .finish()
+    }
+}
+
+struct IcHeaders<'a>(&'a RedactionPolicy, &'a [HttpHeader]);
+
+impl Debug for IcHeaders<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let IcHeaders(policy, headers) = self;
+        f.debug_map()
+            .entries(headers.iter().map(|header| {
+                (
+                    &header.name,
+                    policy.header_value(&header.name, &header.value),
+                )
+            }))
+            .finish()
+    }
+}
+
+#[cfg(feature = "http")]
+struct HttpHeaders<'a>(&'a RedactionPolicy, &'a http::HeaderMap);
+
+#[cfg(feature = "http")]
+impl Debug for HttpHeaders<'_> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let HttpHeaders(policy, headers) = self;
+        f.debug_map()
+            .entries(headers.iter().map(|(name, value)| {
+                let value = if policy.is_redacted_header(name.as_str()) {
+                    REDACTED
+                } else {
+                    value.to_str().unwrap_or("<binary>")
+                };
+                (name.as_str(), value)
+            }))
+            .finish()
+    }
+}
+
+#[cfg(feature = "http")]
+struct DebugBody<'a, T>(&'a RedactionPolicy, &'a T);
+
+#[cfg(feature = "http")]
+impl<T: Debug + 'static> Debug for DebugBody<'_, T> {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let DebugBody(policy, body) = self;
+        if let Some(bytes) = (*body as &dyn std::any::Any).downcast_ref::<Vec<u8>>() {
+            return Debug::fmt(&policy.redact_body(bytes), f);
+        }
+        let body = format!("{body:?}");
+        f.write_str(&policy.truncate(&body, body.len()))
+    }
+}
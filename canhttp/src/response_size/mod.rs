@@ -0,0 +1,265 @@
+//! Middleware to learn the value of `max_response_bytes` from the observed response sizes.
+//!
+//! The cost of an HTTPs outcall grows with `max_response_bytes` and not with the actual size of the response,
+//! so that guessing a too large value wastes cycles, while guessing a too small value fails the request.
+//! The [`AdaptiveMaxResponseBytesLayer`] middleware records the size of successful responses per endpoint
+//! and sets `max_response_bytes` for requests that do not specify it, based on the recently observed sizes.
+//!
+//! # Examples
+//!
+//! Combined with [`DoubleMaxResponseBytes`](crate::retry::DoubleMaxResponseBytes),
+//! requests whose response turns out to be larger than what was previously observed are retried.
+//! Note that the retry middleware must come after, since it does not retry requests without `max_response_bytes`:
+//! ```rust
+//! use canhttp::{cycles::{ChargeMyself, CyclesAccountingServiceBuilder}, retry::DoubleMaxResponseBytes, response_size::AdaptiveMaxResponseBytesLayer, Client};
+//! use std::num::NonZeroUsize;
+//! use std::time::Duration;
+//! use tower::{Service, ServiceBuilder, ServiceExt};
+//!
+//! # #[tokio::main]
+//! # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+//! let mut service = ServiceBuilder::new()
+//!   .layer(
+//!       AdaptiveMaxResponseBytesLayer::new(Duration::from_secs(3_600), NonZeroUsize::new(100).unwrap())
+//!           .with_percentile(99)
+//!           .with_headroom_percent(10)
+//!   )
+//!   .retry(DoubleMaxResponseBytes)
+//!   .cycles_accounting(34, ChargeMyself::default())
+//!   .service(Client::new_with_box_error());
+//!
+//! let _ = service.ready().await.unwrap();
+//! # Ok(())
+//! # }
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+use crate::multi::TimedSizedMap;
+use crate::retry::HTTP_MAX_SIZE;
+use crate::time::{Clock, IcClock};
+use crate::{IcHttpResponseWithCycles, MaxResponseBytesRequestExtension};
+use ic_cdk::management_canister::{HttpRequestArgs as IcHttpRequest, HttpRequestResult};
+use pin_project::pin_project;
+use std::future::Future;
+use std::num::NonZeroUsize;
+use std::pin::Pin;
+use std::sync::{Arc, Mutex};
+use std::task::{Context, Poll};
+use std::time::Duration;
+use tower::Service;
+use tower_layer::Layer;
+
+const DEFAULT_PERCENTILE: u8 = 95;
+const DEFAULT_HEADROOM_PERCENT: u32 = 20;
+
+/// Set `max_response_bytes` of requests that do not specify it to a percentile of the response sizes
+/// recently observed for the same endpoint, plus some headroom.
+///
+/// An endpoint is identified by the request URL without its query and fragment.
+/// The size of a response is the size of its body plus the size of its headers,
+/// since both count towards `max_response_bytes`.
+/// Requests to endpoints without any observed response size are left unchanged.
+///
+/// Note that the response size is measured on the response returned by the inner service,
+/// i.e. *after* the transform function of the request was applied, while `max_response_bytes`
+/// limits the size of the raw response returned by the server. This middleware must therefore not be used
+/// for requests whose transform function shrinks the response (e.g. by dropping headers or extracting
+/// part of the body), since the learned value would be too small and such requests would keep failing
+/// (or, when combined with [`DoubleMaxResponseBytes`](crate::retry::DoubleMaxResponseBytes), keep being retried).
+///
+/// All clones share the same observed response sizes.
+/// The time at which a response size was observed is given by the clock,
+/// which is by default [`IcClock`] and can be changed with [`AdaptiveMaxResponseBytesLayer::with_clock`].
+///
+/// This [`Layer`] produces instances of the [`AdaptiveMaxResponseBytesService`] service.
+#[derive(Clone, Debug)]
+pub struct AdaptiveMaxResponseBytesLayer<C = IcClock> {
+    response_sizes: Arc<Mutex<TimedSizedMap<String, u64>>>,
+    percentile: u8,
+    headroom_percent: u32,
+    clock: C,
+}
+
+impl AdaptiveMaxResponseBytesLayer {
+    /// Create a new middleware remembering for each endpoint at most `capacity` response sizes
+    /// which are no older than `expiration`.
+    ///
+    /// By default, `max_response_bytes` is set to the 95th percentile of the observed response sizes plus 20%.
+    pub fn new(expiration: Duration, capacity: NonZeroUsize) -> Self {
+        Self {
+            response_sizes: Arc::new(Mutex::new(TimedSizedMap::new(expiration, capacity))),
+            percentile: DEFAULT_PERCENTILE,
+            headroom_percent: DEFAULT_HEADROOM_PERCENT,
+            clock: IcClock,
+        }
+    }
+}
+
+impl<C: Clock> AdaptiveMaxResponseBytesLayer<C> {
+    /// Use the given percentile of the observed response sizes.
+    ///
+    /// # Panics
+    ///
+    /// If the percentile is 0 or greater than 100.
+    pub fn with_percentile(self, percentile: u8) -> Self {
+        assert!(
+            (1..=100).contains(&percentile),
+            "ERROR: percentile must be between 1 and 100"
+        );
+        Self { percentile, ..self }
+    }
+
+    /// Add the given percentage to the chosen percentile of the observed response sizes.
+    pub fn with_headroom_percent(self, headroom_percent: u32) -> Self {
+        Self {
+            headroom_percent,
+            ..self
+        }
+    }
+
+    /// Use the given clock to timestamp the observed response sizes.
+    pub fn with_clock<D: Clock>(self, clock: D) -> AdaptiveMaxResponseBytesLayer<D> {
+        AdaptiveMaxResponseBytesLayer {
+            response_sizes: self.response_sizes,
+            percentile: self.percentile,
+            headroom_percent: self.headroom_percent,
+            clock,
+        }
+    }
+
+    /// Return the value of `max_response_bytes` for the given request URL,
+    /// if some response sizes were observed for that endpoint.
+    pub fn max_response_bytes(&self, url: &str) -> Option<u64> {
+        let endpoint = endpoint(url);
+        let mut response_sizes = self.response_sizes.lock().unwrap();
+        let _expired = response_sizes.evict_expired(&[endpoint.clone()], self.clock.now());
+        let mut sizes: Vec<u64> = response_sizes
+            .get(&endpoint)?
+            .iter()
+            .map(|(_timestamp, size)| *size)
+            .collect();
+        if sizes.is_empty() {
+            return None;
+        }
+        sizes.sort_unstable();
+        // nearest-rank method
+        let rank = (sizes.len() * self.percentile as usize).div_ceil(100);
+        let size = sizes[rank.saturating_sub(1)];
+        let headroom = size.saturating_mul(self.headroom_percent as u64) / 100;
+        Some(size.saturating_add(headroom).min(HTTP_MAX_SIZE))
+    }
+
+    fn record(&self, url: &str, response_size: u64) {
+        let _evicted = self.response_sizes.lock().unwrap().insert_evict(
+            self.clock.now(),
+            endpoint(url),
+            response_size,
+        );
+    }
+}
+
+impl<S, C: Clone> Layer<S> for AdaptiveMaxResponseBytesLayer<C> {
+    type Service = AdaptiveMaxResponseBytesService<S, C>;
+
+    fn layer(&self, inner: S) -> Self::Service {
+        AdaptiveMaxResponseBytesService {
+            inner,
+            adaptive: self.clone(),
+        }
+    }
+}
+
+/// Service produced by the [`AdaptiveMaxResponseBytesLayer`] middleware.
+#[derive(Clone, Debug)]
+pub struct AdaptiveMaxResponseBytesService<S, C = IcClock> {
+    inner: S,
+    adaptive: AdaptiveMaxResponseBytesLayer<C>,
+}
+
+impl<S, C> Service<IcHttpRequest> for AdaptiveMaxResponseBytesService<S, C>
+where
+    S: Service<IcHttpRequest>,
+    S::Response: ResponseSize,
+    C: Clock + Clone,
+{
+    type Response = S::Response;
+    type Error = S::Error;
+    type Future = ResponseFuture<S::Future, C>;
+
+    fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+        self.inner.poll_ready(cx)
+    }
+
+    fn call(&mut self, mut request: IcHttpRequest) -> Self::Future {
+        if request.get_max_response_bytes().is_none() {
+            if let Some(max_response_bytes) = self.adaptive.max_response_bytes(&request.url) {
+                request.set_max_response_bytes(max_response_bytes);
+            }
+        }
+        ResponseFuture {
+            url: request.url.clone(),
+            response_future: self.inner.call(request),
+            adaptive: self.adaptive.clone(),
+        }
+    }
+}
+
+/// Response future for [`AdaptiveMaxResponseBytesService`].
+#[pin_project]
+pub struct ResponseFuture<F, C = IcClock> {
+    #[pin]
+    response_future: F,
+    url: String,
+    adaptive: AdaptiveMaxResponseBytesLayer<C>,
+}
+
+impl<F, C, Response, Error> Future for ResponseFuture<F, C>
+where
+    F: Future<Output = Result<Response, Error>>,
+    Response: ResponseSize,
+    C: Clock,
+{
+    type Output = Result<Response, Error>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let this = self.project();
+        let result_fut = this.response_future.poll(cx);
+        if let Poll::Ready(Ok(response)) = &result_fut {
+            this.adaptive.record(this.url, response.response_size());
+        }
+        result_fut
+    }
+}
+
+/// Size of a response as counted towards `max_response_bytes`.
+///
+/// This is only accurate for responses that were not shrunk by a transform function,
+/// see [`AdaptiveMaxResponseBytesLayer`].
+pub trait ResponseSize {
+    /// Return the size of the response in bytes.
+    fn response_size(&self) -> u64;
+}
+
+impl ResponseSize for HttpRequestResult {
+    fn response_size(&self) -> u64 {
+        let headers_size: usize = self
+            .headers
+            .iter()
+            .map(|header| header.name.len() + header.value.len())
+            .sum();
+        (headers_size + self.body.len()) as u64
+    }
+}
+
+impl ResponseSize for IcHttpResponseWithCycles {
+    fn response_size(&self) -> u64 {
+        self.response.response_size()
+    }
+}
+
+fn endpoint(url: &str) -> String {
+    let end = url.find(['?', '#']).unwrap_or(url.len());
+    url[..end].to_string()
+}
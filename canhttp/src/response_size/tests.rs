@@ -0,0 +1,118 @@
+use crate::response_size::{AdaptiveMaxResponseBytesLayer, ResponseSize};
+use crate::time::MockClock;
+use crate::{CyclesUsage, IcHttpResponseWithCycles, MaxResponseBytesRequestExtension};
+use ic_cdk::management_canister::{
+    HttpHeader, HttpRequestArgs as IcHttpRequest, HttpRequestResult,
+};
+use std::num::NonZeroUsize;
+use std::sync::mpsc;
+use std::time::Duration;
+use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+
+const URL: &str = "https://internetcomputer.org/api";
+
+#[test]
+fn should_compute_response_size_with_headers() {
+    let response = HttpRequestResult {
+        status: 200_u16.into(),
+        headers: vec![HttpHeader {
+            name: "content-type".to_string(),
+            value: "application/json".to_string(),
+        }],
+        body: vec![42; 100],
+    };
+
+    assert_eq!(response.response_size(), 12 + 16 + 100);
+}
+
+#[test]
+fn should_use_percentile_of_observed_sizes_with_headroom() {
+    let adaptive = adaptive().with_percentile(90).with_headroom_percent(10);
+    assert_eq!(adaptive.max_response_bytes(URL), None);
+
+    for size in 1..=100 {
+        adaptive.record(URL, size * 100);
+    }
+
+    assert_eq!(adaptive.max_response_bytes(URL), Some(9_900));
+    assert_eq!(
+        adaptive.max_response_bytes(&format!("{URL}?page=2#top")),
+        Some(9_900)
+    );
+    assert_eq!(
+        adaptive.max_response_bytes("https://internetcomputer.org/other"),
+        None
+    );
+
+    let adaptive = adaptive.with_percentile(100).with_headroom_percent(0);
+    assert_eq!(adaptive.max_response_bytes(URL), Some(10_000));
+}
+
+#[test]
+fn should_not_exceed_max_size() {
+    let adaptive = adaptive().with_headroom_percent(100);
+
+    adaptive.record(URL, 1_500_000);
+
+    assert_eq!(adaptive.max_response_bytes(URL), Some(2_000_000));
+}
+
+#[test]
+fn should_forget_expired_sizes() {
+    let clock = MockClock::default();
+    let adaptive = adaptive().with_clock(clock.clone());
+    adaptive.record(URL, 1_000);
+    assert_eq!(adaptive.max_response_bytes(URL), Some(1_200));
+
+    clock.advance(Duration::from_secs(3_599));
+    assert_eq!(adaptive.max_response_bytes(URL), Some(1_200));
+
+    clock.advance(Duration::from_secs(2));
+    assert_eq!(adaptive.max_response_bytes(URL), None);
+}
+
+#[tokio::test]
+async fn should_set_max_response_bytes_when_missing() {
+    let (requests_tx, requests_rx) = mpsc::channel::<IcHttpRequest>();
+    let mut service = ServiceBuilder::new()
+        .layer(adaptive().with_headroom_percent(0))
+        .service_fn(move |request: IcHttpRequest| {
+            let requests_tx = requests_tx.clone();
+            async move {
+                requests_tx.send(request).unwrap();
+                Ok::<_, BoxError>(IcHttpResponseWithCycles {
+                    response: HttpRequestResult {
+                        status: 200_u16.into(),
+                        headers: vec![],
+                        body: vec![42; 1_000],
+                    },
+                    cycles: CyclesUsage::default(),
+                })
+            }
+        });
+    let request = IcHttpRequest {
+        url: URL.to_string(),
+        ..Default::default()
+    };
+
+    for request in [
+        request.clone(),
+        request.clone(),
+        request.clone().max_response_bytes(5_000),
+    ] {
+        let _response = service.ready().await.unwrap().call(request).await.unwrap();
+    }
+
+    assert_eq!(
+        requests_rx
+            .try_iter()
+            .map(|request| request.get_max_response_bytes())
+            .collect::<Vec<_>>(),
+        vec![None, Some(1_000), Some(5_000)]
+    );
+}
+
+fn adaptive() -> AdaptiveMaxResponseBytesLayer<MockClock> {
+    AdaptiveMaxResponseBytesLayer::new(Duration::from_secs(3_600), NonZeroUsize::new(100).unwrap())
+        .with_clock(MockClock::default())
+}
@@ -1,18 +1,36 @@
 //! Middleware for retrying "failed" requests.
+//!
+//! * [`DoubleMaxResponseBytes`] and [`GrowMaxResponseBytes`] retry requests whose response was too large.
+//! * [`RetryTransientErrors`] retries requests that failed due to a transient error.
+//!
+//!
+//! Wrap any of these policies in [`TrackRetryAttempt`] to keep track of the attempt being made with
+//! [`RetryAttemptRequestExtension`], so that it can be observed by the middlewares below them
+//! (see [`crate::observability`]).
 
 #[cfg(test)]
 mod tests;
 
-use crate::{HttpsOutcallError, MaxResponseBytesRequestExtension};
+use crate::time::Sleep;
+use crate::{
+    HttpsOutcallError, IcHttpResponseWithCycles, MaxResponseBytesRequestExtension,
+    RetryAttemptRequestExtension,
+};
+use ic_cdk::management_canister::HttpRequestResult as IcHttpResponse;
 use std::future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
 use tower::retry;
 
 // This constant comes from the IC specification:
 // > If provided, the value must not exceed 2MB
-const HTTP_MAX_SIZE: u64 = 2_000_000;
+pub(crate) const HTTP_MAX_SIZE: u64 = 2_000_000;
 
 /// Double the request `max_response_bytes` in case the error indicates the response was too big.
 ///
+/// See [`GrowMaxResponseBytes`] for a configurable version of this policy.
+///
 /// The value for `max_response_bytes` will be doubled until one of the following conditions happen:
 /// 1. Either the response is `Ok` or the error is not due to the response being too big;
 /// 2. Or, the maximum value of 2MB (`2_000_000`) is reached.
@@ -74,12 +92,10 @@ where
         match result {
             Err(e) if e.is_response_too_large() => {
                 if let Some(previous_estimate) = req.get_max_response_bytes() {
-                    let new_estimate = previous_estimate
-                        .max(1024)
-                        .saturating_mul(2)
-                        .min(HTTP_MAX_SIZE);
+                    let new_estimate = double_max_response_bytes(previous_estimate);
                     if new_estimate > previous_estimate {
                         req.set_max_response_bytes(new_estimate);
+                        trace_retry("response too large");
                         return Some(future::ready(()));
                     }
                 }
@@ -99,3 +115,646 @@ where
         }
     }
 }
+
+/// Values of `max_response_bytes` successively used by a retry policy for requests whose response was too large.
+///
+/// This allows knowing in advance the worst case of all attempts that the retry policy could make,
+/// see [`CyclesAccountingWithRetries`](crate::cycles::CyclesAccountingWithRetries).
+/// A policy whose retries depend on the error, e.g. [`GrowMaxResponseBytes::with_reported_size_parser`],
+/// must return a schedule costing at least as much as any sequence of retries it could make.
+pub trait MaxResponseBytesRetrySchedule {
+    /// Value of `max_response_bytes` used to retry a request after `num_attempts` attempts,
+    /// the last one with the given `max_response_bytes`, or `None` if the request is not retried.
+    ///
+    /// The returned value must be larger than the previous one.
+    fn retry_max_response_bytes(&self, previous: u64, num_attempts: u32) -> Option<u64>;
+}
+
+impl MaxResponseBytesRetrySchedule for DoubleMaxResponseBytes {
+    fn retry_max_response_bytes(&self, previous: u64, _num_attempts: u32) -> Option<u64> {
+        Some(double_max_response_bytes(previous)).filter(|next| *next > previous)
+    }
+}
+
+/// Increase the request `max_response_bytes` in case the error indicates the response was too big,
+/// according to a configurable growth policy.
+///
+/// This is a generalization of [`DoubleMaxResponseBytes`] where the following can be configured:
+/// * the growth of `max_response_bytes`, either by a factor (default: 2) applied to the previous value
+///   or the floor (default: 1024), whichever is larger, or by following an explicit sequence of values;
+/// * the maximum value of `max_response_bytes` (default and at most: 2MB);
+/// * the maximum number of attempts, including the first one (default: unlimited);
+/// * how to parse the actual response size from the reject message, if reported,
+///   to retry directly with that size (see [`GrowMaxResponseBytes::with_reported_size_parser`]).
+///
+/// The value of `max_response_bytes` will be increased until one of the following conditions happen:
+/// 1. Either the response is `Ok` or the error is not due to the response being too big;
+/// 2. Or, the maximum value is reached;
+/// 3. Or, the maximum number of attempts is reached;
+/// 4. Or, the request was already retried with the reported response size.
+///
+/// # Examples
+///
+/// ```rust
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+/// use canhttp::{http::HttpRequest, HttpsOutcallError, IcError, MaxResponseBytesRequestExtension, retry::GrowMaxResponseBytes};
+/// use ic_error_types::RejectCode;
+///
+/// fn response_is_too_large_error(max_response_bytes: u64) -> IcError {
+///     let error = IcError {
+///         code: RejectCode::SysFatal,
+///         message: format!("Http body exceeds size limit of {max_response_bytes} bytes"),
+///     };
+///     assert!(error.is_response_too_large());
+///     error
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+/// .retry(GrowMaxResponseBytes::default().with_steps([1_500_000]))
+/// .service_fn(|request: HttpRequest| async move {
+///     match request.get_max_response_bytes() {
+///         Some(max_response_bytes) if max_response_bytes >= 1_500_000 => Ok(()),
+///         max_response_bytes => Err::<(), IcError>(response_is_too_large_error(max_response_bytes.unwrap_or_default())),
+///     }
+/// });
+///
+/// let request = http::Request::post("https://internetcomputer.org/")
+///     .max_response_bytes(0)
+///     .body(vec![])
+///     .unwrap();
+///
+/// // This will effectively do 2 calls with the following max_response_bytes values: 0, 1_500_000.
+/// let response = service.ready().await?.call(request).await;
+///
+/// assert_eq!(response, Ok(()));
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct GrowMaxResponseBytes {
+    growth: ResponseSizeGrowth,
+    floor: u64,
+    cap: u64,
+    max_attempts: Option<u32>,
+    reported_size_parser: Option<fn(&str) -> Option<u64>>,
+    num_attempts: u32,
+    retried_with_reported_size: bool,
+}
+
+/// How [`GrowMaxResponseBytes`] increases `max_response_bytes`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum ResponseSizeGrowth {
+    /// Multiply the previous value (or the floor, whichever is larger) by the given factor.
+    Factor(u64),
+    /// Use the first value of the sequence that is larger than the previous value
+    /// (and at least the floor), or the maximum value once the sequence is exhausted.
+    Steps(Vec<u64>),
+}
+
+impl Default for GrowMaxResponseBytes {
+    fn default() -> Self {
+        Self {
+            growth: ResponseSizeGrowth::Factor(2),
+            floor: 1024,
+            cap: HTTP_MAX_SIZE,
+            max_attempts: None,
+            reported_size_parser: None,
+            num_attempts: 0,
+            retried_with_reported_size: false,
+        }
+    }
+}
+
+impl GrowMaxResponseBytes {
+    /// Multiply `max_response_bytes` by the given factor for each retry.
+    ///
+    /// # Panics
+    ///
+    /// If the factor is less than 2.
+    pub fn with_factor(self, factor: u64) -> Self {
+        assert!(factor >= 2, "ERROR: factor must be at least 2");
+        Self {
+            growth: ResponseSizeGrowth::Factor(factor),
+            ..self
+        }
+    }
+
+    /// Use the given values of `max_response_bytes` for the retries.
+    pub fn with_steps<I: IntoIterator<Item = u64>>(self, steps: I) -> Self {
+        let mut steps: Vec<_> = steps.into_iter().collect();
+        steps.sort_unstable();
+        Self {
+            growth: ResponseSizeGrowth::Steps(steps),
+            ..self
+        }
+    }
+
+    /// Minimum value of `max_response_bytes` for the retries.
+    pub fn with_floor(self, floor: u64) -> Self {
+        Self { floor, ..self }
+    }
+
+    /// Maximum value of `max_response_bytes`, which cannot exceed 2MB (`2_000_000`).
+    pub fn with_cap(self, cap: u64) -> Self {
+        Self {
+            cap: cap.min(HTTP_MAX_SIZE),
+            ..self
+        }
+    }
+
+    /// Maximum number of attempts, including the first one.
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts: Some(max_attempts),
+            ..self
+        }
+    }
+
+    /// Retry directly with `max_response_bytes` set to the actual response size (up to the maximum value),
+    /// when the given function finds it in the reject message of an error
+    /// due to the response being too big (see [`HttpsOutcallError::reject_message`]).
+    ///
+    /// The Internet Computer does not currently report the actual response size,
+    /// so the parser must match the reject messages of the replica or of the service below this policy.
+    /// A request retried with the reported size is not retried again, since its response
+    /// would only be too large if it changed in the meantime.
+    ///
+    /// Since the size of such a retry is not known in advance, the last retry allowed by
+    /// [`GrowMaxResponseBytes::with_max_attempts`] is then assumed to use the maximum value
+    /// when computing the worst case with [`MaxResponseBytesRetrySchedule`].
+    pub fn with_reported_size_parser(self, parser: fn(&str) -> Option<u64>) -> Self {
+        Self {
+            reported_size_parser: Some(parser),
+            ..self
+        }
+    }
+
+    fn reported_max_response_bytes<E: HttpsOutcallError>(
+        &self,
+        error: &E,
+        previous_estimate: u64,
+    ) -> Option<u64> {
+        let parser = self.reported_size_parser?;
+        error
+            .reject_message()
+            .and_then(parser)
+            .filter(|reported_size| *reported_size > previous_estimate)
+            .map(|reported_size| reported_size.min(self.cap))
+    }
+
+    fn next_max_response_bytes(&self, previous_estimate: u64) -> u64 {
+        let next_estimate = match &self.growth {
+            ResponseSizeGrowth::Factor(factor) => {
+                previous_estimate.max(self.floor).saturating_mul(*factor)
+            }
+            ResponseSizeGrowth::Steps(steps) => steps
+                .iter()
+                .find(|step| **step > previous_estimate)
+                .map_or(self.cap, |step| (*step).max(self.floor)),
+        };
+        next_estimate.min(self.cap)
+    }
+}
+
+impl MaxResponseBytesRetrySchedule for GrowMaxResponseBytes {
+    fn retry_max_response_bytes(&self, previous: u64, num_attempts: u32) -> Option<u64> {
+        if self
+            .max_attempts
+            .is_some_and(|max_attempts| num_attempts >= max_attempts)
+        {
+            return None;
+        }
+        let is_last_retry = self
+            .max_attempts
+            .is_some_and(|max_attempts| num_attempts.saturating_add(1) >= max_attempts);
+        let next = if self.reported_size_parser.is_some() && is_last_retry {
+            // The reported response size may be as large as the maximum value.
+            self.cap
+        } else {
+            self.next_max_response_bytes(previous)
+        };
+        Some(next).filter(|next| *next > previous)
+    }
+}
+
+impl<Request, Response, Error> retry::Policy<Request, Response, Error> for GrowMaxResponseBytes
+where
+    Request: MaxResponseBytesRequestExtension + Clone,
+    Error: HttpsOutcallError,
+{
+    type Future = future::Ready<()>;
+
+    fn retry(
+        &mut self,
+        req: &mut Request,
+        result: &mut Result<Response, Error>,
+    ) -> Option<Self::Future> {
+        self.num_attempts = self.num_attempts.saturating_add(1);
+        if self
+            .max_attempts
+            .is_some_and(|max_attempts| self.num_attempts >= max_attempts)
+        {
+            return None;
+        }
+        match result {
+            Err(e) if e.is_response_too_large() && !self.retried_with_reported_size => {
+                if let Some(previous_estimate) = req.get_max_response_bytes() {
+                    let new_estimate = match self.reported_max_response_bytes(e, previous_estimate)
+                    {
+                        Some(reported_estimate) => {
+                            self.retried_with_reported_size = true;
+                            reported_estimate
+                        }
+                        None => self.next_max_response_bytes(previous_estimate),
+                    };
+                    if new_estimate > previous_estimate {
+                        req.set_max_response_bytes(new_estimate);
+                        trace_retry("response too large");
+                        return Some(future::ready(()));
+                    }
+                }
+                None
+            }
+            _ => None,
+        }
+    }
+
+    fn clone_request(&mut self, req: &Request) -> Option<Request> {
+        match req.get_max_response_bytes() {
+            Some(max_response_bytes) if max_response_bytes < self.cap => Some(req.clone()),
+            // Retrying will not help, see `DoubleMaxResponseBytes::clone_request`.
+            _ => None,
+        }
+    }
+}
+
+/// Retry requests that failed due to a transient error, waiting between attempts with an exponential backoff.
+///
+/// The following failures are considered transient:
+/// * errors for which [`HttpsOutcallError::is_transient`] is true, e.g. an [`IcError`] with
+///   [`RejectCode::SysTransient`];
+/// * responses for which [`RetryableResponse::is_transient_failure`] is true,
+///   i.e. with an HTTP status code 429, 502, 503 or 504.
+///
+/// Since responses with such status codes are only retried when they reach this policy,
+/// it should come before middlewares turning them into errors
+/// (e.g. [`FilterNonSuccessfulHttpResponse`](crate::http::FilterNonSuccessfulHttpResponse)).
+///
+/// The delay before the `n`-th retry is `initial_backoff * 2^(n-1)`, capped by `max_backoff`
+/// and randomly reduced by up to the jitter percentage. If the response contains
+/// a `Retry-After` header (in seconds), the delay is at least that value.
+/// A response requiring a longer delay than `max_backoff` is not retried.
+///
+/// Waiting is done with the given [`Sleep`] implementation, e.g. `IcTimerSleep` inside a canister
+/// (requires the `timers` feature), or [`MockClock`](crate::time::MockClock) in tests to not actually wait.
+///
+/// Note that a canister cannot back off within an ordinary update call with `IcTimerSleep`:
+/// a method returning while its future only waits for a timer has its future canceled,
+/// which makes the call fail. The retried requests must instead be issued from a task that can outlive
+/// the method that started it, i.e. spawned with `ic_cdk::futures::spawn_migratory`,
+/// whose result is then retrieved later, e.g. by a query.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{IcError, retry::RetryTransientErrors, time::Sleep};
+/// use ic_error_types::RejectCode;
+/// use std::future::Future;
+/// use std::pin::Pin;
+/// use std::sync::atomic::{AtomicU8, Ordering};
+/// use std::sync::Arc;
+/// use std::time::Duration;
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// #[derive(Clone)]
+/// struct TokioSleep;
+///
+/// impl Sleep for TokioSleep {
+///     type Future = Pin<Box<tokio::time::Sleep>>;
+///
+///     fn sleep(&self, duration: Duration) -> Self::Future {
+///         Box::pin(tokio::time::sleep(duration))
+///     }
+/// }
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let num_calls = Arc::new(AtomicU8::new(0));
+/// let mut service = ServiceBuilder::new()
+///     .retry(
+///         RetryTransientErrors::new(TokioSleep)
+///             .with_max_attempts(3)
+///             .with_backoff(Duration::from_millis(1), Duration::from_millis(10))
+///     )
+///     .service_fn(|_request: ()| {
+///         let num_calls = num_calls.clone();
+///         async move {
+///             match num_calls.fetch_add(1, Ordering::Relaxed) {
+///                 0 => Err(IcError { code: RejectCode::SysTransient, message: "busy".to_string() }),
+///                 _ => Ok(http::Response::new(())),
+///             }
+///         }
+///     });
+///
+/// let response = service.ready().await?.call(()).await;
+///
+/// assert!(response.is_ok());
+/// assert_eq!(num_calls.load(Ordering::Relaxed), 2);
+/// # Ok(())
+/// # }
+/// ```
+///
+/// [`IcError`]: crate::IcError
+/// [`RejectCode::SysTransient`]: ic_error_types::RejectCode::SysTransient
+#[derive(Clone, Debug)]
+pub struct RetryTransientErrors<S> {
+    sleep: S,
+    max_attempts: u32,
+    initial_backoff: Duration,
+    max_backoff: Duration,
+    jitter_percent: u8,
+    rng: Arc<AtomicU64>,
+    num_attempts: u32,
+}
+
+impl<S> RetryTransientErrors<S> {
+    /// Create a new policy waiting with the given [`Sleep`] implementation.
+    ///
+    /// By default, a request is attempted at most 3 times, the backoff starts at 1 second
+    /// and is at most 10 seconds, with a jitter of 20%.
+    pub fn new(sleep: S) -> Self {
+        Self {
+            sleep,
+            max_attempts: 3,
+            initial_backoff: Duration::from_secs(1),
+            max_backoff: Duration::from_secs(10),
+            jitter_percent: 20,
+            rng: Arc::new(AtomicU64::new(0)),
+            num_attempts: 0,
+        }
+    }
+
+    /// Maximum number of attempts, including the first one.
+    pub fn with_max_attempts(self, max_attempts: u32) -> Self {
+        Self {
+            max_attempts,
+            ..self
+        }
+    }
+
+    /// Delay before the first retry and maximum delay between two attempts.
+    pub fn with_backoff(self, initial_backoff: Duration, max_backoff: Duration) -> Self {
+        Self {
+            initial_backoff,
+            max_backoff,
+            ..self
+        }
+    }
+
+    /// Maximum percentage by which the delay is randomly reduced.
+    ///
+    /// # Panics
+    ///
+    /// If the percentage is greater than 100.
+    pub fn with_jitter_percent(self, jitter_percent: u8) -> Self {
+        assert!(
+            jitter_percent <= 100,
+            "ERROR: jitter percentage must be at most 100"
+        );
+        Self {
+            jitter_percent,
+            ..self
+        }
+    }
+
+    /// Seed of the pseudo-random number generator used for the jitter.
+    pub fn with_jitter_seed(self, seed: u64) -> Self {
+        Self {
+            rng: Arc::new(AtomicU64::new(seed)),
+            ..self
+        }
+    }
+
+    fn backoff(&self, num_retries: u32) -> Duration {
+        let backoff = self
+            .initial_backoff
+            .saturating_mul(2_u32.saturating_pow(num_retries.saturating_sub(1)))
+            .min(self.max_backoff);
+        let max_jitter = backoff.as_nanos() * self.jitter_percent as u128 / 100;
+        if max_jitter == 0 {
+            return backoff;
+        }
+        let jitter = self.next_random() as u128 % (max_jitter + 1);
+        backoff.saturating_sub(Duration::from_nanos(jitter as u64))
+    }
+
+    // SplitMix64, shared by all clones so that concurrent requests get different delays.
+    fn next_random(&self) -> u64 {
+        let mut z = self
+            .rng
+            .fetch_add(0x9E37_79B9_7F4A_7C15, Ordering::Relaxed)
+            .wrapping_add(0x9E37_79B9_7F4A_7C15);
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        z ^ (z >> 31)
+    }
+}
+
+impl<S, Request, Response, Error> retry::Policy<Request, Response, Error>
+    for RetryTransientErrors<S>
+where
+    S: Sleep + Clone,
+    Request: Clone,
+    Response: RetryableResponse,
+    Error: HttpsOutcallError,
+{
+    type Future = S::Future;
+
+    fn retry(
+        &mut self,
+        _req: &mut Request,
+        result: &mut Result<Response, Error>,
+    ) -> Option<Self::Future> {
+        self.num_attempts = self.num_attempts.saturating_add(1);
+        if self.num_attempts >= self.max_attempts {
+            return None;
+        }
+        let retry_after = match result {
+            Ok(response) if response.is_transient_failure() => response.retry_after(),
+            Err(e) if e.is_transient() => None,
+            _ => return None,
+        };
+        let delay = match retry_after {
+            Some(retry_after) if retry_after > self.max_backoff => return None,
+            Some(retry_after) => retry_after.max(self.backoff(self.num_attempts)),
+            None => self.backoff(self.num_attempts),
+        };
+        trace_retry("transient failure");
+        Some(self.sleep.sleep(delay))
+    }
+
+    fn clone_request(&mut self, req: &Request) -> Option<Request> {
+        Some(req.clone())
+    }
+}
+
+/// Keep track of the attempt being made by the wrapped retry policy.
+///
+/// Every time the wrapped policy decides to retry a request, the retry attempt of that request
+/// is incremented (see [`RetryAttemptRequestExtension`]), so that middlewares below the retry layer,
+/// such as [`crate::observability`], can tell attempts apart.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::{http::HttpRequest, retry::{DoubleMaxResponseBytes, TrackRetryAttempt}, HttpsOutcallError, IcError, MaxResponseBytesRequestExtension, RetryAttemptRequestExtension};
+/// use ic_error_types::RejectCode;
+/// use tower::{Service, ServiceBuilder, ServiceExt};
+///
+/// # #[tokio::main]
+/// # async fn main() -> Result<(), Box<dyn std::error::Error>> {
+/// let mut service = ServiceBuilder::new()
+///     .retry(TrackRetryAttempt::new(DoubleMaxResponseBytes))
+///     .service_fn(|request: HttpRequest| async move {
+///         match request.get_retry_attempt() {
+///             Some(attempt) if attempt >= 1 => Ok(attempt),
+///             _ => Err(IcError {
+///                 code: RejectCode::SysFatal,
+///                 message: "Http body exceeds size limit of 1024 bytes".to_string(),
+///             }),
+///         }
+///     });
+///
+/// let request = http::Request::post("https://internetcomputer.org/")
+///     .max_response_bytes(1024)
+///     .body(vec![])
+///     .unwrap();
+///
+/// let attempt = service.ready().await?.call(request).await?;
+///
+/// assert_eq!(attempt, 1);
+/// # Ok(())
+/// # }
+/// ```
+#[derive(Clone, Debug)]
+pub struct TrackRetryAttempt<P> {
+    policy: P,
+}
+
+impl<P> TrackRetryAttempt<P> {
+    /// Keep track of the attempt being made by the given retry policy.
+    pub fn new(policy: P) -> Self {
+        Self { policy }
+    }
+}
+
+impl<P, Request, Response, Error> retry::Policy<Request, Response, Error> for TrackRetryAttempt<P>
+where
+    P: retry::Policy<Request, Response, Error>,
+    Request: RetryAttemptRequestExtension,
+{
+    type Future = P::Future;
+
+    fn retry(
+        &mut self,
+        req: &mut Request,
+        result: &mut Result<Response, Error>,
+    ) -> Option<Self::Future> {
+        let future = self.policy.retry(req, result)?;
+        let attempt = req
+            .get_retry_attempt()
+            .unwrap_or_default()
+            .saturating_add(1);
+        #[cfg(feature = "tracing")]
+        tracing::debug!(attempt, "Tracking retry attempt of HTTPs outcall");
+        req.set_retry_attempt(attempt);
+        Some(future)
+    }
+
+    fn clone_request(&mut self, req: &Request) -> Option<Request> {
+        self.policy.clone_request(req)
+    }
+}
+
+impl<P: MaxResponseBytesRetrySchedule> MaxResponseBytesRetrySchedule for TrackRetryAttempt<P> {
+    fn retry_max_response_bytes(&self, previous: u64, num_attempts: u32) -> Option<u64> {
+        self.policy.retry_max_response_bytes(previous, num_attempts)
+    }
+}
+
+/// Classify responses that indicate a transient failure, see [`RetryTransientErrors`].
+pub trait RetryableResponse {
+    /// HTTP status code of the response.
+    fn status_code(&self) -> u16;
+
+    /// Value of the `Retry-After` header of the response, if any.
+    fn retry_after_header(&self) -> Option<&str>;
+
+    /// Determines whether the response indicates a transient failure,
+    /// i.e. the HTTP status code is 429 (Too Many Requests), 502 (Bad Gateway),
+    /// 503 (Service Unavailable) or 504 (Gateway Timeout).
+    fn is_transient_failure(&self) -> bool {
+        matches!(self.status_code(), 429 | 502 | 503 | 504)
+    }
+
+    /// Delay requested by the `Retry-After` header, if it is given in seconds.
+    fn retry_after(&self) -> Option<Duration> {
+        self.retry_after_header()?
+            .trim()
+            .parse::<u64>()
+            .ok()
+            .map(Duration::from_secs)
+    }
+}
+
+impl RetryableResponse for IcHttpResponse {
+    fn status_code(&self) -> u16 {
+        u16::try_from(&self.status.0).unwrap_or(u16::MAX)
+    }
+
+    fn retry_after_header(&self) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|header| header.name.eq_ignore_ascii_case("retry-after"))
+            .map(|header| header.value.as_str())
+    }
+}
+
+impl RetryableResponse for IcHttpResponseWithCycles {
+    fn status_code(&self) -> u16 {
+        self.response.status_code()
+    }
+
+    fn retry_after_header(&self) -> Option<&str> {
+        self.response.retry_after_header()
+    }
+}
+
+#[cfg(feature = "http")]
+impl<T> RetryableResponse for http::Response<T> {
+    fn status_code(&self) -> u16 {
+        self.status().as_u16()
+    }
+
+    fn retry_after_header(&self) -> Option<&str> {
+        self.headers().get(http::header::RETRY_AFTER)?.to_str().ok()
+    }
+}
+
+/// Next value of `max_response_bytes` tried by [`DoubleMaxResponseBytes`].
+pub(crate) fn double_max_response_bytes(previous_estimate: u64) -> u64 {
+    previous_estimate
+        .max(1024)
+        .saturating_mul(2)
+        .min(HTTP_MAX_SIZE)
+}
+
+/// Record that a request is retried for the given reason.
+fn trace_retry(reason: &'static str) {
+    #[cfg(feature = "tracing")]
+    tracing::debug!(reason, "Retrying HTTPs outcall");
+    #[cfg(not(feature = "tracing"))]
+    let _ = reason;
+}
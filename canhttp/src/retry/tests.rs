@@ -1,12 +1,22 @@
 use crate::http::HttpRequest;
-use crate::retry::DoubleMaxResponseBytes;
-use crate::{HttpsOutcallError, IcError, MaxResponseBytesRequestExtension};
+use crate::retry::{
+    DoubleMaxResponseBytes, GrowMaxResponseBytes, RetryTransientErrors, TrackRetryAttempt,
+};
+use crate::time::Sleep;
+use crate::{
+    HttpsOutcallError, IcError, MaxResponseBytesRequestExtension, RetryAttemptRequestExtension,
+};
 use assert_matches::assert_matches;
 use ic_error_types::RejectCode;
 use std::future;
+use std::future::Future;
+use std::pin::Pin;
+use std::sync::atomic::{AtomicU8, Ordering};
 use std::sync::mpsc;
 use std::sync::mpsc::Sender;
+use std::sync::{Arc, Mutex};
 use std::task::{Context, Poll};
+use std::time::Duration;
 use tower::{Service, ServiceBuilder, ServiceExt};
 
 #[tokio::test]
@@ -121,6 +131,440 @@ async fn should_stop_retrying_when_ok() {
     );
 }
 
+#[tokio::test]
+async fn should_grow_max_response_bytes_like_double_by_default() {
+    assert_eq!(
+        retried_max_response_bytes(GrowMaxResponseBytes::default(), 0).await,
+        retried_max_response_bytes(DoubleMaxResponseBytes, 0).await
+    );
+}
+
+#[tokio::test]
+async fn should_grow_max_response_bytes_with_custom_factor_floor_and_cap() {
+    let policy = GrowMaxResponseBytes::default()
+        .with_factor(4)
+        .with_floor(10_000)
+        .with_cap(1_000_000);
+
+    assert_eq!(
+        retried_max_response_bytes(policy, 0).await,
+        vec![0, 40_000, 160_000, 640_000, 1_000_000]
+    );
+}
+
+#[tokio::test]
+async fn should_grow_max_response_bytes_with_steps() {
+    let policy = GrowMaxResponseBytes::default().with_steps([500_000, 100_000, 1_500_000]);
+
+    assert_eq!(
+        retried_max_response_bytes(policy.clone(), 0).await,
+        vec![0, 100_000, 500_000, 1_500_000, 2_000_000]
+    );
+    assert_eq!(
+        retried_max_response_bytes(policy, 200_000).await,
+        vec![200_000, 500_000, 1_500_000, 2_000_000]
+    );
+}
+
+#[tokio::test]
+async fn should_limit_number_of_attempts() {
+    let policy = GrowMaxResponseBytes::default().with_max_attempts(3);
+
+    assert_eq!(
+        retried_max_response_bytes(policy, 0).await,
+        vec![0, 1024 << 1, 1024 << 2]
+    );
+}
+
+#[tokio::test]
+async fn should_retry_with_reported_response_size() {
+    let policy = GrowMaxResponseBytes::default().with_reported_size_parser(parse_actual_size);
+
+    assert_eq!(
+        reported_size_retries(policy.clone(), Some(1_500_000)).await,
+        vec![1_000, 1_500_000]
+    );
+    assert_eq!(
+        reported_size_retries(policy.clone().with_cap(1_000_000), Some(1_500_000)).await,
+        vec![1_000, 1_000_000]
+    );
+    // Not reported: grow as usual.
+    assert_eq!(
+        reported_size_retries(policy.with_cap(4_000), None).await,
+        vec![1_000, 2_048, 4_000]
+    );
+}
+
+#[tokio::test]
+async fn should_not_retry_again_after_retrying_with_reported_response_size() {
+    let (requests_tx, requests_rx) = mpsc::channel::<HttpRequest>();
+    let mut service = ServiceBuilder::new()
+        .retry(GrowMaxResponseBytes::default().with_reported_size_parser(parse_actual_size))
+        .service_fn(move |request: HttpRequest| {
+            let max_response_bytes = request.get_max_response_bytes().unwrap();
+            requests_tx.send(request).unwrap();
+            // The response grew in the meantime.
+            future::ready(Err::<(), _>(response_too_large_error(Some(
+                max_response_bytes + 1,
+            ))))
+        });
+
+    let request = http::Request::post("https://internetcomputer.org/")
+        .max_response_bytes(1_000)
+        .body(vec![])
+        .unwrap();
+
+    let response = service.ready().await.unwrap().call(request).await;
+
+    assert_matches!(response, Err(e) if e.is_response_too_large());
+    assert_eq!(
+        requests_rx
+            .try_iter()
+            .map(|r| r.get_max_response_bytes().unwrap())
+            .collect::<Vec<_>>(),
+        vec![1_000, 1_001]
+    );
+}
+
+#[test]
+fn should_detect_response_too_large_from_replica_message() {
+    let error = IcError {
+        code: RejectCode::SysFatal,
+        message: "Http body exceeds size limit of 2000 bytes".to_string(),
+    };
+    assert!(error.is_response_too_large());
+
+    let error = IcError {
+        code: RejectCode::SysTransient,
+        message: "Http body exceeds size limit of 2000 bytes".to_string(),
+    };
+    assert!(!error.is_response_too_large());
+}
+
+async fn reported_size_retries(
+    policy: GrowMaxResponseBytes,
+    reported_size: Option<u64>,
+) -> Vec<u64> {
+    let (requests_tx, requests_rx) = mpsc::channel::<HttpRequest>();
+    let mut service =
+        ServiceBuilder::new()
+            .retry(policy)
+            .service_fn(move |request: HttpRequest| {
+                requests_tx.send(request).unwrap();
+                future::ready(Err::<(), _>(response_too_large_error(reported_size)))
+            });
+
+    let request = http::Request::post("https://internetcomputer.org/")
+        .max_response_bytes(1_000)
+        .body(vec![])
+        .unwrap();
+
+    let _error = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap_err();
+
+    requests_rx
+        .try_iter()
+        .map(|r| r.get_max_response_bytes().unwrap())
+        .collect()
+}
+
+fn response_too_large_error(reported_size: Option<u64>) -> IcError {
+    IcError {
+        code: RejectCode::SysFatal,
+        message: match reported_size {
+            Some(size) => {
+                format!("Http body exceeds size limit (actual response size: {size} bytes)")
+            }
+            None => "Http body exceeds size limit".to_string(),
+        },
+    }
+}
+
+fn parse_actual_size(message: &str) -> Option<u64> {
+    let (_, rest) = message.split_once("actual response size: ")?;
+    rest.split_once(' ')?.0.parse().ok()
+}
+
+async fn retried_max_response_bytes<P>(policy: P, initial_max_response_bytes: u64) -> Vec<u64>
+where
+    P: tower::retry::Policy<HttpRequest, HttpRequest, IcError> + Clone,
+{
+    let (requests_tx, requests_rx) = mpsc::channel::<HttpRequest>();
+    let mut service =
+        ServiceBuilder::new().retry(policy).service(
+            StoreRequestServiceAndError::<HttpRequest>::always_error(requests_tx.clone()),
+        );
+
+    let request = http::Request::post("https://internetcomputer.org/")
+        .max_response_bytes(initial_max_response_bytes)
+        .body(vec![])
+        .unwrap();
+
+    let _error = service
+        .ready()
+        .await
+        .unwrap()
+        .call(request)
+        .await
+        .unwrap_err();
+
+    requests_rx
+        .try_iter()
+        .map(|r| r.get_max_response_bytes().unwrap())
+        .collect()
+}
+
+#[tokio::test]
+async fn should_retry_transient_errors_with_exponential_backoff() {
+    let sleep = RecordingSleep::default();
+    let (requests_tx, requests_rx) = mpsc::channel::<()>();
+    let mut service = ServiceBuilder::new()
+        .retry(
+            RetryTransientErrors::new(sleep.clone())
+                .with_max_attempts(4)
+                .with_backoff(Duration::from_millis(100), Duration::from_millis(250))
+                .with_jitter_percent(0),
+        )
+        .service_fn(move |request: ()| {
+            requests_tx.send(request).unwrap();
+            future::ready(Err::<http::Response<()>, _>(IcError {
+                code: RejectCode::SysTransient,
+                message: "busy".to_string(),
+            }))
+        });
+
+    let error = service.ready().await.unwrap().call(()).await.unwrap_err();
+
+    assert!(error.is_transient());
+    assert_eq!(requests_rx.try_iter().count(), 4);
+    assert_eq!(
+        sleep.delays(),
+        vec![
+            Duration::from_millis(100),
+            Duration::from_millis(200),
+            Duration::from_millis(250)
+        ]
+    );
+}
+
+#[tokio::test]
+async fn should_retry_transient_http_responses_and_honor_retry_after() {
+    let sleep = RecordingSleep::default();
+    let (requests_tx, requests_rx) = mpsc::channel::<()>();
+    let num_calls = Arc::new(AtomicU8::new(0));
+    let mut service = ServiceBuilder::new()
+        .retry(RetryTransientErrors::new(sleep.clone()).with_jitter_percent(0))
+        .service_fn(move |request: ()| {
+            requests_tx.send(request).unwrap();
+            let status = match num_calls.fetch_add(1, Ordering::Relaxed) {
+                0 => 503,
+                _ => 200,
+            };
+            future::ready(Ok::<_, IcError>(
+                http::Response::builder()
+                    .status(status)
+                    .header(http::header::RETRY_AFTER, "5")
+                    .body(())
+                    .unwrap(),
+            ))
+        });
+
+    let response = service.ready().await.unwrap().call(()).await.unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::OK);
+    assert_eq!(requests_rx.try_iter().count(), 2);
+    assert_eq!(sleep.delays(), vec![Duration::from_secs(5)]);
+}
+
+#[tokio::test]
+async fn should_not_retry_when_retry_after_exceeds_max_backoff() {
+    let sleep = RecordingSleep::default();
+    let mut service = ServiceBuilder::new()
+        .retry(RetryTransientErrors::new(sleep.clone()))
+        .service_fn(|_request: ()| {
+            future::ready(Ok::<_, IcError>(
+                http::Response::builder()
+                    .status(429)
+                    .header(http::header::RETRY_AFTER, "60")
+                    .body(())
+                    .unwrap(),
+            ))
+        });
+
+    let response = service.ready().await.unwrap().call(()).await.unwrap();
+
+    assert_eq!(response.status(), http::StatusCode::TOO_MANY_REQUESTS);
+    assert_eq!(sleep.delays(), vec![]);
+}
+
+#[tokio::test]
+async fn should_not_retry_non_transient_failures() {
+    let sleep = RecordingSleep::default();
+    let (requests_tx, requests_rx) = mpsc::channel::<u16>();
+    let mut service = ServiceBuilder::new()
+        .retry(RetryTransientErrors::new(sleep.clone()))
+        .service_fn(move |status: u16| {
+            requests_tx.send(status).unwrap();
+            future::ready(match status {
+                0 => Err(IcError {
+                    code: RejectCode::SysFatal,
+                    message: "fatal".to_string(),
+                }),
+                _ => Ok(http::Response::builder().status(status).body(()).unwrap()),
+            })
+        });
+
+    for status in [0, 200, 404, 500] {
+        let _result = service.ready().await.unwrap().call(status).await;
+    }
+
+    assert_eq!(
+        requests_rx.try_iter().collect::<Vec<_>>(),
+        vec![0, 200, 404, 500]
+    );
+    assert_eq!(sleep.delays(), vec![]);
+}
+
+#[tokio::test]
+async fn should_add_jitter_to_backoff() {
+    let sleep = RecordingSleep::default();
+    let mut service = ServiceBuilder::new()
+        .retry(
+            RetryTransientErrors::new(sleep.clone())
+                .with_max_attempts(10)
+                .with_backoff(Duration::from_secs(1), Duration::from_secs(1))
+                .with_jitter_percent(50)
+                .with_jitter_seed(42),
+        )
+        .service_fn(|_request: ()| {
+            future::ready(Ok::<_, IcError>(
+                http::Response::builder().status(502).body(()).unwrap(),
+            ))
+        });
+
+    let _response = service.ready().await.unwrap().call(()).await.unwrap();
+
+    let delays = sleep.delays();
+    assert_eq!(delays.len(), 9);
+    for delay in &delays {
+        assert!(
+            (Duration::from_millis(500)..=Duration::from_secs(1)).contains(delay),
+            "{delay:?}"
+        );
+    }
+    assert!(delays.iter().any(|delay| *delay != delays[0]));
+}
+
+#[tokio::test]
+async fn should_wait_with_tokio_sleep() {
+    let (requests_tx, requests_rx) = mpsc::channel::<()>();
+    let mut service = ServiceBuilder::new()
+        .retry(
+            RetryTransientErrors::new(TokioSleep)
+                .with_backoff(Duration::from_millis(10), Duration::from_millis(10))
+                .with_jitter_percent(0),
+        )
+        .service_fn(move |request: ()| {
+            requests_tx.send(request).unwrap();
+            future::ready(Err::<http::Response<()>, _>(IcError {
+                code: RejectCode::SysTransient,
+                message: "busy".to_string(),
+            }))
+        });
+
+    let start = tokio::time::Instant::now();
+    let _error = service.ready().await.unwrap().call(()).await.unwrap_err();
+
+    assert!(start.elapsed() >= Duration::from_millis(20));
+    assert_eq!(requests_rx.try_iter().count(), 3);
+}
+
+#[tokio::test]
+async fn should_track_retry_attempt() {
+    let (requests_tx, requests_rx) = mpsc::channel::<http::Request<()>>();
+    let mut service = ServiceBuilder::new()
+        .retry(TrackRetryAttempt::new(
+            RetryTransientErrors::new(RecordingSleep::default()).with_max_attempts(4),
+        ))
+        .service_fn(move |request: http::Request<()>| {
+            requests_tx.send(request).unwrap();
+            future::ready(Err::<http::Response<()>, _>(IcError {
+                code: RejectCode::SysTransient,
+                message: "transient".to_string(),
+            }))
+        });
+
+    let _error = service
+        .ready()
+        .await
+        .unwrap()
+        .call(http::Request::new(()))
+        .await
+        .unwrap_err();
+
+    assert_eq!(
+        requests_rx
+            .try_iter()
+            .map(|request| request.get_retry_attempt())
+            .collect::<Vec<_>>(),
+        vec![None, Some(1), Some(2), Some(3)]
+    );
+}
+
+#[tokio::test]
+async fn should_not_track_retry_attempt_when_not_retrying() {
+    let mut service = ServiceBuilder::new()
+        .retry(TrackRetryAttempt::new(DoubleMaxResponseBytes))
+        .service_fn(
+            |request: HttpRequest| async move { Ok::<_, IcError>(request.get_retry_attempt()) },
+        );
+
+    let request = http::Request::post("https://internetcomputer.org/")
+        .max_response_bytes(1024)
+        .body(vec![])
+        .unwrap();
+
+    let attempt = service.ready().await.unwrap().call(request).await;
+
+    assert_eq!(attempt, Ok(None));
+}
+
+#[derive(Clone, Default)]
+struct RecordingSleep {
+    delays: Arc<Mutex<Vec<Duration>>>,
+}
+
+impl RecordingSleep {
+    fn delays(&self) -> Vec<Duration> {
+        self.delays.lock().unwrap().clone()
+    }
+}
+
+impl Sleep for RecordingSleep {
+    type Future = future::Ready<()>;
+
+    fn sleep(&self, duration: Duration) -> Self::Future {
+        self.delays.lock().unwrap().push(duration);
+        future::ready(())
+    }
+}
+
+#[derive(Clone)]
+struct TokioSleep;
+
+impl Sleep for TokioSleep {
+    type Future = Pin<Box<dyn Future<Output = ()>>>;
+
+    fn sleep(&self, duration: Duration) -> Self::Future {
+        Box::pin(tokio::time::sleep(duration))
+    }
+}
+
 #[derive(Clone, Debug)]
 pub struct StoreRequestServiceAndError<T> {
     requests: Sender<T>,
@@ -0,0 +1,184 @@
+//! Abstractions over time for middlewares that need to read the current time or to wait.
+//!
+//! Time-related facilities from other crates (e.g. `std::time::Instant` or `tokio::time`) do not work inside a canister.
+//! Middlewares from this crate therefore rely on:
+//! * the [`Clock`] trait to read the current time, which is implemented by [`IcClock`] inside a canister
+//!   and by [`MockClock`] for tests;
+//! * the [`Sleep`] trait to wait, which is implemented by `IcTimerSleep` inside a canister
+//!   (requires the `timers` feature) and by [`MockClock`] for tests.
+//!
+//! Using [`MockClock`] as both the clock and the sleep of a middleware makes its time-dependent behavior
+//! deterministic in tests, since sleeping simply advances the clock.
+//! Alternatively, [`Sleep`] can be implemented on top of `tokio::time`:
+//!
+//! ```rust
+//! use canhttp::time::Sleep;
+//! use std::future::Future;
+//! use std::pin::Pin;
+//! use std::time::Duration;
+//!
+//! #[derive(Clone)]
+//! struct TokioSleep;
+//!
+//! impl Sleep for TokioSleep {
+//!     type Future = Pin<Box<tokio::time::Sleep>>;
+//!
+//!     fn sleep(&self, duration: Duration) -> Self::Future {
+//!         Box::pin(tokio::time::sleep(duration))
+//!     }
+//! }
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+use futures_util::future;
+use std::future::Future;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+#[cfg(feature = "timers")]
+pub use timers::{IcTimerSleep, TimerFuture};
+
+#[cfg(feature = "timers")]
+mod timers;
+
+/// Asynchronously wait for some time.
+pub trait Sleep {
+    /// Future that completes once the given duration elapsed.
+    type Future: Future<Output = ()>;
+
+    /// Return a future that completes after the given duration.
+    fn sleep(&self, duration: Duration) -> Self::Future;
+}
+
+/// Time in nanoseconds since the epoch (1970-01-01).
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq, Ord, PartialOrd)]
+pub struct Timestamp(Duration);
+
+impl Timestamp {
+    /// The Unix epoch.
+    pub const UNIX_EPOCH: Timestamp = Timestamp::from_nanos_since_unix_epoch(0);
+
+    /// Create a new [`Timestamp`] from a number of nanoseconds since the Unix epoch.
+    pub const fn from_nanos_since_unix_epoch(nanos: u64) -> Self {
+        Timestamp::from_unix_epoch(Duration::from_nanos(nanos))
+    }
+
+    /// Create a new [`Timestamp`] from a [`Duration`] since the Unix epoch.
+    pub const fn from_unix_epoch(duration: Duration) -> Self {
+        Timestamp(duration)
+    }
+
+    /// Checked `Time` subtraction with a `Duration`. Computes `self - rhs`,
+    /// returning [`None`] if underflow occurs.
+    ///
+    /// # Examples
+    ///
+    /// Basic usage:
+    ///
+    /// ```
+    /// use std::time::Duration;
+    /// use canhttp::time::Timestamp;
+    ///
+    /// assert_eq!(Timestamp::from_nanos_since_unix_epoch(3).checked_sub(Duration::from_nanos(2)), Some(Timestamp::from_nanos_since_unix_epoch(1)));
+    /// assert_eq!(Timestamp::from_nanos_since_unix_epoch(2).checked_sub(Duration::from_nanos(3)), None);
+    /// ```
+    pub fn checked_sub(self, rhs: Duration) -> Option<Timestamp> {
+        self.0.checked_sub(rhs).map(Timestamp::from_unix_epoch)
+    }
+
+    /// Return the number of nanoseconds since the Unix epoch, saturating at [`u64::MAX`].
+    pub fn as_nanos_since_unix_epoch(&self) -> u64 {
+        u64::try_from(self.0.as_nanos()).unwrap_or(u64::MAX)
+    }
+
+    /// Return the amount of time elapsed from `earlier` to `self`,
+    /// or zero if `earlier` is later than `self`.
+    pub fn saturating_duration_since(&self, earlier: Timestamp) -> Duration {
+        self.0.saturating_sub(earlier.0)
+    }
+}
+
+/// Source of the current time.
+pub trait Clock {
+    /// Return the current time.
+    fn now(&self) -> Timestamp;
+}
+
+/// Clock returning the current time of the Internet Computer, see [`ic_cdk::api::time`].
+///
+/// The time does not change during the execution of a message.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IcClock;
+
+impl Clock for IcClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_nanos_since_unix_epoch(ic_cdk::api::time())
+    }
+}
+
+/// Clock whose time only changes when told to, meant for tests.
+///
+/// Sleeping with a [`MockClock`] completes immediately after having advanced the clock by the requested duration.
+///
+/// All clones share the same time.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::time::{Clock, MockClock, Sleep, Timestamp};
+/// use std::time::Duration;
+///
+/// # #[tokio::main]
+/// # async fn main() {
+/// let clock = MockClock::new(Timestamp::UNIX_EPOCH);
+/// let sleep = clock.clone();
+///
+/// clock.advance(Duration::from_secs(1));
+/// sleep.sleep(Duration::from_secs(2)).await;
+///
+/// assert_eq!(clock.now(), Timestamp::from_unix_epoch(Duration::from_secs(3)));
+/// # }
+/// ```
+#[derive(Clone, Debug, Default)]
+pub struct MockClock(Arc<AtomicU64>);
+
+impl MockClock {
+    /// Create a new clock starting at the given time.
+    pub fn new(now: Timestamp) -> Self {
+        Self(Arc::new(AtomicU64::new(now.as_nanos_since_unix_epoch())))
+    }
+
+    /// Set the current time.
+    pub fn set(&self, now: Timestamp) {
+        self.0
+            .store(now.as_nanos_since_unix_epoch(), Ordering::Relaxed);
+    }
+
+    /// Move the current time forward by the given duration.
+    pub fn advance(&self, duration: Duration) {
+        let nanos = u64::try_from(duration.as_nanos()).unwrap_or(u64::MAX);
+        let _previous = self
+            .0
+            .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |now| {
+                Some(now.saturating_add(nanos))
+            });
+    }
+}
+
+impl Clock for MockClock {
+    fn now(&self) -> Timestamp {
+        Timestamp::from_nanos_since_unix_epoch(self.0.load(Ordering::Relaxed))
+    }
+}
+
+impl Sleep for MockClock {
+    type Future = future::Ready<()>;
+
+    fn sleep(&self, duration: Duration) -> Self::Future {
+        self.advance(duration);
+        future::ready(())
+    }
+}
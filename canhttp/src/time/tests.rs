@@ -0,0 +1,39 @@
+use crate::time::{Clock, MockClock, Sleep, Timestamp};
+use std::time::Duration;
+
+#[test]
+fn should_share_time_between_mock_clock_clones() {
+    let clock = MockClock::new(Timestamp::from_nanos_since_unix_epoch(1_000));
+    let clone = clock.clone();
+
+    clone.advance(Duration::from_nanos(500));
+    assert_eq!(clock.now(), Timestamp::from_nanos_since_unix_epoch(1_500));
+
+    clock.set(Timestamp::UNIX_EPOCH);
+    assert_eq!(clone.now(), Timestamp::UNIX_EPOCH);
+}
+
+#[tokio::test]
+async fn should_advance_mock_clock_when_sleeping() {
+    let clock = MockClock::default();
+
+    clock.sleep(Duration::from_secs(1)).await;
+    clock.sleep(Duration::from_secs(2)).await;
+
+    assert_eq!(
+        clock.now().saturating_duration_since(Timestamp::UNIX_EPOCH),
+        Duration::from_secs(3)
+    );
+}
+
+#[test]
+fn should_saturate_duration_since_later_timestamp() {
+    let earlier = Timestamp::from_nanos_since_unix_epoch(1);
+    let later = Timestamp::from_nanos_since_unix_epoch(3);
+
+    assert_eq!(
+        later.saturating_duration_since(earlier),
+        Duration::from_nanos(2)
+    );
+    assert_eq!(earlier.saturating_duration_since(later), Duration::ZERO);
+}
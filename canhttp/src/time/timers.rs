@@ -0,0 +1,78 @@
+use crate::time::Sleep;
+use ic_cdk_timers::TimerId;
+use std::cell::RefCell;
+use std::future::Future;
+use std::pin::Pin;
+use std::rc::Rc;
+use std::task::{Context, Poll, Waker};
+use std::time::Duration;
+
+/// Sleep by using a one-off timer from [`ic_cdk_timers`].
+///
+/// Note that a canister method cannot be kept open by a timer alone: a future waiting for a timer
+/// is canceled when the method that polled it returns, including an ordinary update call
+/// or a future spawned by a timer. The sleeping future must therefore be awaited in a task
+/// spawned with `ic_cdk::futures::spawn_migratory`, which is resumed by the timer.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IcTimerSleep;
+
+impl Sleep for IcTimerSleep {
+    type Future = TimerFuture;
+
+    fn sleep(&self, duration: Duration) -> Self::Future {
+        TimerFuture {
+            duration,
+            timer: None,
+        }
+    }
+}
+
+/// Future returned by [`IcTimerSleep`].
+///
+/// The timer is only set when the future is first polled and is cleared when the future is dropped.
+pub struct TimerFuture {
+    duration: Duration,
+    timer: Option<(TimerId, Rc<RefCell<TimerState>>)>,
+}
+
+#[derive(Default)]
+struct TimerState {
+    elapsed: bool,
+    waker: Option<Waker>,
+}
+
+impl Future for TimerFuture {
+    type Output = ();
+
+    fn poll(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let duration = self.duration;
+        let (_timer_id, state) = self.timer.get_or_insert_with(|| {
+            let state = Rc::new(RefCell::new(TimerState::default()));
+            let timer_state = Rc::clone(&state);
+            let timer_id = ic_cdk_timers::set_timer(duration, async move {
+                let mut timer_state = timer_state.borrow_mut();
+                timer_state.elapsed = true;
+                if let Some(waker) = timer_state.waker.take() {
+                    waker.wake();
+                }
+            });
+            (timer_id, state)
+        });
+        let mut state = state.borrow_mut();
+        if state.elapsed {
+            return Poll::Ready(());
+        }
+        state.waker = Some(cx.waker().clone());
+        Poll::Pending
+    }
+}
+
+impl Drop for TimerFuture {
+    fn drop(&mut self) {
+        if let Some((timer_id, state)) = self.timer.take() {
+            if !state.borrow().elapsed {
+                ic_cdk_timers::clear_timer(timer_id);
+            }
+        }
+    }
+}
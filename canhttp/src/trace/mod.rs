@@ -0,0 +1,91 @@
+//! Integration with the [`tracing`](https://crates.io/crates/tracing) ecosystem.
+//!
+//! When the `tracing` feature is enabled, the middlewares of this crate emit the following spans and events:
+//! * [`Client`]: a span `http_outcall` for each HTTPs outcall with the fields `url`
+//!   (redacted with the default [`RedactionPolicy`]), `method` and `cycles`, containing an event with
+//!   the fields `status` and `cycles_refunded` when a response is received,
+//!   or an event with the fields `reject_code` and `message` when the call fails;
+//! * [`ConvertRequest`] and [`ConvertResponse`]: an event with the field `converter` when a
+//!   request or a response cannot be converted;
+//! * [`CyclesAccounting`]: an event with the fields `cycles_attached` and `cycles_charged`
+//!   when the cycles for a request are charged, or an event when charging fails;
+//! * the retry policies of the [`retry`] module: an event with the field `reason`
+//!   when a request is retried, followed by an event with the field `attempt` when the policy
+//!   is wrapped in [`TrackRetryAttempt`](crate::retry::TrackRetryAttempt);
+//! * `parallel_call`: a span `parallel_call` with the field `num_requests`, containing an event
+//!   with the fields `num_ok` and `num_errors` once all calls completed.
+//!
+//! Outside a canister, these can be collected by any [`tracing::Subscriber`], e.g. in tests.
+//! Inside a canister, they can be written to the canister logs with [`init`].
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use canhttp::trace;
+//!
+//! // E.g., in the `init` and `post_upgrade` methods of the canister:
+//! trace::init(tracing::Level::INFO);
+//! ```
+//!
+//! [`Client`]: crate::Client
+//! [`RedactionPolicy`]: crate::redact::RedactionPolicy
+//! [`ConvertRequest`]: crate::convert::ConvertRequest
+//! [`ConvertResponse`]: crate::convert::ConvertResponse
+//! [`CyclesAccounting`]: crate::cycles::CyclesAccounting
+//! [`retry`]: crate::retry
+
+#[cfg(test)]
+mod tests;
+
+use std::io;
+use tracing::{Level, Subscriber};
+use tracing_subscriber::fmt::MakeWriter;
+
+/// Writer of formatted spans and events to the canister logs with [`ic_cdk::println`].
+///
+/// Each write is expected to contain a single formatted event, which is the case
+/// for subscribers from [`tracing_subscriber::fmt`](mod@tracing_subscriber::fmt).
+#[derive(Clone, Copy, Debug, Default)]
+pub struct IcPrintln;
+
+impl io::Write for IcPrintln {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let line = String::from_utf8_lossy(buf);
+        ic_cdk::println!("{}", line.trim_end_matches('\n'));
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MakeWriter<'_> for IcPrintln {
+    type Writer = IcPrintln;
+
+    fn make_writer(&self) -> Self::Writer {
+        *self
+    }
+}
+
+/// Create a subscriber writing spans and events up to the given level to the canister logs.
+///
+/// Timestamps are omitted, since the canister logs already record the time of each entry.
+pub fn subscriber(max_level: Level) -> impl Subscriber + Send + Sync + 'static {
+    tracing_subscriber::fmt()
+        .with_max_level(max_level)
+        .with_writer(IcPrintln)
+        .without_time()
+        .with_ansi(false)
+        .finish()
+}
+
+/// Set the [`subscriber`] writing to the canister logs as the global default.
+///
+/// # Panics
+///
+/// If a global default subscriber was already set.
+pub fn init(max_level: Level) {
+    tracing::subscriber::set_global_default(subscriber(max_level))
+        .expect("ERROR: a global default subscriber was already set")
+}
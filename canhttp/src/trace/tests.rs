@@ -0,0 +1,112 @@
+use crate::http::HttpRequest;
+use crate::retry::{DoubleMaxResponseBytes, TrackRetryAttempt};
+use crate::{IcError, MaxResponseBytesRequestExtension};
+use ic_error_types::RejectCode;
+use std::io;
+use std::sync::{Arc, Mutex};
+use tower::{Service, ServiceBuilder, ServiceExt};
+use tracing::subscriber::DefaultGuard;
+use tracing_subscriber::fmt::MakeWriter;
+
+#[tokio::test]
+async fn should_emit_event_when_retrying() {
+    let logs = CapturedLogs::default();
+    let _guard = logs.set_default();
+    let mut service = ServiceBuilder::new()
+        .retry(TrackRetryAttempt::new(DoubleMaxResponseBytes))
+        .service_fn(|request: HttpRequest| async move {
+            if request.get_max_response_bytes() < Some(4_096) {
+                return Err(IcError {
+                    code: RejectCode::SysFatal,
+                    message: "Http body exceeds size limit".to_string(),
+                });
+            }
+            Ok(http::Response::new(Vec::<u8>::new()))
+        });
+
+    let request = http::Request::get("https://internetcomputer.org/")
+        .max_response_bytes(1_024)
+        .body(vec![])
+        .unwrap();
+    let _response = service.ready().await.unwrap().call(request).await.unwrap();
+
+    assert_eq!(
+        logs.lines(),
+        vec![
+            r#"DEBUG canhttp::retry: Retrying HTTPs outcall reason="response too large""#,
+            r#"DEBUG canhttp::retry: Tracking retry attempt of HTTPs outcall attempt=1"#,
+            r#"DEBUG canhttp::retry: Retrying HTTPs outcall reason="response too large""#,
+            r#"DEBUG canhttp::retry: Tracking retry attempt of HTTPs outcall attempt=2"#,
+        ]
+    );
+}
+
+#[cfg(feature = "multi")]
+#[tokio::test]
+async fn should_emit_span_for_parallel_calls() {
+    let logs = CapturedLogs::default();
+    let _guard = logs.set_default();
+    let service = ServiceBuilder::new().service_fn(|request: u32| async move {
+        if request % 2 == 0 {
+            Ok(request)
+        } else {
+            Err(IcError {
+                code: RejectCode::SysTransient,
+                message: "odd".to_string(),
+            })
+        }
+    });
+
+    let (_service, _results) =
+        crate::multi::parallel_call(service, [(0_u8, 0_u32), (1, 1), (2, 2)]).await;
+
+    assert_eq!(
+        logs.lines(),
+        vec![
+            "DEBUG parallel_call{num_requests=3}: canhttp::multi: Parallel calls completed num_ok=2 num_errors=1"
+        ]
+    );
+}
+
+#[derive(Clone, Default)]
+struct CapturedLogs(Arc<Mutex<Vec<u8>>>);
+
+impl CapturedLogs {
+    fn set_default(&self) -> DefaultGuard {
+        tracing::subscriber::set_default(
+            tracing_subscriber::fmt()
+                .with_max_level(tracing::Level::DEBUG)
+                .with_writer(self.clone())
+                .without_time()
+                .with_ansi(false)
+                .finish(),
+        )
+    }
+
+    fn lines(&self) -> Vec<String> {
+        String::from_utf8(self.0.lock().unwrap().clone())
+            .unwrap()
+            .lines()
+            .map(|line| line.trim().to_string())
+            .collect()
+    }
+}
+
+impl io::Write for CapturedLogs {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.lock().unwrap().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+impl MakeWriter<'_> for CapturedLogs {
+    type Writer = Self;
+
+    fn make_writer(&self) -> Self::Writer {
+        self.clone()
+    }
+}
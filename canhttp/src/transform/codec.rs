@@ -0,0 +1,95 @@
+use candid::CandidType;
+use serde::de::DeserializeOwned;
+#[cfg(feature = "cbor")]
+use serde::Serialize;
+use thiserror::Error;
+
+/// Encoding of typed payloads in the context of a [`TransformContext`].
+///
+/// The payload is encoded when the request is built, e.g. with
+/// [`TransformContextRequestExtension::transform_payload`], and decoded inside the transform function.
+/// Note that the length of the encoded payload is part of the request and is therefore charged for,
+/// see [`CyclesCostEstimator`].
+///
+/// [`TransformContext`]: ic_cdk::management_canister::TransformContext
+/// [`TransformContextRequestExtension::transform_payload`]: crate::TransformContextRequestExtension::transform_payload
+/// [`CyclesCostEstimator`]: crate::cycles::CyclesCostEstimator
+pub trait ContextCodec<T> {
+    /// Encode the payload.
+    fn encode(&self, payload: &T) -> Result<Vec<u8>, ContextCodecError>;
+
+    /// Decode a payload encoded with [`ContextCodec::encode`].
+    fn decode(&self, context: &[u8]) -> Result<T, ContextCodecError>;
+}
+
+/// Error returned when encoding or decoding a payload with a [`ContextCodec`].
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum ContextCodecError {
+    /// Payload could not be encoded.
+    #[error("Failed to encode payload with {codec}: {message}")]
+    EncodingError {
+        /// Name of the codec.
+        codec: &'static str,
+        /// Error message of the codec.
+        message: String,
+    },
+    /// Context could not be decoded into a payload of the expected type.
+    #[error("Failed to decode payload with {codec}: {message}")]
+    DecodingError {
+        /// Name of the codec.
+        codec: &'static str,
+        /// Error message of the codec.
+        message: String,
+    },
+}
+
+/// [`ContextCodec`] encoding payloads with [Candid](https://github.com/dfinity/candid).
+///
+/// The encoded payload contains its type, so that decoding fails with an explicit error
+/// when the payload does not have the expected type, but Candid-encoded payloads are usually
+/// larger than CBOR-encoded ones.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CandidCodec;
+
+impl<T: CandidType + DeserializeOwned> ContextCodec<T> for CandidCodec {
+    fn encode(&self, payload: &T) -> Result<Vec<u8>, ContextCodecError> {
+        candid::encode_one(payload).map_err(|e| ContextCodecError::EncodingError {
+            codec: "Candid",
+            message: e.to_string(),
+        })
+    }
+
+    fn decode(&self, context: &[u8]) -> Result<T, ContextCodecError> {
+        candid::decode_one(context).map_err(|e| ContextCodecError::DecodingError {
+            codec: "Candid",
+            message: e.to_string(),
+        })
+    }
+}
+
+/// [`ContextCodec`] encoding payloads with [CBOR](https://cbor.io/),
+/// by using [ciborium](https://crates.io/crates/ciborium).
+#[cfg(feature = "cbor")]
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct CborCodec;
+
+#[cfg(feature = "cbor")]
+impl<T: Serialize + DeserializeOwned> ContextCodec<T> for CborCodec {
+    fn encode(&self, payload: &T) -> Result<Vec<u8>, ContextCodecError> {
+        let mut context = Vec::new();
+        ciborium::ser::into_writer(payload, &mut context).map_err(|e| {
+            ContextCodecError::EncodingError {
+                codec: "CBOR",
+                message: e.to_string(),
+            }
+        })?;
+        Ok(context)
+    }
+
+    fn decode(&self, context: &[u8]) -> Result<T, ContextCodecError> {
+        ciborium::de::from_reader(context).map_err(|e| ContextCodecError::DecodingError {
+            codec: "CBOR",
+            message: e.to_string(),
+        })
+    }
+}
@@ -0,0 +1,347 @@
+//! Transform functions applied to the responses of HTTPs outcalls.
+//!
+//! Replicated HTTPs outcalls are made by all nodes of the subnet, which must agree on the response.
+//! A [transform function](https://internetcomputer.org/docs/references/https-outcalls-how-it-works#transformation-function)
+//! is typically needed to remove the parts of the responses that differ between nodes (e.g. headers containing timestamps).
+//!
+//! This module offers a single transform function, exported by the canister with [`export_transform!`](crate::export_transform),
+//! that applies the [`Transform`] encoded in the [`TransformContext`] of each request.
+//! Besides ready-made transforms, canisters can register their own with [`register_transform`],
+//! whose parameters can be encoded as typed payloads with a [`ContextCodec`].
+//! With the `http` feature, transforms can also be written against `http::Response`, see `transform_http_response`.
+//!
+//! # Examples
+//!
+//! ```rust,no_run
+//! use canhttp::{transform::Transform, TransformContextRequestExtension};
+//!
+//! // Exports the transform function of the canister.
+//! canhttp::export_transform!();
+//!
+//! let request = http::Request::get("https://internetcomputer.org/")
+//!     .transform_context(
+//!         Transform::Chain(vec![
+//!             Transform::StripHeaders,
+//!             Transform::NormalizeStatus,
+//!         ])
+//!         .into_context(),
+//!     )
+//!     .body(Vec::<u8>::new())
+//!     .unwrap();
+//! ```
+
+#[cfg(test)]
+mod tests;
+
+mod codec;
+
+#[cfg(feature = "cbor")]
+pub use codec::CborCodec;
+pub use codec::{CandidCodec, ContextCodec, ContextCodecError};
+
+use candid::CandidType;
+use ic_cdk::management_canister::{
+    HttpRequestResult as IcHttpResponse, TransformArgs, TransformContext, TransformFunc,
+};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use thiserror::Error;
+
+/// Name of the transform function exported by [`export_transform!`](crate::export_transform).
+pub const TRANSFORM_METHOD: &str = "__canhttp_transform";
+
+/// Export the transform function of the canister, which applies the [`Transform`] encoded in the transform context.
+///
+/// The transform function is a query method named after [`TRANSFORM_METHOD`],
+/// hidden from the Candid interface of the canister.
+/// It traps if the transform context cannot be decoded or if the transform fails.
+#[macro_export]
+macro_rules! export_transform {
+    () => {
+        #[::ic_cdk::query(hidden = true)]
+        fn __canhttp_transform(
+            args: ::ic_cdk::management_canister::TransformArgs,
+        ) -> ::ic_cdk::management_canister::HttpRequestResult {
+            $crate::transform::transform(args)
+        }
+    };
+}
+
+/// Transform applied to the response of an HTTPs outcall.
+///
+/// A transform is encoded with Candid in the context of a [`TransformContext`], see [`Transform::into_context`].
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+pub enum Transform {
+    /// Remove all headers.
+    StripHeaders,
+    /// Keep only the headers with the given names, compared case-insensitively.
+    KeepHeaders(Vec<String>),
+    /// Replace the status code by the first status code of its class, e.g. `204` becomes `200`.
+    NormalizeStatus,
+    /// Parse the body as JSON and serialize it again without whitespace and with object keys sorted.
+    #[cfg(feature = "json")]
+    CanonicalizeJson,
+    /// Parse the body as JSON and replace it by the value identified by the given
+    /// [JSON pointer](https://datatracker.ietf.org/doc/html/rfc6901), serialized as with [`Transform::CanonicalizeJson`].
+    #[cfg(feature = "json")]
+    JsonPointer(String),
+    /// Apply the given transforms in order.
+    Chain(Vec<Transform>),
+    /// Apply the transform registered with the given name, see [`register_transform`].
+    Custom {
+        /// Name of the registered transform.
+        name: String,
+        /// Parameters of the registered transform.
+        context: Vec<u8>,
+    },
+}
+
+impl Transform {
+    /// Create a [`Transform::Custom`] applying the transform registered with the given name,
+    /// whose parameters are the given payload encoded with the given codec.
+    ///
+    /// The registered transform can decode its parameters with the same codec.
+    pub fn custom<C: ContextCodec<T>, T>(
+        name: impl Into<String>,
+        codec: C,
+        payload: &T,
+    ) -> Result<Self, ContextCodecError> {
+        Ok(Transform::Custom {
+            name: name.into(),
+            context: codec.encode(payload)?,
+        })
+    }
+
+    /// Encode the transform with [`CandidCodec`] to be used as the context of a [`TransformContext`].
+    pub fn encode(&self) -> Vec<u8> {
+        CandidCodec
+            .encode(self)
+            .expect("BUG: failed to encode transform")
+    }
+
+    /// Decode a transform encoded with [`Transform::encode`].
+    pub fn decode(context: &[u8]) -> Result<Self, TransformError> {
+        Ok(CandidCodec.decode(context)?)
+    }
+
+    /// Create a [`TransformContext`] applying this transform with the transform function
+    /// exported by [`export_transform!`](crate::export_transform).
+    pub fn into_context(self) -> TransformContext {
+        TransformContext {
+            function: TransformFunc::new(
+                ic_cdk::api::canister_self(),
+                TRANSFORM_METHOD.to_string(),
+            ),
+            context: self.encode(),
+        }
+    }
+
+    /// Apply the transform to the given response.
+    pub fn apply(&self, response: IcHttpResponse) -> Result<IcHttpResponse, TransformError> {
+        match self {
+            Transform::StripHeaders => Ok(IcHttpResponse {
+                headers: vec![],
+                ..response
+            }),
+            Transform::KeepHeaders(names) => {
+                let mut response = response;
+                response.headers.retain(|header| {
+                    names
+                        .iter()
+                        .any(|name| name.eq_ignore_ascii_case(&header.name))
+                });
+                Ok(response)
+            }
+            Transform::NormalizeStatus => {
+                let status = u16::try_from(&response.status.0).unwrap_or(u16::MAX);
+                Ok(IcHttpResponse {
+                    status: (status - status % 100).into(),
+                    ..response
+                })
+            }
+            #[cfg(feature = "json")]
+            Transform::CanonicalizeJson => {
+                let value = parse_json(&response.body)?;
+                Ok(IcHttpResponse {
+                    body: canonical_json(value),
+                    ..response
+                })
+            }
+            #[cfg(feature = "json")]
+            Transform::JsonPointer(pointer) => {
+                let mut value = parse_json(&response.body)?;
+                let value = value
+                    .pointer_mut(pointer)
+                    .map(serde_json::Value::take)
+                    .ok_or_else(|| TransformError::JsonPointerNotFound(pointer.clone()))?;
+                Ok(IcHttpResponse {
+                    body: canonical_json(value),
+                    ..response
+                })
+            }
+            Transform::Chain(transforms) => transforms
+                .iter()
+                .try_fold(response, |response, transform| transform.apply(response)),
+            Transform::Custom { name, context } => {
+                let transform = TRANSFORMS
+                    .with_borrow(|transforms| transforms.get(name).copied())
+                    .ok_or_else(|| TransformError::UnknownTransform(name.clone()))?;
+                transform(response, context)
+            }
+        }
+    }
+}
+
+/// Error returned when applying a [`Transform`].
+#[derive(Error, Clone, Debug, PartialEq, Eq)]
+pub enum TransformError {
+    /// Transform context could not be decoded.
+    #[error("Invalid transform context: {0}")]
+    InvalidContext(#[from] ContextCodecError),
+    /// No transform was registered with that name.
+    #[error("Unknown transform `{0}`")]
+    UnknownTransform(String),
+    /// Transform registered with [`register_transform`] failed.
+    #[error("Custom transform failed: {0}")]
+    CustomTransformFailed(String),
+    /// Response body is not valid JSON.
+    #[cfg(feature = "json")]
+    #[error("Invalid JSON body: {0}")]
+    InvalidJson(String),
+    /// JSON pointer does not identify any value of the response body.
+    #[cfg(feature = "json")]
+    #[error("JSON pointer `{0}` does not identify any value")]
+    JsonPointerNotFound(String),
+    /// Response could not be converted to or from an `http::Response`, see `transform_http_response`.
+    #[cfg(feature = "http")]
+    #[error("Invalid HTTP response: {0}")]
+    InvalidHttpResponse(String),
+}
+
+/// Function applying a custom transform to a response, given the parameters of the transform.
+///
+/// Parameters that cannot be decoded can be reported with the `?` operator,
+/// since [`ContextCodecError`] converts into [`TransformError::InvalidContext`].
+pub type TransformFn = fn(IcHttpResponse, &[u8]) -> Result<IcHttpResponse, TransformError>;
+
+thread_local! {
+    static TRANSFORMS: RefCell<BTreeMap<String, TransformFn>> = const { RefCell::new(BTreeMap::new()) };
+}
+
+/// Register a custom transform with the given name, to be applied with [`Transform::Custom`].
+///
+/// Registered transforms are not persisted across canister upgrades and must be registered again,
+/// typically in the `init` and `post_upgrade` methods of the canister.
+/// Registering a transform with the name of an already registered transform replaces it.
+pub fn register_transform(name: impl Into<String>, transform: TransformFn) {
+    TRANSFORMS.with_borrow_mut(|transforms| transforms.insert(name.into(), transform));
+}
+
+/// Apply the [`Transform`] encoded in the context to the response.
+///
+/// This is the implementation of the transform function exported by [`export_transform!`](crate::export_transform).
+///
+/// # Panics
+///
+/// If the transform context cannot be decoded or if the transform fails.
+pub fn transform(args: TransformArgs) -> IcHttpResponse {
+    Transform::decode(&args.context)
+        .and_then(|transform| transform.apply(args.response))
+        .unwrap_or_else(|e| panic!("ERROR: failed to transform response: {e}"))
+}
+
+/// Apply a transform written against [`HttpResponse`] to an [`IcHttpResponse`].
+///
+/// The response is converted with [`HttpResponseConverter`] before and after applying the transform,
+/// so that transform functions can reuse the same code as the rest of a service stack based on the `http` crate.
+///
+/// # Examples
+///
+/// ```rust
+/// use canhttp::transform::{transform_http_response, TransformError};
+/// use ic_cdk::management_canister::{HttpHeader, HttpRequestResult as IcHttpResponse};
+///
+/// // E.g., a function registered with `canhttp::transform::register_transform`.
+/// fn keep_pagination_links(response: IcHttpResponse, _context: &[u8]) -> Result<IcHttpResponse, TransformError> {
+///     transform_http_response(response, |response| {
+///         let (mut parts, body) = response.into_parts();
+///         let links = parts.headers.get_all(http::header::LINK).iter().cloned().collect::<Vec<_>>();
+///         parts.headers.clear();
+///         for link in links {
+///             parts.headers.append(http::header::LINK, link);
+///         }
+///         http::Response::from_parts(parts, body)
+///     })
+/// }
+///
+/// let response = IcHttpResponse {
+///     status: 200_u16.into(),
+///     headers: vec![
+///         HttpHeader { name: "Date".to_string(), value: "Sat, 17 Oct 2026 10:00:00 GMT".to_string() },
+///         HttpHeader { name: "Link".to_string(), value: "<https://example.com/?page=2>; rel=\"next\"".to_string() },
+///     ],
+///     body: vec![],
+/// };
+///
+/// assert_eq!(
+///     keep_pagination_links(response, &[]).unwrap().headers,
+///     vec![HttpHeader { name: "link".to_string(), value: "<https://example.com/?page=2>; rel=\"next\"".to_string() }]
+/// );
+/// ```
+///
+/// # Errors
+///
+/// Returns [`TransformError::InvalidHttpResponse`] if the response cannot be converted,
+/// e.g. because it has an invalid status code.
+///
+/// [`HttpResponse`]: crate::http::HttpResponse
+/// [`HttpResponseConverter`]: crate::http::HttpResponseConverter
+#[cfg(feature = "http")]
+pub fn transform_http_response<F>(
+    response: IcHttpResponse,
+    transform: F,
+) -> Result<IcHttpResponse, TransformError>
+where
+    F: FnOnce(crate::http::HttpResponse) -> crate::http::HttpResponse,
+{
+    use crate::convert::Convert;
+    use crate::http::HttpResponseConverter;
+
+    let response: crate::http::HttpResponse = HttpResponseConverter
+        .try_convert(response)
+        .map_err(|e| TransformError::InvalidHttpResponse(e.to_string()))?;
+    HttpResponseConverter
+        .try_convert(transform(response))
+        .map_err(|e| TransformError::InvalidHttpResponse(e.to_string()))
+}
+
+#[cfg(feature = "json")]
+fn parse_json(body: &[u8]) -> Result<serde_json::Value, TransformError> {
+    serde_json::from_slice(body).map_err(|e| TransformError::InvalidJson(e.to_string()))
+}
+
+/// Serialize without whitespace and with object keys sorted,
+/// even if the `preserve_order` feature of `serde_json` is enabled.
+#[cfg(feature = "json")]
+fn canonical_json(value: serde_json::Value) -> Vec<u8> {
+    fn sort_keys(value: serde_json::Value) -> serde_json::Value {
+        match value {
+            serde_json::Value::Array(values) => {
+                serde_json::Value::Array(values.into_iter().map(sort_keys).collect())
+            }
+            serde_json::Value::Object(map) => {
+                let mut entries: Vec<_> = map.into_iter().collect();
+                entries.sort_by(|(left, _), (right, _)| left.cmp(right));
+                serde_json::Value::Object(
+                    entries
+                        .into_iter()
+                        .map(|(key, value)| (key, sort_keys(value)))
+                        .collect(),
+                )
+            }
+            value => value,
+        }
+    }
+    serde_json::to_vec(&sort_keys(value)).expect("BUG: failed to serialize JSON value")
+}
@@ -0,0 +1,365 @@
+use crate::transform::{
+    register_transform, transform, CandidCodec, ContextCodec, ContextCodecError, Transform,
+    TransformError,
+};
+use candid::CandidType;
+use ic_cdk::management_canister::{HttpHeader, HttpRequestResult as IcHttpResponse, TransformArgs};
+use serde::{Deserialize, Serialize};
+
+#[test]
+fn should_strip_headers() {
+    let response = Transform::StripHeaders.apply(response()).unwrap();
+
+    assert_eq!(
+        response,
+        IcHttpResponse {
+            headers: vec![],
+            ..self::response()
+        }
+    );
+}
+
+#[test]
+fn should_keep_allowlisted_headers() {
+    let response = Transform::KeepHeaders(vec!["content-type".to_string()])
+        .apply(response())
+        .unwrap();
+
+    assert_eq!(
+        response.headers,
+        vec![HttpHeader {
+            name: "Content-Type".to_string(),
+            value: "application/json".to_string(),
+        }]
+    );
+}
+
+#[test]
+fn should_normalize_status() {
+    for (status, expected) in [
+        (200_u16, 200_u16),
+        (204, 200),
+        (301, 300),
+        (404, 400),
+        (429, 400),
+        (503, 500),
+    ] {
+        let response = Transform::NormalizeStatus
+            .apply(IcHttpResponse {
+                status: status.into(),
+                ..response()
+            })
+            .unwrap();
+
+        assert_eq!(response.status, candid::Nat::from(expected));
+    }
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn should_canonicalize_json() {
+    let response = Transform::CanonicalizeJson
+        .apply(IcHttpResponse {
+            body: br#"{ "b": [ {"d": 1, "c": 2} ], "a": null }"#.to_vec(),
+            ..response()
+        })
+        .unwrap();
+
+    assert_eq!(response.body, br#"{"a":null,"b":[{"c":2,"d":1}]}"#.to_vec());
+
+    assert!(matches!(
+        Transform::CanonicalizeJson.apply(IcHttpResponse {
+            body: b"not json".to_vec(),
+            ..self::response()
+        }),
+        Err(TransformError::InvalidJson(_))
+    ));
+}
+
+#[cfg(feature = "json")]
+#[test]
+fn should_extract_json_pointer() {
+    let response = IcHttpResponse {
+        body: br#"{"jsonrpc": "2.0", "id": 1, "result": {"number": "0x1", "hash": "0xa"}}"#
+            .to_vec(),
+        ..response()
+    };
+
+    assert_eq!(
+        Transform::JsonPointer("/result".to_string())
+            .apply(response.clone())
+            .unwrap()
+            .body,
+        br#"{"hash":"0xa","number":"0x1"}"#.to_vec()
+    );
+    assert_eq!(
+        Transform::JsonPointer("/result/number".to_string())
+            .apply(response.clone())
+            .unwrap()
+            .body,
+        br#""0x1""#.to_vec()
+    );
+    assert_eq!(
+        Transform::JsonPointer("/error".to_string()).apply(response),
+        Err(TransformError::JsonPointerNotFound("/error".to_string()))
+    );
+}
+
+#[test]
+fn should_chain_transforms() {
+    let response = Transform::Chain(vec![
+        Transform::KeepHeaders(vec!["Date".to_string()]),
+        Transform::NormalizeStatus,
+        Transform::Chain(vec![Transform::StripHeaders]),
+    ])
+    .apply(IcHttpResponse {
+        status: 201_u16.into(),
+        ..response()
+    })
+    .unwrap();
+
+    assert_eq!(
+        response,
+        IcHttpResponse {
+            status: 200_u16.into(),
+            headers: vec![],
+            ..self::response()
+        }
+    );
+}
+
+#[test]
+fn should_apply_registered_transform() {
+    fn truncate_body(
+        response: IcHttpResponse,
+        context: &[u8],
+    ) -> Result<IcHttpResponse, TransformError> {
+        let len = context
+            .first()
+            .copied()
+            .ok_or_else(|| TransformError::CustomTransformFailed("missing length".to_string()))?;
+        Ok(IcHttpResponse {
+            body: response.body.into_iter().take(usize::from(len)).collect(),
+            ..response
+        })
+    }
+
+    let custom = Transform::Custom {
+        name: "truncate_body".to_string(),
+        context: vec![2],
+    };
+    assert_eq!(
+        custom.apply(response()),
+        Err(TransformError::UnknownTransform(
+            "truncate_body".to_string()
+        ))
+    );
+
+    register_transform("truncate_body", truncate_body);
+
+    assert_eq!(custom.apply(response()).unwrap().body, b"{\"".to_vec());
+
+    let custom_without_length = Transform::Custom {
+        name: "truncate_body".to_string(),
+        context: vec![],
+    };
+    assert_eq!(
+        custom_without_length.apply(response()),
+        Err(TransformError::CustomTransformFailed(
+            "missing length".to_string()
+        ))
+    );
+}
+
+#[test]
+fn should_apply_registered_transform_with_typed_payload() {
+    fn truncate_body(
+        response: IcHttpResponse,
+        context: &[u8],
+    ) -> Result<IcHttpResponse, TransformError> {
+        let params: TruncateParams = CandidCodec.decode(context)?;
+        Ok(IcHttpResponse {
+            body: response.body.into_iter().take(params.max_len).collect(),
+            ..response
+        })
+    }
+    register_transform("truncate_body_with_params", truncate_body);
+
+    let custom = Transform::custom(
+        "truncate_body_with_params",
+        CandidCodec,
+        &TruncateParams { max_len: 3 },
+    )
+    .unwrap();
+
+    assert_eq!(custom.apply(response()).unwrap().body, b"{\"r".to_vec());
+
+    let custom_with_invalid_payload = Transform::custom(
+        "truncate_body_with_params",
+        CandidCodec,
+        &"not the parameters".to_string(),
+    )
+    .unwrap();
+    assert!(matches!(
+        custom_with_invalid_payload.apply(response()),
+        Err(TransformError::InvalidContext(
+            ContextCodecError::DecodingError {
+                codec: "Candid",
+                ..
+            }
+        ))
+    ));
+}
+
+#[test]
+fn should_encode_and_decode_payload_with_candid() {
+    let params = TruncateParams { max_len: 42 };
+
+    let context = CandidCodec.encode(&params).unwrap();
+
+    assert_eq!(CandidCodec.decode(&context), Ok(params));
+    assert!(matches!(
+        ContextCodec::<String>::decode(&CandidCodec, &context),
+        Err(ContextCodecError::DecodingError {
+            codec: "Candid",
+            ..
+        })
+    ));
+}
+
+#[cfg(feature = "cbor")]
+#[test]
+fn should_encode_and_decode_payload_with_cbor() {
+    use crate::transform::CborCodec;
+
+    let params = TruncateParams { max_len: 42 };
+
+    let context = CborCodec.encode(&params).unwrap();
+
+    assert_eq!(CborCodec.decode(&context), Ok(params));
+    assert!(matches!(
+        ContextCodec::<String>::decode(&CborCodec, &context),
+        Err(ContextCodecError::DecodingError { codec: "CBOR", .. })
+    ));
+    assert!(matches!(
+        ContextCodec::<TruncateParams>::decode(&CborCodec, b""),
+        Err(ContextCodecError::DecodingError { codec: "CBOR", .. })
+    ));
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn should_transform_http_response() {
+    use crate::transform::transform_http_response;
+
+    let transformed = transform_http_response(response(), |response| {
+        let (mut parts, body) = response.into_parts();
+        parts.headers.remove(http::header::DATE);
+        parts.status = http::StatusCode::ACCEPTED;
+        http::Response::from_parts(parts, body)
+    });
+
+    assert_eq!(
+        transformed.unwrap(),
+        IcHttpResponse {
+            status: 202_u16.into(),
+            headers: vec![HttpHeader {
+                name: "content-type".to_string(),
+                value: "application/json".to_string(),
+            }],
+            ..response()
+        }
+    );
+}
+
+#[cfg(feature = "http")]
+#[test]
+fn should_fail_to_transform_invalid_http_response() {
+    let result = crate::transform::transform_http_response(
+        IcHttpResponse {
+            status: 1_000_u16.into(),
+            ..response()
+        },
+        |response| response,
+    );
+
+    assert!(matches!(
+        result,
+        Err(TransformError::InvalidHttpResponse(_))
+    ));
+}
+
+#[test]
+fn should_encode_and_decode_transform() {
+    let transforms = vec![
+        Transform::StripHeaders,
+        Transform::KeepHeaders(vec!["Content-Type".to_string()]),
+        Transform::NormalizeStatus,
+        #[cfg(feature = "json")]
+        Transform::CanonicalizeJson,
+        #[cfg(feature = "json")]
+        Transform::JsonPointer("/result".to_string()),
+        Transform::Custom {
+            name: "custom".to_string(),
+            context: vec![1, 2, 3],
+        },
+    ];
+    let transform = Transform::Chain(transforms);
+
+    assert_eq!(Transform::decode(&transform.encode()), Ok(transform));
+    assert!(matches!(
+        Transform::decode(b"invalid"),
+        Err(TransformError::InvalidContext(_))
+    ));
+}
+
+#[test]
+fn should_transform_with_encoded_context() {
+    let transformed = transform(TransformArgs {
+        response: response(),
+        context: Transform::StripHeaders.encode(),
+    });
+
+    assert_eq!(
+        transformed,
+        IcHttpResponse {
+            headers: vec![],
+            ..response()
+        }
+    );
+}
+
+#[test]
+#[should_panic(expected = "Unknown transform `missing`")]
+fn should_panic_when_transform_fails() {
+    transform(TransformArgs {
+        response: response(),
+        context: Transform::Custom {
+            name: "missing".to_string(),
+            context: vec![],
+        }
+        .encode(),
+    });
+}
+
+fn response() -> IcHttpResponse {
+    IcHttpResponse {
+        status: 200_u16.into(),
+        headers: vec![
+            HttpHeader {
+                name: "Content-Type".to_string(),
+                value: "application/json".to_string(),
+            },
+            HttpHeader {
+                name: "Date".to_string(),
+                value: "Sat, 17 Oct 2026 10:00:00 GMT".to_string(),
+            },
+        ],
+        body: br#"{"result": 1}"#.to_vec(),
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, CandidType, Serialize, Deserialize)]
+struct TruncateParams {
+    max_len: usize,
+}
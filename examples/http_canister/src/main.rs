@@ -1,10 +1,20 @@
 //! Example of a canister using `canhttp` to issue HTTP requests.
 use canhttp::cycles::{ChargeMyself, CyclesAccountingServiceBuilder};
-use canhttp::http::HttpConversionLayer;
+use canhttp::http::{HttpConversionLayer, ValidateHttpRequestLimits};
 use canhttp::observability::ObservabilityLayer;
-use canhttp::{Client, MaxResponseBytesRequestExtension};
-use ic_cdk::update;
-use tower::{BoxError, Service, ServiceBuilder, ServiceExt};
+use canhttp::redact::Redacted;
+use canhttp::retry::RetryTransientErrors;
+use canhttp::time::IcTimerSleep;
+use canhttp::{CanHttpError, Client, ConvertServiceBuilder, MaxResponseBytesRequestExtension};
+use ic_cdk::{query, update};
+use std::cell::{Cell, RefCell};
+use std::rc::Rc;
+use std::time::Duration;
+use tower::{Service, ServiceBuilder, ServiceExt};
+
+thread_local! {
+    static RETRIED_REQUEST_RESULT: RefCell<Option<Result<(u32, u16), String>>> = const { RefCell::new(None) };
+}
 
 /// Make an HTTP POST request.
 #[update]
@@ -28,26 +38,79 @@ pub async fn make_http_post_request() -> String {
     String::from_utf8_lossy(response.body()).to_string()
 }
 
+/// Make an HTTP GET request in the background, retrying transient failures after waiting with a timer.
+///
+/// Waiting cannot be done within this update call, which would be canceled when it returns,
+/// so that the request is made by a migratory task whose outcome is retrieved with
+/// [`retried_http_get_request_result`].
+#[update]
+pub fn start_retried_http_get_request(url: String) {
+    RETRIED_REQUEST_RESULT.set(None);
+    ic_cdk::futures::spawn_migratory(async move {
+        let num_attempts = Rc::new(Cell::new(0_u32));
+        let request = http::Request::get(url)
+            .max_response_bytes(1_000)
+            .body(vec![])
+            .unwrap();
+
+        let counter = num_attempts.clone();
+        let mut service = ServiceBuilder::new()
+            .retry(
+                RetryTransientErrors::new(IcTimerSleep)
+                    .with_max_attempts(3)
+                    .with_backoff(Duration::from_secs(1), Duration::from_secs(2)),
+            )
+            // Count the attempts made by the retry policy.
+            .layer(ObservabilityLayer::new().on_request(
+                move |_request: &http::Request<Vec<u8>>| counter.set(counter.get() + 1),
+            ))
+            .service(http_client());
+        // Panicking would leave the result unset forever, so the error is stored instead.
+        let result = match service.ready().await {
+            Ok(service) => service.call(request).await,
+            Err(error) => Err(error),
+        };
+
+        RETRIED_REQUEST_RESULT.set(Some(
+            result
+                .map(|response| (num_attempts.get(), response.status().as_u16()))
+                .map_err(|error| error.to_string()),
+        ));
+    });
+}
+
+/// Number of attempts and status code of the last response of the request started with
+/// [`start_retried_http_get_request`], or the error if it failed, once it completed.
+#[query]
+pub fn retried_http_get_request_result() -> Option<Result<(u32, u16), String>> {
+    RETRIED_REQUEST_RESULT.with_borrow(|result| result.clone())
+}
+
 fn http_client(
-) -> impl Service<http::Request<Vec<u8>>, Response = http::Response<Vec<u8>>, Error = BoxError> {
+) -> impl Service<http::Request<Vec<u8>>, Response = http::Response<Vec<u8>>, Error = CanHttpError> + Clone
+{
     ServiceBuilder::new()
-        // Print request, response and errors to the console
+        // Print request, response and errors to the console, without sensitive headers.
         .layer(
             ObservabilityLayer::new()
-                .on_request(|request: &http::Request<Vec<u8>>| ic_cdk::println!("{request:?}"))
+                .on_request(|request: &http::Request<Vec<u8>>| {
+                    ic_cdk::println!("{:?}", Redacted::new(request))
+                })
                 .on_response(|_, response: &http::Response<Vec<u8>>| {
-                    ic_cdk::println!("{response:?}");
+                    ic_cdk::println!("{:?}", Redacted::new(response));
                 })
-                .on_error(|_, error: &BoxError| {
+                .on_error(|_, error: &CanHttpError| {
                     ic_cdk::println!("Error {error:?}");
                 }),
         )
         // Only deal with types from the http crate.
         .layer(HttpConversionLayer)
+        // Reject requests exceeding the limits of the management canister before paying for them.
+        .convert_request(ValidateHttpRequestLimits)
         // Use cycles from the canister to pay for HTTPs outcalls
         .cycles_accounting(34, ChargeMyself::default())
         // The actual client
-        .service(Client::new_with_box_error())
+        .service(Client::new_with_error::<CanHttpError>())
 }
 
 fn main() {}
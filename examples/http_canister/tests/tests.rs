@@ -1,11 +1,17 @@
 use candid::utils::ArgumentEncoder;
 use candid::{decode_args, encode_args, CandidType, Encode, Principal};
 use ic_management_canister_types::{CanisterId, CanisterSettings};
+use pocket_ic::common::rest::{
+    CanisterHttpReply, CanisterHttpRequest, CanisterHttpResponse, MockCanisterHttpResponse,
+};
 use pocket_ic::{PocketIc, PocketIcBuilder};
 use serde::de::DeserializeOwned;
 use std::env::var;
 use std::path::PathBuf;
 use std::sync::Arc;
+use std::time::Duration;
+
+const MAX_TICKS: usize = 10;
 
 #[test]
 fn should_make_http_post_request() {
@@ -22,14 +28,57 @@ fn should_make_http_post_request() {
     assert!(http_request_result.contains("\"X-Id\": \"42\""));
 }
 
+#[test]
+fn should_retry_transient_http_failures_after_waiting_with_timer() {
+    const URL: &str = "https://internetcomputer.org/unavailable";
+    let setup = Setup::with_mocked_http_outcalls();
+    let http_canister = setup.http_canister();
+
+    http_canister.update_call::<_, ()>(
+        Principal::anonymous(),
+        "start_retried_http_get_request",
+        (URL.to_string(),),
+    );
+
+    for attempt in 1..=3 {
+        let request = setup.mock_http_response(503);
+        assert_eq!(request.url, URL);
+
+        let result = http_canister.query_call::<_, Option<Result<(u32, u16), String>>>(
+            Principal::anonymous(),
+            "retried_http_get_request_result",
+            (),
+        );
+        if attempt < 3 {
+            assert_eq!(result, None);
+            // Longest backoff before the next attempt.
+            setup.env.advance_time(Duration::from_secs(2));
+        } else {
+            assert_eq!(result, Some(Ok((3, 503))));
+        }
+    }
+    assert!(setup.env.get_canister_http().is_empty());
+}
+
 pub struct Setup {
     env: Arc<PocketIc>,
     http_canister_id: CanisterId,
+    live: bool,
 }
 impl Setup {
     pub const DEFAULT_CONTROLLER: Principal = Principal::from_slice(&[0x9d, 0xf7, 0x02]);
 
+    /// HTTPs outcalls are made to the actual servers.
     pub fn new() -> Self {
+        let mut setup = Self::with_mocked_http_outcalls();
+        let env = Arc::get_mut(&mut setup.env).expect("BUG: environment should not be shared yet");
+        let _endpoint = env.make_live(None);
+        setup.live = true;
+        setup
+    }
+
+    /// HTTPs outcalls must be answered with [`Setup::mock_http_response`].
+    pub fn with_mocked_http_outcalls() -> Self {
         let env = PocketIcBuilder::new()
             .with_nns_subnet() //make_live requires NNS subnet.
             .with_fiduciary_subnet()
@@ -51,12 +100,10 @@ impl Setup {
             Some(Self::DEFAULT_CONTROLLER),
         );
 
-        let mut env = env;
-        let _endpoint = env.make_live(None);
-
         Self {
             env: Arc::new(env),
             http_canister_id: canister_id,
+            live: false,
         }
     }
 
@@ -64,8 +111,35 @@ impl Setup {
         Canister {
             env: self.env.clone(),
             id: self.http_canister_id,
+            live: self.live,
         }
     }
+
+    /// Answer the next HTTPs outcall with an empty response with the given status code,
+    /// and return the request of that outcall.
+    fn mock_http_response(&self, status: u16) -> CanisterHttpRequest {
+        let request = (0..MAX_TICKS)
+            .find_map(|_| {
+                self.env.tick();
+                self.env.get_canister_http().into_iter().next()
+            })
+            .expect("BUG: no HTTPs outcall was made");
+        self.env
+            .mock_canister_http_response(MockCanisterHttpResponse {
+                subnet_id: request.subnet_id,
+                request_id: request.request_id,
+                response: CanisterHttpResponse::CanisterHttpReply(CanisterHttpReply {
+                    status,
+                    headers: vec![],
+                    body: vec![],
+                }),
+                additional_responses: vec![],
+            });
+        for _ in 0..MAX_TICKS {
+            self.env.tick();
+        }
+        request
+    }
 }
 
 impl Default for Setup {
@@ -77,6 +151,7 @@ impl Default for Setup {
 pub struct Canister {
     env: Arc<PocketIc>,
     id: CanisterId,
+    live: bool,
 }
 
 impl Canister {
@@ -96,10 +171,34 @@ impl Canister {
                 }),
             )
             .unwrap_or_else(|e| panic!("Failed to call method {method}: {e}"));
+        let response = if self.live {
+            self.env.await_call_no_ticks(message_id)
+        } else {
+            self.env.await_call(message_id)
+        };
+        let response_bytes = response.unwrap_or_else(|e| panic!("Failed to await call for method {method}: {e}"));
+        let (res,) = decode_args(&response_bytes).unwrap_or_else(|e| {
+            panic!("Failed to decode canister response for method {method}: {e}")
+        });
+        res
+    }
+
+    pub fn query_call<In, Out>(&self, sender: Principal, method: &str, args: In) -> Out
+    where
+        In: ArgumentEncoder + Send,
+        Out: CandidType + DeserializeOwned,
+    {
         let response_bytes = self
             .env
-            .await_call_no_ticks(message_id)
-            .unwrap_or_else(|e| panic!("Failed to await call for method {method}: {e}"));
+            .query_call(
+                self.id,
+                sender,
+                method,
+                encode_args(args).unwrap_or_else(|e| {
+                    panic!("Failed to encode arguments for method {method}: {e}")
+                }),
+            )
+            .unwrap_or_else(|e| panic!("Failed to query method {method}: {e}"));
         let (res,) = decode_args(&response_bytes).unwrap_or_else(|e| {
             panic!("Failed to decode canister response for method {method}: {e}")
         });